@@ -0,0 +1,527 @@
+//! PeerId and CIDR allow/deny lists enforced at connection time.
+//!
+//! The lists live in a TOML file:
+//!
+//! ```toml
+//! [[deny]]
+//! peer = "12D3KooW..."
+//! reason = "spam"
+//!
+//! [[deny]]
+//! cidr = "203.0.113.0/24"
+//! expires_at = 1798761600 # Unix time (seconds); the entry is ignored afterwards
+//!
+//! [[allow]]
+//! peer = "12D3KooW..."
+//! ```
+//!
+//! Deny entries always win. When the allow list is non-empty only peers (or
+//! source networks) on it may connect, which includes peers the relay dials.
+//! Inbound connections from a denied network are refused before the transport
+//! upgrade; peer entries and the allow list are checked once the PeerId is
+//! known. The file is re-read when it changes, and connections that the new
+//! lists no longer admit are closed.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ipnet::IpNet;
+use libp2p::core::transport::PortUse;
+use libp2p::core::Endpoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{
+    dummy, CloseConnection, ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm,
+    NetworkBehaviour, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use log::info;
+use serde::Deserialize;
+
+/// Errors that can occur while loading an access list.
+#[derive(Debug, thiserror::Error)]
+pub enum AccessListError {
+    #[error("failed to read access list {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse access list: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("invalid access list entry: {0}")]
+    InvalidEntry(String),
+}
+
+/// Why a connection was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DenyReason {
+    /// The PeerId is on the deny list
+    DeniedPeer,
+    /// The remote address is in a denied network
+    DeniedAddress,
+    /// The allow list is in use and neither the PeerId nor the address is on it
+    NotAllowed,
+}
+
+impl fmt::Display for DenyReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DenyReason::DeniedPeer => "denied_peer",
+            DenyReason::DeniedAddress => "denied_address",
+            DenyReason::NotAllowed => "not_allowed",
+        })
+    }
+}
+
+/// Reason attached to the [`ConnectionDenied`] of a refused connection.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub struct AccessDenied {
+    pub reason: DenyReason,
+    /// The `reason` text of the matching deny entry, if any
+    pub note: Option<String>,
+}
+
+impl fmt::Display for AccessDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "access list: {}", self.reason)?;
+        if let Some(note) = &self.note {
+            write!(f, " ({})", note)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Peer(PeerId),
+    Network(IpNet),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    target: Target,
+    expires_at: Option<SystemTime>,
+    reason: Option<String>,
+}
+
+impl Rule {
+    fn is_active(&self, now: SystemTime) -> bool {
+        self.expires_at.is_none_or(|at| now < at)
+    }
+
+    fn matches(&self, peer: Option<&PeerId>, ip: Option<IpAddr>) -> bool {
+        match &self.target {
+            Target::Peer(id) => peer == Some(id),
+            Target::Network(net) => ip.is_some_and(|ip| net.contains(&ip)),
+        }
+    }
+}
+
+// On-disk representation
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AccessListFile {
+    deny: Vec<EntryFile>,
+    allow: Vec<EntryFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EntryFile {
+    peer: Option<String>,
+    cidr: Option<String>,
+    expires_at: Option<u64>,
+    reason: Option<String>,
+}
+
+impl TryFrom<EntryFile> for Rule {
+    type Error = AccessListError;
+
+    fn try_from(entry: EntryFile) -> Result<Self, Self::Error> {
+        let target =
+            match (entry.peer, entry.cidr) {
+                (Some(peer), None) => Target::Peer(peer.parse().map_err(|e| {
+                    AccessListError::InvalidEntry(format!("peer '{}': {}", peer, e))
+                })?),
+                (None, Some(cidr)) => Target::Network(parse_network(&cidr)?),
+                _ => {
+                    return Err(AccessListError::InvalidEntry(
+                        "each entry needs exactly one of `peer` and `cidr`".to_string(),
+                    ))
+                }
+            };
+        Ok(Self {
+            target,
+            expires_at: entry
+                .expires_at
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            reason: entry.reason,
+        })
+    }
+}
+
+// A CIDR, or a single address standing for its host route
+fn parse_network(value: &str) -> Result<IpNet, AccessListError> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map(|net| net.trunc())
+        .map_err(|_| AccessListError::InvalidEntry(format!("cidr '{}' is not a network", value)))
+}
+
+/// The deny list and the optional allow list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessList {
+    deny: Vec<Rule>,
+    allow: Vec<Rule>,
+}
+
+impl AccessList {
+    /// Parses an access list in the TOML format described in the module docs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AccessListError`] if the document or an entry is invalid.
+    pub fn from_toml(contents: &str) -> Result<Self, AccessListError> {
+        let file: AccessListFile = toml::from_str(contents)?;
+        Ok(Self {
+            deny: file
+                .deny
+                .into_iter()
+                .map(Rule::try_from)
+                .collect::<Result<_, _>>()?,
+            allow: file
+                .allow
+                .into_iter()
+                .map(Rule::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Reads and parses the access list at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AccessListError`] if the file cannot be read or is invalid.
+    pub fn load(path: &Path) -> Result<Self, AccessListError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| AccessListError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_toml(&contents)
+    }
+
+    /// Number of deny and allow entries that have not expired.
+    pub fn active_len(&self, now: SystemTime) -> (usize, usize) {
+        let active = |rules: &[Rule]| rules.iter().filter(|r| r.is_active(now)).count();
+        (active(&self.deny), active(&self.allow))
+    }
+
+    /// Checks a connection whose PeerId may not be known yet.
+    ///
+    /// Without a PeerId only network entries are applied, and the allow list
+    /// is only enforced if it holds no peer entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`AccessDenied`] reason when the connection is not admitted.
+    pub fn check(
+        &self,
+        peer: Option<&PeerId>,
+        ip: Option<IpAddr>,
+        now: SystemTime,
+    ) -> Result<(), AccessDenied> {
+        if let Some(rule) = self
+            .deny
+            .iter()
+            .find(|rule| rule.is_active(now) && rule.matches(peer, ip))
+        {
+            return Err(AccessDenied {
+                reason: match rule.target {
+                    Target::Peer(_) => DenyReason::DeniedPeer,
+                    Target::Network(_) => DenyReason::DeniedAddress,
+                },
+                note: rule.reason.clone(),
+            });
+        }
+        let mut allow = self
+            .allow
+            .iter()
+            .filter(|rule| rule.is_active(now))
+            .peekable();
+        if allow.peek().is_none() {
+            return Ok(());
+        }
+        let mut undecided = false;
+        for rule in allow {
+            if rule.matches(peer, ip) {
+                return Ok(());
+            }
+            undecided |= peer.is_none() && matches!(rule.target, Target::Peer(_));
+        }
+        if undecided {
+            return Ok(());
+        }
+        Err(AccessDenied {
+            reason: DenyReason::NotAllowed,
+            note: None,
+        })
+    }
+}
+
+/// Enforces an [`AccessList`], optionally reloaded from a file. Emits no events.
+pub struct Behaviour {
+    list: AccessList,
+    file: Option<(PathBuf, Option<SystemTime>)>,
+    connections: HashMap<ConnectionId, (PeerId, Option<IpAddr>)>,
+    to_close: VecDeque<(PeerId, ConnectionId)>,
+    denied: BTreeMap<DenyReason, u64>,
+    waker: Option<Waker>,
+}
+
+impl Behaviour {
+    /// Enforces `list`; it only changes through [`Behaviour::set_list`].
+    pub fn new(list: AccessList) -> Self {
+        Self {
+            list,
+            file: None,
+            connections: HashMap::new(),
+            to_close: VecDeque::new(),
+            denied: BTreeMap::new(),
+            waker: None,
+        }
+    }
+
+    /// Loads the list from `path`; [`Behaviour::reload_if_changed`] picks up later edits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AccessListError`] if the file cannot be read or is invalid.
+    pub fn from_file(path: &Path) -> Result<Self, AccessListError> {
+        let modified = modified(path);
+        let mut behaviour = Self::new(AccessList::load(path)?);
+        behaviour.file = Some((path.to_path_buf(), modified));
+        Ok(behaviour)
+    }
+
+    /// Re-reads the file if its modification time changed. Returns `true` when
+    /// a new list was loaded; on error the current list stays in force.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AccessListError`] if the changed file cannot be loaded.
+    pub fn reload_if_changed(&mut self) -> Result<bool, AccessListError> {
+        let Some((path, last_modified)) = &self.file else {
+            return Ok(false);
+        };
+        let modified = modified(path);
+        if modified == *last_modified {
+            return Ok(false);
+        }
+        let list = AccessList::load(path)?;
+        self.file = Some((path.clone(), modified));
+        self.set_list(list);
+        Ok(true)
+    }
+
+    /// Replaces the lists and closes established connections they no longer admit.
+    pub fn set_list(&mut self, list: AccessList) {
+        self.list = list;
+        let now = SystemTime::now();
+        for (connection_id, (peer, ip)) in &self.connections {
+            if let Err(denied) = self.list.check(Some(peer), *ip, now) {
+                info!("Closing connection to {}: {}", peer, denied);
+                self.to_close.push_back((*peer, *connection_id));
+            }
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// The lists currently enforced.
+    pub fn list(&self) -> &AccessList {
+        &self.list
+    }
+
+    /// Number of refused connections per reason since startup.
+    pub fn denied_counts(&self) -> &BTreeMap<DenyReason, u64> {
+        &self.denied
+    }
+
+    fn check(&mut self, peer: Option<&PeerId>, addr: &Multiaddr) -> Result<(), ConnectionDenied> {
+        self.list
+            .check(peer, ip_of(addr), SystemTime::now())
+            .map_err(|denied| {
+                *self.denied.entry(denied.reason).or_default() += 1;
+                ConnectionDenied::new(denied)
+            })
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.check(None, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check(Some(&peer), remote_addr)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check(Some(&peer), addr)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                self.connections.insert(
+                    established.connection_id,
+                    (
+                        established.peer_id,
+                        ip_of(established.endpoint.get_remote_address()),
+                    ),
+                );
+            }
+            FromSwarm::ConnectionClosed(ConnectionClosed { connection_id, .. }) => {
+                self.connections.remove(&connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        if let Some((peer_id, connection_id)) = self.to_close.pop_front() {
+            return Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::One(connection_id),
+            });
+        }
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    match addr.iter().next()? {
+        Protocol::Ip4(ip) => Some(ip.into()),
+        Protocol::Ip6(ip) => Some(ip.into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_id() -> PeerId {
+        PeerId::from(libp2p::identity::Keypair::generate_ed25519().public())
+    }
+
+    #[test]
+    fn test_deny_entries_and_expiry() {
+        let banned = peer_id();
+        let list = AccessList::from_toml(&format!(
+            r#"
+            [[deny]]
+            peer = "{}"
+            reason = "spam"
+
+            [[deny]]
+            cidr = "203.0.113.0/24"
+            expires_at = 2000000000
+            "#,
+            banned
+        ))
+        .unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(1_900_000_000);
+        let banned_ip = Some("203.0.113.9".parse().unwrap());
+
+        let denied = list.check(Some(&banned), None, now).unwrap_err();
+        assert_eq!(denied.reason, DenyReason::DeniedPeer);
+        assert_eq!(denied.note.as_deref(), Some("spam"));
+        assert_eq!(
+            list.check(None, banned_ip, now).unwrap_err().reason,
+            DenyReason::DeniedAddress
+        );
+        assert!(list
+            .check(Some(&peer_id()), Some("198.51.100.1".parse().unwrap()), now)
+            .is_ok());
+
+        // Expired entries no longer apply
+        let later = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+        assert!(list.check(None, banned_ip, later).is_ok());
+        assert_eq!(list.active_len(later), (1, 0));
+    }
+
+    #[test]
+    fn test_allow_list_admits_only_listed_peers() {
+        let known = peer_id();
+        let list = AccessList::from_toml(&format!("[[allow]]\npeer = \"{}\"\n", known)).unwrap();
+        let now = SystemTime::now();
+        let ip = Some("198.51.100.1".parse().unwrap());
+
+        // The PeerId is not known yet before the upgrade
+        assert!(list.check(None, ip, now).is_ok());
+        assert!(list.check(Some(&known), ip, now).is_ok());
+        assert_eq!(
+            list.check(Some(&peer_id()), ip, now).unwrap_err().reason,
+            DenyReason::NotAllowed
+        );
+    }
+
+    #[test]
+    fn test_invalid_entries_are_rejected() {
+        assert!(AccessList::from_toml("[[deny]]\nreason = \"nothing\"\n").is_err());
+        assert!(AccessList::from_toml("[[deny]]\npeer = \"not-a-peer\"\n").is_err());
+        assert_eq!(
+            AccessList::from_toml("[[deny]]\ncidr = \"2001:db8::1\"\n")
+                .unwrap()
+                .active_len(SystemTime::now()),
+            (1, 0)
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Addresses of other peers, learnt from discovery announcements and dials.
+//!
+//! The relay dials peers it discovers and re-announces the addresses of its
+//! connected peers in the legacy JSON format. [`AddressBook`] keeps those
+//! addresses bounded: at most `max_addrs_per_peer` per peer and `max_peers`
+//! peers, the least recently seen going first, and nothing older than the TTL.
+//! Each failed dial demotes an address; after `max_dial_failures` in a row it
+//! is dropped, so dead addresses are not dialed forever.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::{Multiaddr, PeerId};
+
+#[derive(Debug, Clone)]
+struct AddressEntry {
+    addr: Multiaddr,
+    last_seen: Instant,
+    // Failed dials since the address last worked
+    failures: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PeerEntry {
+    addrs: Vec<AddressEntry>,
+}
+
+impl PeerEntry {
+    fn last_seen(&self) -> Option<Instant> {
+        self.addrs.iter().map(|entry| entry.last_seen).max()
+    }
+
+    // Fewest failures first, then most recently seen
+    fn sort(&mut self) {
+        self.addrs.sort_by(|a, b| {
+            a.failures
+                .cmp(&b.failures)
+                .then(b.last_seen.cmp(&a.last_seen))
+        });
+    }
+}
+
+/// Bounded addresses per peer, with last-seen times and dial failures.
+#[derive(Debug, Clone)]
+pub struct AddressBook {
+    max_peers: usize,
+    max_addrs_per_peer: usize,
+    ttl: Duration,
+    max_dial_failures: u32,
+    peers: HashMap<PeerId, PeerEntry>,
+}
+
+impl AddressBook {
+    /// Creates a book of at most `max_peers` peers and `max_addrs_per_peer`
+    /// addresses each, forgetting addresses not seen for `ttl` or that failed
+    /// `max_dial_failures` dials in a row. A bound of 0 is not enforced.
+    pub fn new(
+        max_peers: usize,
+        max_addrs_per_peer: usize,
+        ttl: Duration,
+        max_dial_failures: u32,
+    ) -> Self {
+        Self {
+            max_peers,
+            max_addrs_per_peer,
+            ttl,
+            max_dial_failures,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Records that `peer` was announced at `addrs` at `now`. Known addresses
+    /// are refreshed but keep their failures.
+    pub fn add(&mut self, peer: PeerId, addrs: impl IntoIterator<Item = Multiaddr>, now: Instant) {
+        for addr in addrs {
+            self.upsert(peer, addr, now, false);
+        }
+    }
+
+    /// Records a successful dial of `peer` at `addr`, clearing its failures.
+    pub fn connected(&mut self, peer: PeerId, addr: Multiaddr, now: Instant) {
+        self.upsert(peer, addr, now, true);
+    }
+
+    /// Demotes `addrs` of `peer` after a failed dial, dropping those that
+    /// reached `max_dial_failures`, and the peer once it has no address left.
+    pub fn dial_failed(&mut self, peer: &PeerId, addrs: &[Multiaddr]) {
+        let Some(entry) = self.peers.get_mut(peer) else {
+            return;
+        };
+        for address in &mut entry.addrs {
+            if addrs.contains(&address.addr) {
+                address.failures += 1;
+            }
+        }
+        let max_dial_failures = self.max_dial_failures;
+        entry
+            .addrs
+            .retain(|address| max_dial_failures == 0 || address.failures < max_dial_failures);
+        entry.sort();
+        if entry.addrs.is_empty() {
+            self.peers.remove(peer);
+        }
+    }
+
+    /// Addresses of `peer`, the most promising first.
+    pub fn addresses(&self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.peers.get(peer).map_or_else(Vec::new, |entry| {
+            entry
+                .addrs
+                .iter()
+                .map(|address| address.addr.clone())
+                .collect()
+        })
+    }
+
+    /// The address to dial `peer` at: fewest failures, then most recently seen.
+    pub fn best_address(&self, peer: &PeerId) -> Option<&Multiaddr> {
+        self.peers
+            .get(peer)
+            .and_then(|entry| entry.addrs.first())
+            .map(|address| &address.addr)
+    }
+
+    /// Forgets addresses not seen for the TTL at `now`, and peers left without
+    /// any. Returns the number of addresses forgotten, always 0 with a zero TTL.
+    pub fn evict_expired(&mut self, now: Instant) -> usize {
+        let ttl = self.ttl;
+        if ttl.is_zero() {
+            return 0;
+        }
+        let mut evicted = 0;
+        self.peers.retain(|_, entry| {
+            let before = entry.addrs.len();
+            entry
+                .addrs
+                .retain(|address| now.duration_since(address.last_seen) < ttl);
+            evicted += before - entry.addrs.len();
+            !entry.addrs.is_empty()
+        });
+        evicted
+    }
+
+    /// Number of peers with known addresses.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Whether no peer is known.
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    fn upsert(&mut self, peer: PeerId, addr: Multiaddr, now: Instant, reachable: bool) {
+        if !self.peers.contains_key(&peer)
+            && self.max_peers > 0
+            && self.peers.len() >= self.max_peers
+        {
+            self.evict_least_recently_seen();
+        }
+        let entry = self.peers.entry(peer).or_default();
+        match entry.addrs.iter_mut().find(|address| address.addr == addr) {
+            Some(address) => {
+                address.last_seen = now;
+                if reachable {
+                    address.failures = 0;
+                }
+            }
+            None => entry.addrs.push(AddressEntry {
+                addr,
+                last_seen: now,
+                failures: 0,
+            }),
+        }
+        entry.sort();
+        if self.max_addrs_per_peer > 0 {
+            entry.addrs.truncate(self.max_addrs_per_peer);
+        }
+    }
+
+    fn evict_least_recently_seen(&mut self) {
+        let oldest = self
+            .peers
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_seen())
+            .map(|(peer, _)| *peer);
+        if let Some(peer) = oldest {
+            self.peers.remove(&peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> Multiaddr {
+        format!("/ip4/192.0.2.1/tcp/{port}").parse().unwrap()
+    }
+
+    #[test]
+    fn test_caps_and_ttl() {
+        let mut book = AddressBook::new(2, 2, Duration::from_secs(60), 3);
+        let (alice, bob, carol) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let start = Instant::now();
+
+        book.add(alice, [addr(1), addr(2)], start);
+        book.add(alice, [addr(3)], start + Duration::from_secs(1));
+        // The least recently seen address made room for the new one
+        assert_eq!(book.addresses(&alice), vec![addr(3), addr(1)]);
+        assert_eq!(book.best_address(&alice), Some(&addr(3)));
+
+        book.add(bob, [addr(4)], start + Duration::from_secs(2));
+        book.add(carol, [addr(5)], start + Duration::from_secs(3));
+        // Alice, seen least recently, was evicted to stay within two peers
+        assert_eq!(book.len(), 2);
+        assert!(book.addresses(&alice).is_empty());
+
+        assert_eq!(book.evict_expired(start + Duration::from_secs(62)), 1);
+        assert!(book.addresses(&bob).is_empty());
+        assert_eq!(book.addresses(&carol), vec![addr(5)]);
+    }
+
+    #[test]
+    fn test_failed_dials_demote_then_drop() {
+        let mut book = AddressBook::new(0, 0, Duration::from_secs(60), 2);
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        book.add(peer, [addr(1), addr(2)], now);
+        book.connected(peer, addr(1), now);
+        assert_eq!(book.best_address(&peer), Some(&addr(1)));
+
+        book.dial_failed(&peer, &[addr(1)]);
+        assert_eq!(book.best_address(&peer), Some(&addr(2)));
+
+        // Working again clears the failures
+        book.connected(peer, addr(1), now);
+        book.dial_failed(&peer, &[addr(2)]);
+        book.dial_failed(&peer, &[addr(2)]);
+        assert_eq!(book.addresses(&peer), vec![addr(1)]);
+
+        book.dial_failed(&peer, &[addr(1)]);
+        book.dial_failed(&peer, &[addr(1)]);
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_zero_ttl_never_expires() {
+        let mut book = AddressBook::new(0, 0, Duration::ZERO, 0);
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        book.add(peer, [addr(1)], now);
+        assert_eq!(book.evict_expired(now + Duration::from_secs(86_400)), 0);
+        assert_eq!(book.addresses(&peer), vec![addr(1)]);
+    }
+}
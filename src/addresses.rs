@@ -0,0 +1,308 @@
+//! Single source of truth for the addresses this relay advertises.
+//!
+//! The same list is pushed into the swarm's external addresses (and from there
+//! into Identify, which runs with `hide_listen_addrs`) and served by the
+//! `/adresses` HTTP endpoint, so both channels always agree.
+//!
+//! Semantics follow js-libp2p's `addresses` option used by `src/relai.ts`:
+//! - when `announce` is non-empty it replaces the listen addresses entirely,
+//! - otherwise the addresses reported by `NewListenAddr` are used,
+//! - `no_announce` entries filter the result; an entry matches every address it is a prefix of.
+//!
+//! webrtc-direct is the exception: its port (unless fixed) and certhash are
+//! only known once the listener reports them, so webrtc-direct listen addresses
+//! are advertised next to the announce list unless it already has one. With a
+//! domain configured their IP is replaced by `/dns4/<domain>` (`/dns6` for IPv6),
+//! keeping the real port. They carry the certhashes set with
+//! [`AdvertisedAddresses::set_certhashes`], which may list more than one
+//! certificate while a rotation is pending.
+//!
+//! Relayed (`/p2p-circuit`) listen addresses, obtained from reservations on
+//! upstream relays, are likewise always advertised next to the announce list.
+
+use libp2p::{multiaddr::Protocol, multihash::Multihash, Multiaddr, PeerId};
+
+/// Tracks listen addresses and computes the advertised address set.
+#[derive(Debug, Clone)]
+pub struct AdvertisedAddresses {
+    local_peer_id: PeerId,
+    announce: Vec<Multiaddr>,
+    no_announce: Vec<Multiaddr>,
+    listen: Vec<Multiaddr>,
+    certhashes: Vec<Multihash<64>>,
+    domain: Option<String>,
+}
+
+impl AdvertisedAddresses {
+    /// Creates the address set for `local_peer_id`.
+    ///
+    /// `announce` replaces listen addresses when non-empty, `no_announce`
+    /// removes every address starting with one of its entries.
+    pub fn new(
+        local_peer_id: PeerId,
+        announce: Vec<Multiaddr>,
+        no_announce: Vec<Multiaddr>,
+    ) -> Self {
+        Self {
+            local_peer_id,
+            announce,
+            no_announce,
+            listen: Vec::new(),
+            certhashes: Vec::new(),
+            domain: None,
+        }
+    }
+
+    /// Advertises webrtc-direct listen addresses under `domain` instead of their IP.
+    pub fn with_domain(mut self, domain: Option<String>) -> Self {
+        self.domain = domain;
+        self
+    }
+
+    /// Records a new listen address. Returns `true` if it was not known yet.
+    pub fn add_listen_addr(&mut self, address: Multiaddr) -> bool {
+        if self.listen.contains(&address) {
+            return false;
+        }
+        self.listen.push(address);
+        true
+    }
+
+    /// Forgets a listen address. Returns `true` if it was known.
+    pub fn remove_listen_addr(&mut self, address: &Multiaddr) -> bool {
+        let before = self.listen.len();
+        self.listen.retain(|a| a != address);
+        before != self.listen.len()
+    }
+
+    /// Sets the certhashes carried by webrtc-direct addresses, replacing any
+    /// certhash already present in them. An empty list leaves addresses untouched.
+    pub fn set_certhashes(&mut self, certhashes: Vec<Multihash<64>>) {
+        self.certhashes = certhashes;
+    }
+
+    /// The advertised addresses, each ending in `/p2p/<local peer id>`.
+    pub fn addresses(&self) -> Vec<Multiaddr> {
+        let listen = self.listen.iter().filter(|addr| !is_unspecified(addr));
+        let candidates: Vec<Multiaddr> = if self.announce.is_empty() {
+            listen.map(|addr| self.with_domain_host(addr)).collect()
+        } else {
+            let announces_webrtc_direct = self.announce.iter().any(is_webrtc_direct);
+            let derived = listen
+                .filter(|addr| {
+                    is_relayed(addr) || (!announces_webrtc_direct && is_webrtc_direct(addr))
+                })
+                .map(|addr| self.with_domain_host(addr));
+            self.announce.iter().cloned().chain(derived).collect()
+        };
+
+        let mut addresses: Vec<Multiaddr> = Vec::new();
+        for addr in &candidates {
+            if self
+                .no_announce
+                .iter()
+                .any(|filter| starts_with(addr, filter))
+            {
+                continue;
+            }
+            let addr = with_peer_id(self.with_certhashes(addr), self.local_peer_id);
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        addresses
+    }
+
+    // Swap the IP of a webrtc-direct address for the configured domain, keeping the port
+    fn with_domain_host(&self, address: &Multiaddr) -> Multiaddr {
+        let Some(domain) = &self.domain else {
+            return address.clone();
+        };
+        if !is_webrtc_direct(address) {
+            return address.clone();
+        }
+        address
+            .iter()
+            .map(|protocol| match protocol {
+                Protocol::Ip4(_) => Protocol::Dns4(domain.clone().into()),
+                Protocol::Ip6(_) => Protocol::Dns6(domain.clone().into()),
+                other => other,
+            })
+            .collect()
+    }
+
+    fn with_certhashes(&self, address: &Multiaddr) -> Multiaddr {
+        if self.certhashes.is_empty() || !is_webrtc_direct(address) {
+            return address.clone();
+        }
+        let mut rewritten = Multiaddr::empty();
+        for protocol in address.iter() {
+            match protocol {
+                Protocol::Certhash(_) => {}
+                Protocol::WebRTCDirect => {
+                    rewritten.push(Protocol::WebRTCDirect);
+                    for hash in &self.certhashes {
+                        rewritten.push(Protocol::Certhash(*hash));
+                    }
+                }
+                other => rewritten.push(other),
+            }
+        }
+        rewritten
+    }
+}
+
+/// Appends `/p2p/<peer_id>` unless the address already ends with a peer ID.
+pub fn with_peer_id(address: Multiaddr, peer_id: PeerId) -> Multiaddr {
+    match address.iter().last() {
+        Some(Protocol::P2p(_)) => address,
+        _ => address.with(Protocol::P2p(peer_id)),
+    }
+}
+
+fn is_webrtc_direct(address: &Multiaddr) -> bool {
+    address.iter().any(|p| p == Protocol::WebRTCDirect)
+}
+
+fn is_relayed(address: &Multiaddr) -> bool {
+    address.iter().any(|p| p == Protocol::P2pCircuit)
+}
+
+// `0.0.0.0` / `::` listen addresses and bare shorthands like `/p2p-circuit` are not dialable
+fn is_unspecified(address: &Multiaddr) -> bool {
+    match address.iter().next() {
+        Some(Protocol::Ip4(ip)) => ip.is_unspecified(),
+        Some(Protocol::Ip6(ip)) => ip.is_unspecified(),
+        Some(Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) | Protocol::Dnsaddr(_)) => {
+            false
+        }
+        _ => true,
+    }
+}
+
+// Whether `prefix` matches the leading protocols of `address`
+fn starts_with(address: &Multiaddr, prefix: &Multiaddr) -> bool {
+    let mut address = address.iter();
+    prefix.iter().all(|p| address.next() == Some(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    fn peer_id() -> PeerId {
+        PeerId::from(Keypair::generate_ed25519().public())
+    }
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_announce_replaces_listen_addresses() {
+        let id = peer_id();
+        let mut advertised = AdvertisedAddresses::new(
+            id,
+            vec![addr("/dns4/relay.example.com/tcp/443/wss")],
+            vec![],
+        );
+        advertised.add_listen_addr(addr("/ip4/10.0.0.5/tcp/12345/ws"));
+
+        assert_eq!(
+            advertised.addresses(),
+            vec![addr(&format!(
+                "/dns4/relay.example.com/tcp/443/wss/p2p/{}",
+                id
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_relayed_addresses_advertised_next_to_announce() {
+        let id = peer_id();
+        let relay = peer_id();
+        let mut advertised =
+            AdvertisedAddresses::new(id, vec![addr("/dns4/home.example.com/tcp/443/wss")], vec![]);
+        advertised.add_listen_addr(addr("/ip4/192.168.1.20/tcp/4001"));
+        advertised.add_listen_addr(addr(&format!(
+            "/dns4/relay.example.com/tcp/443/wss/p2p/{relay}/p2p-circuit"
+        )));
+
+        assert_eq!(
+            advertised.addresses(),
+            vec![
+                addr(&format!("/dns4/home.example.com/tcp/443/wss/p2p/{id}")),
+                addr(&format!(
+                    "/dns4/relay.example.com/tcp/443/wss/p2p/{relay}/p2p-circuit/p2p/{id}"
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listen_addresses_filtered_by_no_announce() {
+        let id = peer_id();
+        let mut advertised = AdvertisedAddresses::new(id, vec![], vec![addr("/ip4/127.0.0.1")]);
+        advertised.add_listen_addr(addr("/ip4/127.0.0.1/tcp/4001"));
+        advertised.add_listen_addr(addr("/ip4/203.0.113.7/tcp/4001"));
+        advertised.add_listen_addr(addr("/p2p-circuit"));
+
+        assert_eq!(
+            advertised.addresses(),
+            vec![addr(&format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", id))]
+        );
+
+        advertised.remove_listen_addr(&addr("/ip4/203.0.113.7/tcp/4001"));
+        assert!(advertised.addresses().is_empty());
+    }
+
+    #[test]
+    fn test_webrtc_direct_addresses_carry_all_certhashes() {
+        let id = peer_id();
+        let current = Multihash::<64>::wrap(0x12, &[1; 32]).unwrap();
+        let next = Multihash::<64>::wrap(0x12, &[2; 32]).unwrap();
+        let mut advertised = AdvertisedAddresses::new(id, vec![], vec![]);
+        advertised.add_listen_addr(
+            addr("/ip4/203.0.113.7/udp/9090/webrtc-direct").with(Protocol::Certhash(current)),
+        );
+        advertised.set_certhashes(vec![current, next]);
+
+        let expected = addr("/ip4/203.0.113.7/udp/9090/webrtc-direct")
+            .with(Protocol::Certhash(current))
+            .with(Protocol::Certhash(next))
+            .with(Protocol::P2p(id));
+        assert_eq!(advertised.addresses(), vec![expected]);
+    }
+
+    #[test]
+    fn test_webrtc_direct_listen_address_advertised_under_domain() {
+        let id = peer_id();
+        let certhash = Multihash::<64>::wrap(0x12, &[1; 32]).unwrap();
+        let mut advertised = AdvertisedAddresses::new(
+            id,
+            vec![addr("/dns4/relay.example.com/tcp/443/wss")],
+            vec![],
+        )
+        .with_domain(Some("relay.example.com".to_string()));
+        // One webrtc-direct listener reported on two interfaces
+        for ip in ["127.0.0.1", "10.0.0.5"] {
+            advertised.add_listen_addr(
+                addr(&format!("/ip4/{}/udp/41234/webrtc-direct", ip))
+                    .with(Protocol::Certhash(certhash)),
+            );
+        }
+        advertised.add_listen_addr(addr("/ip4/10.0.0.5/tcp/12345/ws"));
+
+        let webrtc_direct = addr("/dns4/relay.example.com/udp/41234/webrtc-direct")
+            .with(Protocol::Certhash(certhash))
+            .with(Protocol::P2p(id));
+        assert_eq!(
+            advertised.addresses(),
+            vec![
+                addr(&format!("/dns4/relay.example.com/tcp/443/wss/p2p/{}", id)),
+                webrtc_direct,
+            ]
+        );
+    }
+}
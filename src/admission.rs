@@ -0,0 +1,497 @@
+//! Admission policy for circuit relay reservations and circuits.
+//!
+//! Reservations (`ReservationReq`) and circuits (`CircuitReq`, judged by their
+//! source peer) are each admitted according to an [`AdmissionMode`]:
+//! - `open`: anyone, subject to the relay limits,
+//! - `allowlist`: only PeerIds on `admission.allowlist`,
+//! - `token`: allowlisted PeerIds, plus peers that presented a valid access token.
+//!
+//! An access token is issued out of band (e.g. by the app backend) and pinned
+//! to one PeerId: `<payload>.<signature>`, both base64url without padding,
+//! where the payload is the JSON `{"peer":"12D3KooW...","exp":<unix seconds>}`
+//! and the signature is an ed25519 signature of the payload bytes by one of the
+//! configured issuers. Clients hand it to the relay with `POST /reservations/token`
+//! before reserving. Tokens valid for longer than `max_token_ttl_secs` are refused.
+//!
+//! The policy plugs into `relay::Config` as a rate limiter, so a denial reaches
+//! the client as `RESOURCE_LIMIT_EXCEEDED`. [`Behaviour`] reports each denial
+//! as an [`Event`] carrying the reason.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use libp2p::core::transport::PortUse;
+use libp2p::core::Endpoint;
+use libp2p::identity::ed25519;
+use libp2p::swarm::{
+    dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{relay, Multiaddr, PeerId};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// How reservation or circuit requests are admitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdmissionMode {
+    /// Every peer, subject to the relay limits
+    #[default]
+    Open,
+    /// Only allowlisted PeerIds
+    Allowlist,
+    /// Allowlisted PeerIds and peers holding a valid access token
+    Token,
+}
+
+/// Errors in the admission configuration.
+#[derive(Debug, thiserror::Error)]
+pub enum AdmissionError {
+    #[error("invalid allowlist PeerId '{0}'")]
+    InvalidPeerId(String),
+    #[error("invalid token issuer key '{0}': expected a base64 ed25519 public key")]
+    InvalidIssuerKey(String),
+    #[error("token admission needs at least one entry in admission.token_issuers")]
+    NoTokenIssuer,
+}
+
+/// Why an access token was refused.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum TokenError {
+    #[error("malformed token")]
+    Malformed,
+    #[error("signature does not match any trusted issuer")]
+    BadSignature,
+    #[error("token has expired")]
+    Expired,
+    #[error("token is valid for longer than the allowed {0:?}")]
+    TooLongLived(Duration),
+    #[error("token admission is not enabled")]
+    Disabled,
+}
+
+/// Why a request was denied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenialReason {
+    /// Allowlist mode and the peer is not on the allowlist
+    NotAllowlisted,
+    /// Token mode and the peer holds no unexpired token
+    NoValidToken,
+}
+
+impl fmt::Display for DenialReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DenialReason::NotAllowlisted => "not on the allowlist",
+            DenialReason::NoValidToken => "no valid access token",
+        })
+    }
+}
+
+/// Emitted for every request the policy denies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    ReservationDenied {
+        peer_id: PeerId,
+        addr: Multiaddr,
+        reason: DenialReason,
+    },
+    CircuitDenied {
+        src_peer_id: PeerId,
+        addr: Multiaddr,
+        reason: DenialReason,
+    },
+}
+
+/// Signed claims of an access token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    /// The only PeerId the token admits
+    pub peer: String,
+    /// Expiry, Unix time in seconds
+    pub exp: u64,
+}
+
+struct State {
+    allowlist: HashSet<PeerId>,
+    issuers: Vec<ed25519::PublicKey>,
+    max_token_ttl: Duration,
+    // Token holders and when their token expires
+    grants: HashMap<PeerId, SystemTime>,
+}
+
+/// The admission policy, shared between the relay limiters, the HTTP token
+/// endpoint and [`Behaviour`].
+#[derive(Clone)]
+pub struct AdmissionPolicy {
+    reservations: AdmissionMode,
+    circuits: AdmissionMode,
+    state: Arc<Mutex<State>>,
+    denials: mpsc::UnboundedSender<Event>,
+}
+
+impl AdmissionPolicy {
+    /// Creates the policy and the [`Behaviour`] reporting its denials.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AdmissionError`] if an allowlist entry or issuer key does
+    /// not parse, or if token mode is selected without an issuer.
+    pub fn new(
+        reservations: AdmissionMode,
+        circuits: AdmissionMode,
+        allowlist: &[String],
+        token_issuers: &[String],
+        max_token_ttl: Duration,
+    ) -> Result<(Self, Behaviour), AdmissionError> {
+        let allowlist = allowlist
+            .iter()
+            .map(|peer| {
+                peer.trim()
+                    .parse()
+                    .map_err(|_| AdmissionError::InvalidPeerId(peer.clone()))
+            })
+            .collect::<Result<_, _>>()?;
+        let issuers: Vec<_> = token_issuers
+            .iter()
+            .map(|key| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(key.trim())
+                    .ok()
+                    .and_then(|bytes| ed25519::PublicKey::try_from_bytes(&bytes).ok())
+                    .ok_or_else(|| AdmissionError::InvalidIssuerKey(key.clone()))
+            })
+            .collect::<Result<_, _>>()?;
+        if issuers.is_empty()
+            && (reservations == AdmissionMode::Token || circuits == AdmissionMode::Token)
+        {
+            return Err(AdmissionError::NoTokenIssuer);
+        }
+        let (denials, events) = mpsc::unbounded();
+        let policy = Self {
+            reservations,
+            circuits,
+            state: Arc::new(Mutex::new(State {
+                allowlist,
+                issuers,
+                max_token_ttl,
+                grants: HashMap::new(),
+            })),
+            denials,
+        };
+        let behaviour = Behaviour {
+            policy: policy.clone(),
+            events,
+        };
+        Ok((policy, behaviour))
+    }
+
+    /// Whether both reservations and circuits are open to everyone.
+    pub fn is_open(&self) -> bool {
+        self.reservations == AdmissionMode::Open && self.circuits == AdmissionMode::Open
+    }
+
+    /// Verifies an access token and admits its peer until the token expires.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`TokenError`] if the token is malformed, not signed by a
+    /// trusted issuer, expired, or valid for longer than allowed.
+    pub fn submit_token(
+        &self,
+        token: &str,
+        now: SystemTime,
+    ) -> Result<(PeerId, SystemTime), TokenError> {
+        if self.reservations != AdmissionMode::Token && self.circuits != AdmissionMode::Token {
+            return Err(TokenError::Disabled);
+        }
+        let mut state = self.state.lock();
+        let (payload, signature) = token.trim().split_once('.').ok_or(TokenError::Malformed)?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| TokenError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| TokenError::Malformed)?;
+        if !state
+            .issuers
+            .iter()
+            .any(|key| key.verify(&payload, &signature))
+        {
+            return Err(TokenError::BadSignature);
+        }
+        let claims: TokenClaims =
+            serde_json::from_slice(&payload).map_err(|_| TokenError::Malformed)?;
+        let peer: PeerId = claims.peer.parse().map_err(|_| TokenError::Malformed)?;
+        let expires_at = UNIX_EPOCH + Duration::from_secs(claims.exp);
+        if expires_at <= now {
+            return Err(TokenError::Expired);
+        }
+        if expires_at.duration_since(now).unwrap_or_default() > state.max_token_ttl {
+            return Err(TokenError::TooLongLived(state.max_token_ttl));
+        }
+        let grant = state.grants.entry(peer).or_insert(expires_at);
+        *grant = (*grant).max(expires_at);
+        Ok((peer, *grant))
+    }
+
+    /// Rate limiter enforcing the reservation mode, for `relay::Config::reservation_rate_limiters`.
+    pub fn reservation_limiter(&self) -> Box<dyn relay::RateLimiter> {
+        let policy = self.clone();
+        Box::new(move |peer_id: PeerId, addr: &Multiaddr, _now: Instant| {
+            match policy.check(policy.reservations, &peer_id, SystemTime::now()) {
+                Ok(()) => true,
+                Err(reason) => {
+                    let _ = policy.denials.unbounded_send(Event::ReservationDenied {
+                        peer_id,
+                        addr: addr.clone(),
+                        reason,
+                    });
+                    false
+                }
+            }
+        })
+    }
+
+    /// Rate limiter enforcing the circuit mode on the source peer, for
+    /// `relay::Config::circuit_src_rate_limiters`.
+    pub fn circuit_limiter(&self) -> Box<dyn relay::RateLimiter> {
+        let policy = self.clone();
+        Box::new(
+            move |src_peer_id: PeerId, addr: &Multiaddr, _now: Instant| match policy.check(
+                policy.circuits,
+                &src_peer_id,
+                SystemTime::now(),
+            ) {
+                Ok(()) => true,
+                Err(reason) => {
+                    let _ = policy.denials.unbounded_send(Event::CircuitDenied {
+                        src_peer_id,
+                        addr: addr.clone(),
+                        reason,
+                    });
+                    false
+                }
+            },
+        )
+    }
+
+    fn check(
+        &self,
+        mode: AdmissionMode,
+        peer: &PeerId,
+        now: SystemTime,
+    ) -> Result<(), DenialReason> {
+        let mut state = self.state.lock();
+        match mode {
+            AdmissionMode::Open => Ok(()),
+            _ if state.allowlist.contains(peer) => Ok(()),
+            AdmissionMode::Allowlist => Err(DenialReason::NotAllowlisted),
+            AdmissionMode::Token => {
+                state.grants.retain(|_, expires_at| *expires_at > now);
+                if state.grants.contains_key(peer) {
+                    Ok(())
+                } else {
+                    Err(DenialReason::NoValidToken)
+                }
+            }
+        }
+    }
+}
+
+/// Surfaces the policy's denials as swarm events. Handles no connections.
+pub struct Behaviour {
+    policy: AdmissionPolicy,
+    events: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Behaviour {
+    /// The policy this behaviour reports on, e.g. to hand tokens to.
+    pub fn policy(&self) -> &AdmissionPolicy {
+        &self.policy
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Event;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _peer: PeerId,
+        _local_addr: &Multiaddr,
+        _remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _peer: PeerId,
+        _addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, _event: FromSwarm) {}
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        let event: Infallible = event;
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        match self.events.poll_next_unpin(cx) {
+            Poll::Ready(Some(event)) => Poll::Ready(ToSwarm::GenerateEvent(event)),
+            // `self.policy` keeps a sender alive, so the channel never closes
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(15 * 60);
+
+    fn peer_id() -> PeerId {
+        PeerId::from(libp2p::identity::Keypair::generate_ed25519().public())
+    }
+
+    // Signs an access token for `peer` valid until `expires_at`, as the app backend would
+    fn sign_token(issuer: &ed25519::Keypair, peer: &PeerId, expires_at: SystemTime) -> String {
+        let claims = TokenClaims {
+            peer: peer.to_string(),
+            exp: expires_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        let payload = serde_json::to_vec(&claims).unwrap();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(issuer.sign(&payload))
+        )
+    }
+
+    fn issuer_key(issuer: &ed25519::Keypair) -> String {
+        base64::engine::general_purpose::STANDARD.encode(issuer.public().to_bytes())
+    }
+
+    #[test]
+    fn test_allowlist_mode() {
+        let allowed = peer_id();
+        let (policy, mut behaviour) = AdmissionPolicy::new(
+            AdmissionMode::Allowlist,
+            AdmissionMode::Open,
+            &[allowed.to_string()],
+            &[],
+            TTL,
+        )
+        .unwrap();
+        let mut reservations = policy.reservation_limiter();
+        let mut circuits = policy.circuit_limiter();
+        let addr: Multiaddr = "/ip4/203.0.113.9/tcp/4001".parse().unwrap();
+        let stranger = peer_id();
+
+        assert!(reservations.try_next(allowed, &addr, Instant::now()));
+        assert!(!reservations.try_next(stranger, &addr, Instant::now()));
+        assert!(circuits.try_next(stranger, &addr, Instant::now()));
+
+        let event = futures::executor::block_on(behaviour.events.next()).unwrap();
+        assert_eq!(
+            event,
+            Event::ReservationDenied {
+                peer_id: stranger,
+                addr,
+                reason: DenialReason::NotAllowlisted
+            }
+        );
+    }
+
+    #[test]
+    fn test_token_admits_pinned_peer_until_expiry() {
+        let issuer = ed25519::Keypair::generate();
+        let (policy, _behaviour) = AdmissionPolicy::new(
+            AdmissionMode::Token,
+            AdmissionMode::Token,
+            &[],
+            &[issuer_key(&issuer)],
+            TTL,
+        )
+        .unwrap();
+        let holder = peer_id();
+        let now = SystemTime::now();
+
+        assert_eq!(
+            policy.check(AdmissionMode::Token, &holder, now),
+            Err(DenialReason::NoValidToken)
+        );
+        let token = sign_token(&issuer, &holder, now + Duration::from_secs(600));
+        assert_eq!(policy.submit_token(&token, now).unwrap().0, holder);
+        assert!(policy.check(AdmissionMode::Token, &holder, now).is_ok());
+        // The token is pinned to its PeerId and stops working when it expires
+        assert!(policy.check(AdmissionMode::Token, &peer_id(), now).is_err());
+        assert!(policy
+            .check(
+                AdmissionMode::Token,
+                &holder,
+                now + Duration::from_secs(601)
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_tokens_are_refused() {
+        let issuer = ed25519::Keypair::generate();
+        let (policy, _behaviour) = AdmissionPolicy::new(
+            AdmissionMode::Token,
+            AdmissionMode::Open,
+            &[],
+            &[issuer_key(&issuer)],
+            TTL,
+        )
+        .unwrap();
+        let now = SystemTime::now();
+        let peer = peer_id();
+
+        let forged = sign_token(&ed25519::Keypair::generate(), &peer, now + TTL);
+        assert_eq!(
+            policy.submit_token(&forged, now),
+            Err(TokenError::BadSignature)
+        );
+        let expired = sign_token(&issuer, &peer, now - Duration::from_secs(1));
+        assert_eq!(policy.submit_token(&expired, now), Err(TokenError::Expired));
+        let long_lived = sign_token(&issuer, &peer, now + 2 * TTL);
+        assert_eq!(
+            policy.submit_token(&long_lived, now),
+            Err(TokenError::TooLongLived(TTL))
+        );
+        assert_eq!(
+            policy.submit_token("garbage", now),
+            Err(TokenError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Command-line interface for the relay binary.
+//!
+//! Every setting that used to be read straight from the environment in `main()`
+//! has a matching flag here. Flags take precedence over environment variables,
+//! which in turn take precedence over built-in defaults (clap's `env` support
+//! handles that ordering for us).
+
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use ipnet::IpNet;
+
+// Environment variable names, kept identical to the TypeScript relay (`src/relai.ts`)
+/// Domain name the relay is reachable on
+pub const DOMAIN_ENV: &str = "DOMAINE";
+/// Comma-separated list of bootstrap multiaddrs
+pub const BOOTSTRAP_LIST_ENV: &str = "RELAY_BOOTSTRAP_LIST";
+/// Comma-separated list of pubsub peer discovery topics
+pub const PUBSUB_DISCOVERY_TOPICS_ENV: &str = "RELAY_PUBSUB_PEER_DISCOVERY_TOPICS";
+/// Base64-encoded private key of the relay identity
+pub const PRIVATE_KEY_ENV: &str = "CLEF_PRIVEE_RELAI";
+/// PEM-encoded WebRTC certificate, used instead of the certificate file
+pub const WEBRTC_CERTIFICATE_ENV: &str = "RELAY_WEBRTC_CERTIFICATE";
+/// PEM certificate chain served on `/tls/ws` listeners
+pub const WSS_CERTIFICATE_ENV: &str = "RELAY_WSS_CERTIFICATE";
+/// PEM private key matching the WSS certificate
+pub const WSS_PRIVATE_KEY_ENV: &str = "RELAY_WSS_PRIVATE_KEY";
+/// Comma-separated listen multiaddrs whose connections start with a PROXY protocol header
+pub const PROXY_PROTOCOL_ENV: &str = "RELAY_PROXY_PROTOCOL";
+/// Comma-separated CIDRs allowed to send PROXY protocol headers
+pub const PROXY_PROTOCOL_TRUSTED_ENV: &str = "RELAY_PROXY_PROTOCOL_TRUSTED";
+/// TOML file with PeerId and CIDR allow/deny lists
+pub const ACCESS_LIST_ENV: &str = "RELAY_ACCESS_LIST";
+/// Enables the relay client, reserving on upstream relays when behind a NAT
+pub const RELAY_CLIENT_ENV: &str = "RELAY_CLIENT";
+/// Disables TLS certificate verification (development only)
+pub const DISABLE_CERT_VERIFICATION_ENV: &str = "DISABLE_CERT_VERIFICATION";
+/// Path to a TOML configuration file
+pub const CONFIG_FILE_ENV: &str = "RELAY_CONFIG";
+/// `;`-separated `key=value` configuration overrides
+pub const CONFIG_SET_ENV: &str = "RELAY_CONFIG_SET";
+/// Comma-separated listen multiaddrs; a trailing `!` marks a listener as required
+pub const LISTEN_ENV: &str = "RELAY_LISTEN";
+/// Fixed UDP port for webrtc-direct listeners
+pub const WEBRTC_DIRECT_PORT_ENV: &str = "RELAY_WEBRTC_DIRECT_PORT";
+/// Comma-separated multiaddrs announced instead of the listen addresses
+pub const ANNOUNCE_ENV: &str = "RELAY_ANNOUNCE";
+/// Comma-separated multiaddr prefixes that are never announced
+pub const NO_ANNOUNCE_ENV: &str = "RELAY_NO_ANNOUNCE";
+/// Legacy switch that used to turn `run` into a connection probe
+pub const LEGACY_TEST_RELAY_ENV: &str = "TEST_RELAY";
+
+/// Top-level command line of the `rust-libp2p-relay` binary.
+#[derive(Debug, Parser)]
+#[command(
+    name = "rust-libp2p-relay",
+    version,
+    about = "A libp2p relay implementation in Rust"
+)]
+pub struct Cli {
+    /// Subcommand to execute. Defaults to `run` when omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands understood by the relay binary.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the relay node (default)
+    Run(RunArgs),
+    /// Generate a new Ed25519 identity and print it in the CLEF_PRIVEE_RELAI format
+    Keygen(KeygenArgs),
+    /// Decode a private key and print the identity it corresponds to
+    InspectKey(InspectKeyArgs),
+    /// Try to connect to a remote relay over WSS and report the result
+    Probe(ProbeArgs),
+    /// Print the effective configuration after applying flags and environment
+    PrintConfig(RunArgs),
+}
+
+impl Cli {
+    /// Returns the subcommand to run, falling back to `run` with its
+    /// environment-derived defaults when no subcommand was given.
+    pub fn command_or_default(self) -> Command {
+        self.command
+            .unwrap_or_else(|| Command::Run(RunArgs::parse_from_env()))
+    }
+}
+
+/// Settings for running the relay node.
+#[derive(Debug, Clone, Default, Args)]
+pub struct RunArgs {
+    /// TOML configuration file; flags and environment variables override its values
+    #[arg(long, short = 'c', env = CONFIG_FILE_ENV)]
+    pub config: Option<PathBuf>,
+
+    /// Override a single configuration value, e.g. `--set relay.max_circuits=64` (repeatable)
+    #[arg(long = "set", value_name = "KEY=VALUE", env = CONFIG_SET_ENV, value_delimiter = ';')]
+    pub set: Vec<String>,
+
+    /// Domain name the relay is reachable on, used for announced addresses
+    #[arg(long, env = DOMAIN_ENV)]
+    pub domain: Option<String>,
+
+    /// Bootstrap peers to dial on startup (comma-separated multiaddrs)
+    #[arg(long = "bootstrap", env = BOOTSTRAP_LIST_ENV, value_delimiter = ',')]
+    pub bootstrap_list: Vec<String>,
+
+    /// Pubsub peer discovery topics (comma-separated)
+    #[arg(long = "discovery-topics", env = PUBSUB_DISCOVERY_TOPICS_ENV, value_delimiter = ',')]
+    pub pubsub_discovery_topics: Vec<String>,
+
+    /// Base64-encoded private key; a new one is generated and saved to .env when absent
+    #[arg(long, env = PRIVATE_KEY_ENV, hide_env_values = true)]
+    pub private_key: Option<String>,
+
+    /// PEM-encoded WebRTC certificate; otherwise it is loaded from (or created in) `webrtc.certificate_file`
+    #[arg(long, env = WEBRTC_CERTIFICATE_ENV, hide_env_values = true)]
+    pub webrtc_certificate: Option<String>,
+
+    /// PEM certificate chain for `/tls/ws` listeners (e.g. certbot's fullchain.pem); reloaded when it changes
+    #[arg(long, env = WSS_CERTIFICATE_ENV)]
+    pub wss_certificate: Option<PathBuf>,
+
+    /// PEM private key for `/tls/ws` listeners (e.g. certbot's privkey.pem)
+    #[arg(long, env = WSS_PRIVATE_KEY_ENV)]
+    pub wss_private_key: Option<PathBuf>,
+
+    /// Listen addresses behind a proxy sending PROXY protocol v1/v2 headers (comma-separated multiaddrs)
+    #[arg(long, env = PROXY_PROTOCOL_ENV, value_delimiter = ',')]
+    pub proxy_protocol: Vec<String>,
+
+    /// Source networks trusted to send PROXY protocol headers (comma-separated CIDRs)
+    #[arg(long, env = PROXY_PROTOCOL_TRUSTED_ENV, value_delimiter = ',')]
+    pub proxy_protocol_trusted: Vec<IpNet>,
+
+    /// PeerId/CIDR allow and deny lists (TOML), re-read when the file changes
+    #[arg(long, env = ACCESS_LIST_ENV)]
+    pub access_list: Option<PathBuf>,
+
+    /// Addresses to listen on (comma-separated multiaddrs); append `!` to make a listener required
+    #[arg(long, env = LISTEN_ENV, value_delimiter = ',')]
+    pub listen: Vec<String>,
+
+    /// Fixed UDP port for webrtc-direct, so the advertised address is predictable behind a firewall
+    #[arg(long, env = WEBRTC_DIRECT_PORT_ENV)]
+    pub webrtc_direct_port: Option<u16>,
+
+    /// Addresses to announce instead of the listen addresses (comma-separated multiaddrs)
+    #[arg(long, env = ANNOUNCE_ENV, value_delimiter = ',')]
+    pub announce: Vec<String>,
+
+    /// Address prefixes never to announce (comma-separated multiaddrs)
+    #[arg(long, env = NO_ANNOUNCE_ENV, value_delimiter = ',')]
+    pub no_announce: Vec<String>,
+
+    /// Disable TLS certificate verification (INSECURE, development only); `false` overrides the configuration file
+    #[arg(
+        long,
+        env = DISABLE_CERT_VERIFICATION_ENV,
+        num_args = 0..=1,
+        default_missing_value = "true",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    pub disable_cert_verification: Option<bool>,
+
+    /// Reserve on the bootstrap relays (or `relay_client.relays`) when AutoNAT reports a private address; `false` overrides the configuration file
+    #[arg(
+        long,
+        env = RELAY_CLIENT_ENV,
+        num_args = 0..=1,
+        default_missing_value = "true",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    pub relay_client: Option<bool>,
+}
+
+impl RunArgs {
+    /// Builds [`RunArgs`] from environment variables only, as if `run` had been
+    /// given without any flags.
+    pub fn parse_from_env() -> Self {
+        #[derive(Parser)]
+        struct RunOnly {
+            #[command(flatten)]
+            args: RunArgs,
+        }
+        RunOnly::parse_from(["rust-libp2p-relay"]).args
+    }
+}
+
+/// Settings for `keygen`.
+#[derive(Debug, Clone, Args)]
+pub struct KeygenArgs {
+    /// Append the generated key to the .env file instead of only printing it
+    #[arg(long)]
+    pub write_env: bool,
+}
+
+/// Settings for `inspect-key`.
+#[derive(Debug, Clone, Args)]
+pub struct InspectKeyArgs {
+    /// Base64-encoded private key to inspect
+    #[arg(long = "key", env = PRIVATE_KEY_ENV, hide_env_values = true)]
+    pub private_key: String,
+}
+
+/// Settings for `probe`.
+#[derive(Debug, Clone, Args)]
+pub struct ProbeArgs {
+    /// Hostname of the relay to connect to (dialed as /dns4/<relay>/tcp/443/wss)
+    pub relay: String,
+
+    /// Seconds to wait for the connection before giving up
+    #[arg(long, default_value_t = 10)]
+    pub timeout_secs: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn test_flags_take_precedence_over_env() {
+        std::env::set_var(DOMAIN_ENV, "env.example.com");
+        std::env::set_var(
+            BOOTSTRAP_LIST_ENV,
+            "/dns4/a/tcp/443/wss,/dns4/b/tcp/443/wss",
+        );
+
+        let cli = Cli::parse_from(["rust-libp2p-relay", "run", "--domain", "flag.example.com"]);
+        let Some(Command::Run(args)) = cli.command else {
+            panic!("expected run subcommand");
+        };
+        assert_eq!(args.domain.as_deref(), Some("flag.example.com"));
+        assert_eq!(args.bootstrap_list.len(), 2);
+
+        std::env::remove_var(DOMAIN_ENV);
+        std::env::remove_var(BOOTSTRAP_LIST_ENV);
+    }
+
+    #[test]
+    #[serial]
+    fn test_missing_subcommand_defaults_to_run() {
+        std::env::set_var(PUBSUB_DISCOVERY_TOPICS_ENV, "a,b");
+
+        let cli = Cli::parse_from(["rust-libp2p-relay"]);
+        let Command::Run(args) = cli.command_or_default() else {
+            panic!("expected run subcommand");
+        };
+        assert_eq!(
+            args.pubsub_discovery_topics,
+            vec!["a".to_string(), "b".to_string()]
+        );
+
+        std::env::remove_var(PUBSUB_DISCOVERY_TOPICS_ENV);
+    }
+
+    #[test]
+    #[serial]
+    fn test_boolish_cert_verification_values() {
+        for value in ["TRUE", "1", "yes"] {
+            std::env::set_var(DISABLE_CERT_VERIFICATION_ENV, value);
+            assert_eq!(
+                RunArgs::parse_from_env().disable_cert_verification,
+                Some(true)
+            );
+        }
+        std::env::set_var(DISABLE_CERT_VERIFICATION_ENV, "0");
+        assert_eq!(
+            RunArgs::parse_from_env().disable_cert_verification,
+            Some(false)
+        );
+        std::env::remove_var(DISABLE_CERT_VERIFICATION_ENV);
+
+        let cli = Cli::parse_from(["rust-libp2p-relay", "run", "--disable-cert-verification"]);
+        let Some(Command::Run(args)) = cli.command else {
+            panic!("expected run subcommand");
+        };
+        assert_eq!(args.disable_cert_verification, Some(true));
+        assert_eq!(RunArgs::parse_from_env().disable_cert_verification, None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_boolish_relay_client_values() {
+        std::env::set_var(RELAY_CLIENT_ENV, "TRUE");
+        assert_eq!(RunArgs::parse_from_env().relay_client, Some(true));
+        std::env::set_var(RELAY_CLIENT_ENV, "false");
+        assert_eq!(RunArgs::parse_from_env().relay_client, Some(false));
+        std::env::remove_var(RELAY_CLIENT_ENV);
+
+        let cli = Cli::parse_from(["rust-libp2p-relay", "run", "--relay-client=0"]);
+        let Some(Command::Run(args)) = cli.command else {
+            panic!("expected run subcommand");
+        };
+        assert_eq!(args.relay_client, Some(false));
+        assert_eq!(RunArgs::parse_from_env().relay_client, None);
+    }
+}
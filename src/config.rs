@@ -0,0 +1,2138 @@
+//! Typed relay configuration.
+//!
+//! Settings are layered, later layers winning:
+//! 1. built-in defaults (the values `build_swarm` used to hardcode),
+//! 2. the TOML file given with `--config` / `RELAY_CONFIG`,
+//! 3. `--set key=value` overrides (also `RELAY_CONFIG_SET`, `;`-separated),
+//! 4. dedicated flags and their environment variables (`--domain` / `DOMAINE`, ...).
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+use libp2p::gossipsub::{PeerScoreParams, PeerScoreThresholds, TopicHash, TopicScoreParams};
+use libp2p::{identity::Keypair, multiaddr::Protocol, relay, yamux, Multiaddr};
+use serde::{Deserialize, Serialize};
+
+use crate::address_book::AddressBook;
+use crate::admission::{self, AdmissionError, AdmissionMode, AdmissionPolicy};
+use crate::cli::RunArgs;
+use crate::discovery_codec::DiscoveryFormat;
+use crate::discovery_schedule::DiscoverySchedule;
+use crate::limit_classes::{ClassLimits, LimitClassError, LimitClasses};
+use crate::message_validation::{
+    DiscoveryPeers, JsonSchema, MaxSize, MessageValidation, MessageValidationError,
+    SourceRateLimit, TopicSelector,
+};
+use crate::muxer::MuxerUpgrade;
+use crate::proxy_protocol::ProxyProtocolPolicy;
+use crate::security::{SecurityError, SecurityProtocol, SecurityUpgrade};
+use crate::topic_hashing::TopicHashing;
+use crate::topic_policy::{MirrorMode, TopicMatcher, TopicPattern, TopicPolicy, TopicPolicyError};
+use crate::webrtc_certificate::RotationSchedule;
+
+// Relay (circuit relay v2 server) defaults
+/// Maximum number of simultaneous circuits across all peers
+pub const DEFAULT_MAX_CIRCUITS: usize = 32;
+/// Lifetime of a reservation before the client has to renew it
+pub const DEFAULT_RESERVATION_DURATION_SECS: u64 = 60 * 60;
+/// Maximum lifetime of a single relayed circuit
+pub const DEFAULT_MAX_CIRCUIT_DURATION_SECS: u64 = 2 * 60;
+/// Maximum bytes relayed per circuit, 1 MiB to match the TypeScript relay's maxMessageSize
+pub const DEFAULT_MAX_CIRCUIT_BYTES: u64 = 1024 * 1024;
+/// Reservations a single peer may hold at once
+pub const DEFAULT_MAX_RESERVATIONS_PER_PEER: usize = 1;
+/// Circuits a single peer may have open at once
+pub const DEFAULT_MAX_CIRCUITS_PER_PEER: usize = 16;
+/// Maximum bytes relayed per circuit in the `trusted` limit class
+pub const DEFAULT_TRUSTED_MAX_CIRCUIT_BYTES: u64 = 64 * 1024 * 1024;
+/// Maximum circuit lifetime in the `trusted` limit class
+pub const DEFAULT_TRUSTED_MAX_CIRCUIT_DURATION_SECS: u64 = 60 * 60;
+/// Circuits a `trusted` peer may have open at once
+pub const DEFAULT_TRUSTED_MAX_CIRCUITS_PER_PEER: usize = 64;
+/// Reservations a `trusted` peer may hold at once
+pub const DEFAULT_TRUSTED_MAX_RESERVATIONS_PER_PEER: usize = 4;
+
+// Gossipsub defaults, aligned with the js-libp2p gossipsub defaults
+/// Largest message gossipsub will transmit (matches TS 1e6 approx)
+pub const DEFAULT_GOSSIPSUB_MAX_TRANSMIT_SIZE_BYTES: usize = 1024 * 1024;
+/// Target mesh degree (TS `D`)
+pub const DEFAULT_GOSSIPSUB_MESH_N: usize = 8;
+/// Time to live of fanout state (TS `fanoutTTL`)
+pub const DEFAULT_GOSSIPSUB_FANOUT_TTL_SECS: u64 = 60;
+/// Number of peers to gossip to (TS `Dlazy`)
+pub const DEFAULT_GOSSIPSUB_GOSSIP_LAZY: usize = 6;
+/// Fraction of peers to gossip to (TS `gossipFactor`)
+pub const DEFAULT_GOSSIPSUB_GOSSIP_FACTOR: f64 = 0.25;
+/// Score below which no gossip is exchanged with a peer (TS `gossipThreshold`)
+pub const DEFAULT_GOSSIPSUB_GOSSIP_THRESHOLD: f64 = -10.0;
+/// Score below which our own messages are not published to a peer (TS `publishThreshold`)
+pub const DEFAULT_GOSSIPSUB_PUBLISH_THRESHOLD: f64 = -50.0;
+/// Score below which everything a peer sends is ignored (TS `graylistThreshold`)
+pub const DEFAULT_GOSSIPSUB_GRAYLIST_THRESHOLD: f64 = -80.0;
+
+// Transport defaults
+/// Yamux per-stream receive window
+pub const DEFAULT_YAMUX_RECEIVE_WINDOW_BYTES: u32 = 16 * 1024 * 1024;
+/// Yamux streams per connection (the libp2p-yamux default)
+pub const DEFAULT_YAMUX_MAX_NUM_STREAMS: usize = 512;
+/// Mplex substreams per connection (the libp2p-mplex default)
+pub const DEFAULT_MPLEX_MAX_NUM_STREAMS: usize = 128;
+/// Frames buffered per mplex substream before `max_buffer_behaviour` applies
+pub const DEFAULT_MPLEX_MAX_BUFFER_FRAMES: usize = 32;
+/// Largest mplex frame payload sent at once; js-libp2p's mplex uses the same limit
+pub const DEFAULT_MPLEX_SPLIT_SEND_SIZE_BYTES: usize = 8 * 1024;
+/// Timeout for the security and multiplexer upgrade of a connection
+pub const DEFAULT_TRANSPORT_TIMEOUT_SECS: u64 = 20;
+/// Security protocols offered on TCP and WebSocket; Noise first as js-libp2p peers expect it
+pub const DEFAULT_SECURITY_PROTOCOLS: &[SecurityProtocol] =
+    &[SecurityProtocol::Noise, SecurityProtocol::Tls];
+
+// Swarm defaults
+/// Close connections that have had no active streams for this long
+pub const DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS: u64 = 60;
+/// Inbound streams allowed to negotiate concurrently on one connection
+pub const DEFAULT_MAX_NEGOTIATING_INBOUND_STREAMS: usize = 10_000;
+
+// Listen defaults
+/// Listeners started when no `listen` addresses are configured, with whether each is required.
+/// TCP and the WebSocket port nginx proxies to are required, as `main()` always treated them.
+pub const DEFAULT_LISTEN_ADDRESSES: &[(&str, bool)] = &[
+    ("/ip4/0.0.0.0/tcp/0", true),
+    ("/ip4/0.0.0.0/tcp/12345/ws", true),
+    ("/ip4/0.0.0.0/udp/0/webrtc-direct", false),
+    ("/ip4/0.0.0.0/udp/443/quic-v1", false),
+    ("/ip4/0.0.0.0/udp/443/webtransport", false),
+    // Generic shorthand transports (like JS defaults)
+    ("/webrtc", false),
+    ("/webtransport", false),
+    // No bare `/p2p-circuit`: it fails without a relay peer, and the relay client
+    // listens on `<relay>/p2p-circuit` itself
+];
+/// Suffix marking a `--listen` / `RELAY_LISTEN` entry as required
+pub const REQUIRED_LISTEN_SUFFIX: char = '!';
+
+// Announce defaults
+/// Addresses announced when a domain is set but no `announce` list is configured,
+/// the same pair `src/relai.ts` announces (nginx terminates TLS on 443)
+pub const DEFAULT_DOMAIN_ANNOUNCE_ADDRESSES: &[&str] =
+    &["/dns4/{domain}/tcp/443/wss", "/dns4/{domain}/tcp/80/ws"];
+
+// WebRTC certificate defaults
+/// File the WebRTC certificate is persisted in, relative to the working directory
+pub const DEFAULT_WEBRTC_CERTIFICATE_FILE: &str = "webrtc-certificate.toml";
+/// How long before a rotation the successor's certhash is advertised alongside the current one
+pub const DEFAULT_WEBRTC_ROTATION_OVERLAP_SECS: u64 = 7 * 24 * 60 * 60;
+
+// WSS defaults
+/// How often the WSS certificate and key files are checked for changes
+pub const DEFAULT_WSS_RELOAD_INTERVAL_SECS: u64 = 60;
+
+// PROXY protocol defaults
+/// Sources trusted to send PROXY headers: a proxy on the same host
+pub const DEFAULT_PROXY_PROTOCOL_TRUSTED_SOURCES: &[&str] = &["127.0.0.1/32", "::1/128"];
+/// How long a trusted source has to send the PROXY header
+pub const DEFAULT_PROXY_PROTOCOL_HEADER_TIMEOUT_SECS: u64 = 5;
+
+// Connection limit defaults
+/// Connections still in their transport upgrade, inbound
+pub const DEFAULT_MAX_PENDING_INCOMING: u32 = 256;
+/// Dials in progress
+pub const DEFAULT_MAX_PENDING_OUTGOING: u32 = 256;
+/// Established connections in total
+pub const DEFAULT_MAX_ESTABLISHED: u32 = 4096;
+/// Established connections with a single peer
+pub const DEFAULT_MAX_ESTABLISHED_PER_PEER: u32 = 8;
+/// Inbound connections in their transport upgrade from one source IP or subnet
+pub const DEFAULT_MAX_PENDING_PER_IP: u32 = 16;
+/// Established inbound connections from one source IP or subnet
+pub const DEFAULT_MAX_ESTABLISHED_PER_IP: u32 = 64;
+/// IPv4 sources are grouped by this prefix length for the per-IP limits
+pub const DEFAULT_IPV4_PREFIX_LEN: u8 = 32;
+/// IPv6 sources are grouped by this prefix length (one customer allocation)
+pub const DEFAULT_IPV6_PREFIX_LEN: u8 = 64;
+/// Sources exempt from the per-IP limits: a proxy on the same host
+pub const DEFAULT_CONNECTION_LIMITS_EXEMPT_SOURCES: &[&str] = &["127.0.0.1/32", "::1/128"];
+
+// Admission defaults
+/// Longest validity an access token may have, so leaked tokens expire quickly
+pub const DEFAULT_MAX_TOKEN_TTL_SECS: u64 = 15 * 60;
+
+// Mirror defaults
+/// How long a mirrored topic is kept once no connected peer is subscribed to it
+pub const DEFAULT_MIRROR_UNSUBSCRIBE_GRACE_SECS: u64 = 5 * 60;
+/// Topics mirrored at once before the least recently used one is evicted
+pub const DEFAULT_MIRROR_MAX_TOPICS: usize = 1024;
+
+// Message validation defaults
+/// Window over which `rate_limit_messages` are counted
+pub const DEFAULT_VALIDATION_RATE_LIMIT_WINDOW_SECS: u64 = 1;
+
+// Peer discovery defaults
+/// Topics announcements are read from and published to: Constellation's and Orbiter's
+pub const DEFAULT_PUBSUB_DISCOVERY_TOPICS: [&str; 3] = [
+    "constellation._peer-discovery._p2p._pubsub",
+    "orbiter._peer-discovery._p2p._pubsub",
+    "orbiter._content-discovery._p2p._pubsub",
+];
+/// Seconds between announcements on a discovery topic
+pub const DEFAULT_DISCOVERY_INTERVAL_SECS: u64 = 60;
+/// Random delay added to each announcement interval, in seconds
+pub const DEFAULT_DISCOVERY_JITTER_SECS: u64 = 5;
+
+// Address book defaults
+/// Peers whose addresses are remembered
+pub const DEFAULT_ADDRESS_BOOK_MAX_PEERS: usize = 1024;
+/// Addresses remembered per peer
+pub const DEFAULT_ADDRESS_BOOK_MAX_ADDRS_PER_PEER: usize = 8;
+/// How long an address is remembered once last announced or dialed
+pub const DEFAULT_ADDRESS_BOOK_TTL_SECS: u64 = 60 * 60;
+/// Failed dials in a row after which an address is forgotten
+pub const DEFAULT_ADDRESS_BOOK_MAX_DIAL_FAILURES: u32 = 3;
+
+// Admin defaults
+/// Address of the admin HTTP server: loopback only, unlike the public web server on port 8000
+pub const DEFAULT_ADMIN_LISTEN: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8001);
+
+// Identify defaults
+/// Interval between periodic identify pushes
+pub const DEFAULT_IDENTIFY_INTERVAL_SECS: u64 = 600;
+
+/// Errors that can occur while assembling a [`RelayConfig`].
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read config file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid override '{entry}': {source}")]
+    InvalidOverride {
+        entry: String,
+        source: toml::de::Error,
+    },
+    #[error("invalid configuration: {0}")]
+    Invalid(#[from] toml::de::Error),
+    #[error("at least one of transport.yamux and transport.mplex must be enabled")]
+    NoMultiplexer,
+    #[error("transport.security must list at least one security protocol")]
+    NoSecurityProtocol,
+    #[error("wss.certificate_file and wss.private_key_file must be set together")]
+    IncompleteWss,
+    #[error("listen address '{address}' needs wss.certificate_file and wss.private_key_file")]
+    WssWithoutCertificate { address: Multiaddr },
+    #[error("invalid relay limit classes: {0}")]
+    LimitClass(#[from] LimitClassError),
+    #[error("invalid admission policy: {0}")]
+    Admission(#[from] AdmissionError),
+    #[error("invalid mirror policy: {0}")]
+    TopicPolicy(#[from] TopicPolicyError),
+    #[error("invalid gossipsub.peer_score: {0}")]
+    PeerScore(String),
+    #[error("invalid message validation: {0}")]
+    MessageValidation(#[from] MessageValidationError),
+    #[error("invalid discovery: {0}")]
+    Discovery(String),
+    #[error("invalid {field} address '{address}': {source}")]
+    InvalidAddress {
+        field: &'static str,
+        address: String,
+        source: libp2p::multiaddr::Error,
+    },
+}
+
+/// Complete configuration of a relay node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Domain name the relay is reachable on
+    pub domain: Option<String>,
+    /// Bootstrap multiaddrs dialed on startup
+    pub bootstrap_list: Vec<String>,
+    /// Pubsub peer discovery topics, where announcements are read and published
+    pub pubsub_discovery_topics: Vec<String>,
+    /// Disable TLS certificate verification (development only)
+    pub disable_cert_verification: bool,
+    /// Circuit relay server limits
+    pub relay: RelayLimitsConfig,
+    /// Gossipsub tuning
+    pub gossipsub: GossipsubConfig,
+    /// Transport upgrade tuning
+    pub transport: TransportConfig,
+    /// Swarm connection handling
+    pub swarm: SwarmConfig,
+    /// Identify protocol settings
+    pub identify: IdentifyConfig,
+    /// Addresses to listen on and per-transport toggles
+    pub listen: ListenConfig,
+    /// Addresses advertised instead of the listen addresses; see [`RelayConfig::announce_addresses`]
+    pub announce: Vec<String>,
+    /// Address prefixes that are never advertised
+    pub no_announce: Vec<String>,
+    /// WebRTC certificate persistence and rotation
+    pub webrtc: WebRtcConfig,
+    /// TLS termination for `/tls/ws` listeners
+    pub wss: WssConfig,
+    /// PROXY protocol on listeners behind a proxy
+    pub proxy_protocol: ProxyProtocolConfig,
+    /// Connection limits: total, per peer and per source IP
+    pub connection_limits: ConnectionLimitsConfig,
+    /// PeerId and CIDR allow/deny lists
+    pub access_list: AccessListConfig,
+    /// Who may make reservations and open circuits
+    pub admission: AdmissionConfig,
+    /// Reservations on upstream relays while behind a NAT
+    pub relay_client: RelayClientConfig,
+    /// Topics the relay subscribes to on behalf of its peers
+    pub mirror: MirrorConfig,
+    /// Checks on gossipsub messages before they are forwarded
+    pub validation: ValidationConfig,
+    /// Peer discovery announcements on the discovery topics
+    pub discovery: DiscoveryConfig,
+    /// Addresses remembered for discovered and dialed peers
+    pub address_book: AddressBookConfig,
+    /// HTTP server for support staff
+    pub admin: AdminConfig,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            domain: None,
+            bootstrap_list: Vec::new(),
+            pubsub_discovery_topics: DEFAULT_PUBSUB_DISCOVERY_TOPICS
+                .iter()
+                .map(|topic| topic.to_string())
+                .collect(),
+            disable_cert_verification: false,
+            relay: RelayLimitsConfig::default(),
+            gossipsub: GossipsubConfig::default(),
+            transport: TransportConfig::default(),
+            swarm: SwarmConfig::default(),
+            identify: IdentifyConfig::default(),
+            listen: ListenConfig::default(),
+            announce: Vec::new(),
+            no_announce: Vec::new(),
+            webrtc: WebRtcConfig::default(),
+            wss: WssConfig::default(),
+            proxy_protocol: ProxyProtocolConfig::default(),
+            connection_limits: ConnectionLimitsConfig::default(),
+            access_list: AccessListConfig::default(),
+            admission: AdmissionConfig::default(),
+            relay_client: RelayClientConfig::default(),
+            mirror: MirrorConfig::default(),
+            validation: ValidationConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            address_book: AddressBookConfig::default(),
+            admin: AdminConfig::default(),
+        }
+    }
+}
+
+/// Limits of the circuit relay v2 server, mirrored from [`relay::Config`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayLimitsConfig {
+    /// Maximum number of simultaneous circuits
+    pub max_circuits: usize,
+    /// Maximum number of reservations; unlimited when unset (like TypeScript's `Infinity`)
+    pub max_reservations: Option<usize>,
+    /// Reservation lifetime in seconds
+    pub reservation_duration_secs: u64,
+    /// Maximum circuit lifetime in seconds
+    pub max_circuit_duration_secs: u64,
+    /// Maximum bytes relayed per circuit
+    pub max_circuit_bytes: u64,
+    /// Reservations a single peer may hold
+    pub max_reservations_per_peer: usize,
+    /// Circuits a single peer may have open
+    pub max_circuits_per_peer: usize,
+    /// Limit classes for listed peers; the limits above form the `default` class
+    pub classes: BTreeMap<String, LimitClassConfig>,
+}
+
+impl Default for RelayLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_circuits: DEFAULT_MAX_CIRCUITS,
+            max_reservations: None,
+            reservation_duration_secs: DEFAULT_RESERVATION_DURATION_SECS,
+            max_circuit_duration_secs: DEFAULT_MAX_CIRCUIT_DURATION_SECS,
+            max_circuit_bytes: DEFAULT_MAX_CIRCUIT_BYTES,
+            max_reservations_per_peer: DEFAULT_MAX_RESERVATIONS_PER_PEER,
+            max_circuits_per_peer: DEFAULT_MAX_CIRCUITS_PER_PEER,
+            classes: BTreeMap::from([
+                (
+                    "trusted".to_string(),
+                    LimitClassConfig {
+                        peers: Vec::new(),
+                        max_circuit_bytes: DEFAULT_TRUSTED_MAX_CIRCUIT_BYTES,
+                        max_circuit_duration_secs: DEFAULT_TRUSTED_MAX_CIRCUIT_DURATION_SECS,
+                        max_circuits_per_peer: DEFAULT_TRUSTED_MAX_CIRCUITS_PER_PEER,
+                        max_reservations_per_peer: DEFAULT_TRUSTED_MAX_RESERVATIONS_PER_PEER,
+                    },
+                ),
+                ("unlimited".to_string(), LimitClassConfig::default()),
+            ]),
+        }
+    }
+}
+
+impl RelayLimitsConfig {
+    /// Builds the libp2p relay server configuration from these limits.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::LimitClass`] if a limit class is invalid.
+    pub fn to_relay_config(&self) -> Result<relay::Config, ConfigError> {
+        let limit_classes = self.limit_classes()?;
+        Ok(relay::Config {
+            max_circuits: self.max_circuits,
+            max_reservations: self.max_reservations.unwrap_or(usize::MAX),
+            reservation_duration: Duration::from_secs(self.reservation_duration_secs),
+            max_circuit_duration: Duration::from_secs(self.max_circuit_duration_secs),
+            max_circuit_bytes: self.max_circuit_bytes,
+            max_reservations_per_peer: self.max_reservations_per_peer,
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            circuit_src_rate_limiters: Default::default(),
+            reservation_rate_limiters: Default::default(),
+            limit_selector: (!limit_classes.is_empty())
+                .then(|| Box::new(limit_classes) as Box<dyn relay::LimitSelector>),
+        })
+    }
+
+    /// The limit classes, with the limits above as the `default` class.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::LimitClass`] if a class is named `default`, a
+    /// PeerId does not parse or a peer is listed in two classes.
+    pub fn limit_classes(&self) -> Result<LimitClasses, ConfigError> {
+        let default = ClassLimits {
+            max_circuit_bytes: self.max_circuit_bytes,
+            max_circuit_duration: Duration::from_secs(self.max_circuit_duration_secs),
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            max_reservations_per_peer: self.max_reservations_per_peer,
+        };
+        Ok(LimitClasses::new(
+            Duration::from_secs(self.reservation_duration_secs),
+            default,
+            self.classes
+                .iter()
+                .map(|(name, class)| (name.as_str(), class.limits(), class.peers.as_slice())),
+        )?)
+    }
+}
+
+/// A relay limit class and the peers in it; see [`crate::limit_classes`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitClassConfig {
+    /// PeerIds in this class
+    pub peers: Vec<String>,
+    /// Maximum bytes relayed per circuit, 0 for unlimited
+    pub max_circuit_bytes: u64,
+    /// Maximum circuit lifetime in seconds, 0 for unlimited
+    pub max_circuit_duration_secs: u64,
+    /// Circuits a single peer may have open, 0 for unlimited
+    pub max_circuits_per_peer: usize,
+    /// Reservations a single peer may hold, 0 for unlimited
+    pub max_reservations_per_peer: usize,
+}
+
+impl LimitClassConfig {
+    fn limits(&self) -> ClassLimits {
+        ClassLimits {
+            max_circuit_bytes: self.max_circuit_bytes,
+            max_circuit_duration: Duration::from_secs(self.max_circuit_duration_secs),
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            max_reservations_per_peer: self.max_reservations_per_peer,
+        }
+    }
+}
+
+/// Gossipsub parameters exposed for tuning.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GossipsubConfig {
+    /// Largest message that will be transmitted, in bytes
+    pub max_transmit_size: usize,
+    /// Target mesh degree
+    pub mesh_n: usize,
+    /// Fanout state time to live, in seconds
+    pub fanout_ttl_secs: u64,
+    /// Number of peers to emit gossip to
+    pub gossip_lazy: usize,
+    /// Fraction of peers to emit gossip to
+    pub gossip_factor: f64,
+    /// Peer scoring
+    pub peer_score: PeerScoreConfig,
+    /// How topic names are hashed: `identity` like js-libp2p, or `sha256`
+    pub topic_hashing: TopicHashing,
+    /// Topics joined under both hash forms, messages being republished from one to the other
+    pub bridge_topics: Vec<String>,
+}
+
+impl Default for GossipsubConfig {
+    fn default() -> Self {
+        Self {
+            max_transmit_size: DEFAULT_GOSSIPSUB_MAX_TRANSMIT_SIZE_BYTES,
+            mesh_n: DEFAULT_GOSSIPSUB_MESH_N,
+            fanout_ttl_secs: DEFAULT_GOSSIPSUB_FANOUT_TTL_SECS,
+            gossip_lazy: DEFAULT_GOSSIPSUB_GOSSIP_LAZY,
+            gossip_factor: DEFAULT_GOSSIPSUB_GOSSIP_FACTOR,
+            peer_score: PeerScoreConfig::default(),
+            topic_hashing: TopicHashing::default(),
+            bridge_topics: Vec::new(),
+        }
+    }
+}
+
+/// Gossipsub peer scoring; the scores are logged per peer on the status tick.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerScoreConfig {
+    /// Score peers, ignoring or pruning those below the thresholds
+    pub enabled: bool,
+    /// Score below which no gossip is exchanged with a peer
+    pub gossip_threshold: f64,
+    /// Score below which our own messages are not published to a peer
+    pub publish_threshold: f64,
+    /// Score below which everything a peer sends is ignored
+    pub graylist_threshold: f64,
+    /// Penalty for many peers sharing an IP; 0 as browsers behind one NAT are common
+    pub ip_colocation_factor_weight: f64,
+    /// Score parameters by topic name; the relay's own topics default to [`TopicScoreConfig::default`]
+    pub topics: BTreeMap<String, TopicScoreConfig>,
+}
+
+impl Default for PeerScoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            gossip_threshold: DEFAULT_GOSSIPSUB_GOSSIP_THRESHOLD,
+            publish_threshold: DEFAULT_GOSSIPSUB_PUBLISH_THRESHOLD,
+            graylist_threshold: DEFAULT_GOSSIPSUB_GRAYLIST_THRESHOLD,
+            ip_colocation_factor_weight: 0.0,
+            topics: BTreeMap::new(),
+        }
+    }
+}
+
+/// Score weights of one topic, see the gossipsub v1.1 specification.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopicScoreConfig {
+    /// Weight of this topic in the total score
+    pub topic_weight: f64,
+    /// Reward for time spent in our mesh (P1)
+    pub time_in_mesh_weight: f64,
+    /// Reward for delivering messages first (P2)
+    pub first_message_deliveries_weight: f64,
+    /// Penalty for mesh peers delivering too few messages (P3), negative or 0
+    pub mesh_message_deliveries_weight: f64,
+    /// Penalty for messages rejected by validation (P4), negative or 0
+    pub invalid_message_deliveries_weight: f64,
+}
+
+impl Default for TopicScoreConfig {
+    fn default() -> Self {
+        let params = TopicScoreParams::default();
+        Self {
+            topic_weight: params.topic_weight,
+            time_in_mesh_weight: params.time_in_mesh_weight,
+            first_message_deliveries_weight: params.first_message_deliveries_weight,
+            // Quiet topics would otherwise penalize every mesh peer
+            mesh_message_deliveries_weight: 0.0,
+            invalid_message_deliveries_weight: params.invalid_message_deliveries_weight,
+        }
+    }
+}
+
+impl TopicScoreConfig {
+    fn to_params(&self) -> TopicScoreParams {
+        TopicScoreParams {
+            topic_weight: self.topic_weight,
+            time_in_mesh_weight: self.time_in_mesh_weight,
+            first_message_deliveries_weight: self.first_message_deliveries_weight,
+            mesh_message_deliveries_weight: self.mesh_message_deliveries_weight,
+            invalid_message_deliveries_weight: self.invalid_message_deliveries_weight,
+            ..TopicScoreParams::default()
+        }
+    }
+}
+
+impl PeerScoreConfig {
+    /// Builds the gossipsub score parameters and thresholds. Topics are hashed
+    /// with `topic_hash`; `default_topics` get the default weights unless configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::PeerScore`] when gossipsub rejects a parameter or threshold.
+    pub fn to_params(
+        &self,
+        topic_hash: impl Fn(&str) -> TopicHash,
+        default_topics: impl IntoIterator<Item = TopicHash>,
+    ) -> Result<(PeerScoreParams, PeerScoreThresholds), ConfigError> {
+        let mut params = PeerScoreParams {
+            ip_colocation_factor_weight: self.ip_colocation_factor_weight,
+            ..Default::default()
+        };
+        for topic in default_topics {
+            params
+                .topics
+                .insert(topic, TopicScoreConfig::default().to_params());
+        }
+        for (name, topic) in &self.topics {
+            params.topics.insert(topic_hash(name), topic.to_params());
+        }
+        params.validate().map_err(ConfigError::PeerScore)?;
+
+        let thresholds = PeerScoreThresholds {
+            gossip_threshold: self.gossip_threshold,
+            publish_threshold: self.publish_threshold,
+            graylist_threshold: self.graylist_threshold,
+            ..Default::default()
+        };
+        thresholds
+            .validate()
+            .map_err(|e| ConfigError::PeerScore(e.to_string()))?;
+        Ok((params, thresholds))
+    }
+}
+
+/// Connection upgrade parameters shared by the TCP and WebSocket transports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportConfig {
+    /// Timeout for the security and multiplexer upgrade, in seconds
+    pub timeout_secs: u64,
+    /// Security protocols offered, in order of preference
+    pub security: Vec<SecurityProtocol>,
+    /// Yamux multiplexer, offered first
+    pub yamux: YamuxConfig,
+    /// Mplex multiplexer, offered as a fallback for older js-libp2p peers
+    pub mplex: MplexConfig,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: DEFAULT_TRANSPORT_TIMEOUT_SECS,
+            security: DEFAULT_SECURITY_PROTOCOLS.to_vec(),
+            yamux: YamuxConfig::default(),
+            mplex: MplexConfig::default(),
+        }
+    }
+}
+
+impl TransportConfig {
+    /// Builds the security upgrade offering the configured protocols in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SecurityError`] if a protocol cannot be set up for `keypair`.
+    pub fn security_upgrade(&self, keypair: &Keypair) -> Result<SecurityUpgrade, SecurityError> {
+        SecurityUpgrade::new(keypair, &self.security)
+    }
+
+    /// Builds the multiplexer upgrade offering every enabled multiplexer.
+    pub fn muxer_upgrade(&self) -> MuxerUpgrade {
+        MuxerUpgrade::new(
+            self.yamux.enabled.then(|| self.yamux.to_yamux_config()),
+            self.mplex.enabled.then(|| self.mplex.to_mplex_config()),
+        )
+    }
+}
+
+/// Yamux limits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct YamuxConfig {
+    /// Offer yamux during multiplexer negotiation
+    pub enabled: bool,
+    /// Per-stream receive window, in bytes
+    pub receive_window_bytes: u32,
+    /// Maximum number of streams per connection
+    pub max_num_streams: usize,
+}
+
+impl Default for YamuxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            receive_window_bytes: DEFAULT_YAMUX_RECEIVE_WINDOW_BYTES,
+            max_num_streams: DEFAULT_YAMUX_MAX_NUM_STREAMS,
+        }
+    }
+}
+
+impl YamuxConfig {
+    /// Builds the libp2p yamux configuration from these limits.
+    pub fn to_yamux_config(&self) -> yamux::Config {
+        let mut config = yamux::Config::default();
+        #[allow(deprecated)] // No per-connection window replacement in libp2p-yamux yet
+        config.set_receive_window_size(self.receive_window_bytes);
+        config.set_max_num_streams(self.max_num_streams);
+        config
+    }
+}
+
+/// Mplex limits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MplexConfig {
+    /// Offer mplex during multiplexer negotiation
+    pub enabled: bool,
+    /// Maximum number of substreams per connection
+    pub max_num_streams: usize,
+    /// Frames buffered per substream
+    pub max_buffer_frames: usize,
+    /// What to do when a substream's buffer is full
+    pub max_buffer_behaviour: MplexBufferBehaviour,
+    /// Largest frame payload sent at once, in bytes
+    pub split_send_size_bytes: usize,
+}
+
+impl Default for MplexConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_num_streams: DEFAULT_MPLEX_MAX_NUM_STREAMS,
+            max_buffer_frames: DEFAULT_MPLEX_MAX_BUFFER_FRAMES,
+            max_buffer_behaviour: MplexBufferBehaviour::Block,
+            split_send_size_bytes: DEFAULT_MPLEX_SPLIT_SEND_SIZE_BYTES,
+        }
+    }
+}
+
+impl MplexConfig {
+    /// Builds the libp2p mplex configuration from these limits.
+    pub fn to_mplex_config(&self) -> libp2p_mplex::Config {
+        let mut config = libp2p_mplex::Config::new();
+        config
+            .set_max_num_streams(self.max_num_streams)
+            .set_max_buffer_size(self.max_buffer_frames)
+            .set_max_buffer_behaviour(match self.max_buffer_behaviour {
+                MplexBufferBehaviour::Block => libp2p_mplex::MaxBufferBehaviour::Block,
+                MplexBufferBehaviour::ResetStream => libp2p_mplex::MaxBufferBehaviour::ResetStream,
+            })
+            .set_split_send_size(self.split_send_size_bytes);
+        config
+    }
+}
+
+/// Mirror of [`libp2p_mplex::MaxBufferBehaviour`] that can be deserialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MplexBufferBehaviour {
+    /// Stop reading from the connection until the full substream is read
+    Block,
+    /// Reset the substream whose buffer overflowed
+    ResetStream,
+}
+
+/// Swarm-level connection handling.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwarmConfig {
+    /// Idle connection timeout, in seconds
+    pub idle_connection_timeout_secs: u64,
+    /// Inbound streams allowed to negotiate concurrently per connection
+    pub max_negotiating_inbound_streams: usize,
+}
+
+impl Default for SwarmConfig {
+    fn default() -> Self {
+        Self {
+            idle_connection_timeout_secs: DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS,
+            max_negotiating_inbound_streams: DEFAULT_MAX_NEGOTIATING_INBOUND_STREAMS,
+        }
+    }
+}
+
+/// Limits on pending and established connections; a limit of 0 is not enforced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionLimitsConfig {
+    /// Inbound connections still in their transport upgrade
+    pub max_pending_incoming: u32,
+    /// Outbound dials in progress
+    pub max_pending_outgoing: u32,
+    /// Established connections in total
+    pub max_established: u32,
+    /// Established inbound connections
+    pub max_established_incoming: u32,
+    /// Established outbound connections
+    pub max_established_outgoing: u32,
+    /// Established connections with a single peer
+    pub max_established_per_peer: u32,
+    /// Pending inbound connections from one source IP or subnet
+    pub max_pending_per_ip: u32,
+    /// Established inbound connections from one source IP or subnet
+    pub max_established_per_ip: u32,
+    /// Prefix length grouping IPv4 sources for the per-IP limits
+    pub ipv4_prefix_len: u8,
+    /// Prefix length grouping IPv6 sources for the per-IP limits
+    pub ipv6_prefix_len: u8,
+    /// Sources not subject to the per-IP limits
+    pub exempt_sources: Vec<IpNet>,
+}
+
+impl Default for ConnectionLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_pending_incoming: DEFAULT_MAX_PENDING_INCOMING,
+            max_pending_outgoing: DEFAULT_MAX_PENDING_OUTGOING,
+            max_established: DEFAULT_MAX_ESTABLISHED,
+            max_established_incoming: 0,
+            max_established_outgoing: 0,
+            max_established_per_peer: DEFAULT_MAX_ESTABLISHED_PER_PEER,
+            max_pending_per_ip: DEFAULT_MAX_PENDING_PER_IP,
+            max_established_per_ip: DEFAULT_MAX_ESTABLISHED_PER_IP,
+            ipv4_prefix_len: DEFAULT_IPV4_PREFIX_LEN,
+            ipv6_prefix_len: DEFAULT_IPV6_PREFIX_LEN,
+            exempt_sources: DEFAULT_CONNECTION_LIMITS_EXEMPT_SOURCES
+                .iter()
+                .map(|net| net.parse().expect("valid default CIDR"))
+                .collect(),
+        }
+    }
+}
+
+/// PeerId and CIDR allow/deny lists; see [`crate::access_list`] for the file format.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessListConfig {
+    /// TOML file with `[[deny]]` and `[[allow]]` entries, re-read when it changes
+    pub file: Option<PathBuf>,
+}
+
+/// Admission of reservations and circuits; see [`crate::admission`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionConfig {
+    /// Who may make reservations
+    pub reservations: AdmissionMode,
+    /// Who may open circuits, judged by the source peer
+    pub circuits: AdmissionMode,
+    /// PeerIds admitted in `allowlist` and `token` mode
+    pub allowlist: Vec<String>,
+    /// Base64 ed25519 public keys whose access tokens are accepted
+    pub token_issuers: Vec<String>,
+    /// Longest validity an accepted token may have, in seconds
+    pub max_token_ttl_secs: u64,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            reservations: AdmissionMode::Open,
+            circuits: AdmissionMode::Open,
+            allowlist: Vec::new(),
+            token_issuers: Vec::new(),
+            max_token_ttl_secs: DEFAULT_MAX_TOKEN_TTL_SECS,
+        }
+    }
+}
+
+impl AdmissionConfig {
+    /// Builds the admission policy and the behaviour reporting its denials.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::Admission`] for an invalid PeerId or issuer key,
+    /// or token mode without an issuer.
+    pub fn to_policy(&self) -> Result<(AdmissionPolicy, admission::Behaviour), ConfigError> {
+        Ok(AdmissionPolicy::new(
+            self.reservations,
+            self.circuits,
+            &self.allowlist,
+            &self.token_issuers,
+            Duration::from_secs(self.max_token_ttl_secs),
+        )?)
+    }
+}
+
+/// Relay client settings; see [`crate::relay_client`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayClientConfig {
+    /// Add the relay client transport and reserve on `relays` when AutoNAT reports a private address
+    pub enabled: bool,
+    /// Relay multiaddrs ending in `/p2p/<PeerId>`; the bootstrap list when empty
+    pub relays: Vec<String>,
+}
+
+/// Subscriptions mirrored from peers; see [`crate::topic_interest`] and [`crate::topic_policy`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MirrorConfig {
+    /// Seconds a topic is kept once no connected peer is subscribed to it
+    pub unsubscribe_grace_secs: u64,
+    /// Mirror every topic not denied, or only topics matching `allow`
+    pub mode: MirrorMode,
+    /// Topics mirrored even when denied, or the only ones in `allowlist` mode
+    pub allow: Vec<TopicPattern>,
+    /// Topics never mirrored
+    pub deny: Vec<TopicPattern>,
+    /// Topics mirrored at once, the least recently used being evicted; 0 for unlimited
+    pub max_topics: usize,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            unsubscribe_grace_secs: DEFAULT_MIRROR_UNSUBSCRIBE_GRACE_SECS,
+            mode: MirrorMode::All,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            max_topics: DEFAULT_MIRROR_MAX_TOPICS,
+        }
+    }
+}
+
+impl MirrorConfig {
+    /// Builds the policy deciding which topics are mirrored.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::TopicPolicy`] if a regex does not compile.
+    pub fn to_policy(&self) -> Result<TopicPolicy, ConfigError> {
+        Ok(TopicPolicy::new(
+            self.mode,
+            &self.allow,
+            &self.deny,
+            self.max_topics,
+        )?)
+    }
+}
+
+/// Message validation; see [`crate::message_validation`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    /// Hold messages until validated; when disabled everything is forwarded
+    pub enabled: bool,
+    /// Largest message accepted on any topic, 0 to rely on `gossipsub.max_transmit_size`
+    pub max_message_bytes: usize,
+    /// Messages accepted per forwarding peer and `rate_limit_window_secs` on any topic, 0 for unlimited
+    pub rate_limit_messages: u32,
+    /// Window over which `rate_limit_messages` are counted, in seconds
+    pub rate_limit_window_secs: u64,
+    /// Reject discovery messages that are not a list of peers
+    pub discovery_peers: bool,
+    /// Additional checks for the topics matching a pattern
+    pub topics: Vec<TopicValidationConfig>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_message_bytes: 0,
+            rate_limit_messages: 0,
+            rate_limit_window_secs: DEFAULT_VALIDATION_RATE_LIMIT_WINDOW_SECS,
+            discovery_peers: true,
+            topics: Vec::new(),
+        }
+    }
+}
+
+/// Checks for the topics matching `topic`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopicValidationConfig {
+    /// Topics these checks apply to
+    pub topic: TopicPattern,
+    /// Largest message accepted
+    #[serde(default)]
+    pub max_message_bytes: Option<usize>,
+    /// JSON schema file messages must match
+    #[serde(default)]
+    pub json_schema_file: Option<PathBuf>,
+    /// Messages accepted per forwarding peer and `rate_limit_window_secs`
+    #[serde(default)]
+    pub rate_limit_messages: Option<u32>,
+    /// Window over which `rate_limit_messages` are counted, in seconds
+    #[serde(default = "default_validation_rate_limit_window_secs")]
+    pub rate_limit_window_secs: u64,
+}
+
+fn default_validation_rate_limit_window_secs() -> u64 {
+    DEFAULT_VALIDATION_RATE_LIMIT_WINDOW_SECS
+}
+
+impl ValidationConfig {
+    /// Builds the validation pipeline, `discovery_topics` getting the discovery checks.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::MessageValidation`] if a JSON schema file cannot
+    /// be read or parsed, or [`ConfigError::TopicPolicy`] for an invalid regex.
+    pub fn to_pipeline(
+        &self,
+        discovery_topics: impl IntoIterator<Item = libp2p::gossipsub::TopicHash>,
+    ) -> Result<MessageValidation, ConfigError> {
+        let mut validation = MessageValidation::new();
+        if self.max_message_bytes > 0 {
+            validation = validation.with(TopicSelector::All, MaxSize(self.max_message_bytes));
+        }
+        if self.rate_limit_messages > 0 {
+            validation = validation.with(
+                TopicSelector::All,
+                SourceRateLimit::new(
+                    self.rate_limit_messages,
+                    Duration::from_secs(self.rate_limit_window_secs),
+                ),
+            );
+        }
+        if self.discovery_peers {
+            validation = validation.with(
+                TopicSelector::Topics(discovery_topics.into_iter().collect()),
+                DiscoveryPeers,
+            );
+        }
+        for topic in &self.topics {
+            let selector = || -> Result<TopicSelector, ConfigError> {
+                Ok(TopicSelector::Pattern(TopicMatcher::new(&topic.topic)?))
+            };
+            if let Some(max_bytes) = topic.max_message_bytes {
+                validation = validation.with(selector()?, MaxSize(max_bytes));
+            }
+            if let Some(max_messages) = topic.rate_limit_messages {
+                validation = validation.with(
+                    selector()?,
+                    SourceRateLimit::new(
+                        max_messages,
+                        Duration::from_secs(topic.rate_limit_window_secs),
+                    ),
+                );
+            }
+            if let Some(path) = &topic.json_schema_file {
+                validation = validation.with(selector()?, JsonSchema::from_file(path)?);
+            }
+        }
+        Ok(validation)
+    }
+}
+
+/// Peer discovery announcements; see [`crate::discovery_codec`] and [`crate::discovery_schedule`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// Format published: `protobuf` like `@libp2p/pubsub-peer-discovery`, or the legacy `json`; both are read
+    pub format: DiscoveryFormat,
+    /// Seconds between announcements on each discovery topic
+    pub interval_secs: u64,
+    /// Random delay of up to this many seconds added to each interval
+    pub jitter_secs: u64,
+    /// Per-topic overrides, keyed by discovery topic name
+    pub topics: BTreeMap<String, DiscoveryTopicConfig>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            format: DiscoveryFormat::default(),
+            interval_secs: DEFAULT_DISCOVERY_INTERVAL_SECS,
+            jitter_secs: DEFAULT_DISCOVERY_JITTER_SECS,
+            topics: BTreeMap::new(),
+        }
+    }
+}
+
+/// Announcement timing of one discovery topic; unset values come from [`DiscoveryConfig`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryTopicConfig {
+    /// Seconds between announcements on this topic, `discovery.interval_secs` when unset
+    pub interval_secs: Option<u64>,
+    /// Largest random delay added to each announcement, in seconds, `discovery.jitter_secs` when unset
+    pub jitter_secs: Option<u64>,
+}
+
+impl DiscoveryConfig {
+    /// Schedules announcements on `discovery_topics`, hashed with `topic_hash`, from `now`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::Discovery`] for a zero interval, or overrides of a
+    /// topic that is not a discovery topic.
+    pub fn to_schedule(
+        &self,
+        discovery_topics: &[String],
+        topic_hash: impl Fn(&str) -> TopicHash,
+        now: Instant,
+    ) -> Result<DiscoverySchedule, ConfigError> {
+        if let Some(name) = self
+            .topics
+            .keys()
+            .find(|name| !discovery_topics.contains(name))
+        {
+            return Err(ConfigError::Discovery(format!(
+                "'{name}' is not one of pubsub_discovery_topics"
+            )));
+        }
+        let mut rng = rand::thread_rng();
+        let mut schedule = DiscoverySchedule::new();
+        for name in discovery_topics {
+            let overrides = self.topics.get(name).cloned().unwrap_or_default();
+            let interval_secs = overrides.interval_secs.unwrap_or(self.interval_secs);
+            if interval_secs == 0 {
+                return Err(ConfigError::Discovery(format!(
+                    "zero announcement interval for '{name}'"
+                )));
+            }
+            schedule = schedule.with_topic(
+                topic_hash(name),
+                Duration::from_secs(interval_secs),
+                Duration::from_secs(overrides.jitter_secs.unwrap_or(self.jitter_secs)),
+                now,
+                &mut rng,
+            );
+        }
+        Ok(schedule)
+    }
+}
+
+/// Bounds of the address book; see [`crate::address_book`]. A bound of 0 is not enforced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AddressBookConfig {
+    /// Peers remembered, the least recently seen being forgotten first
+    pub max_peers: usize,
+    /// Addresses remembered per peer, the least promising being forgotten first
+    pub max_addrs_per_peer: usize,
+    /// Seconds an address is remembered once last announced or dialed, 0 for no expiry
+    pub ttl_secs: u64,
+    /// Failed dials in a row after which an address is forgotten
+    pub max_dial_failures: u32,
+}
+
+impl Default for AddressBookConfig {
+    fn default() -> Self {
+        Self {
+            max_peers: DEFAULT_ADDRESS_BOOK_MAX_PEERS,
+            max_addrs_per_peer: DEFAULT_ADDRESS_BOOK_MAX_ADDRS_PER_PEER,
+            ttl_secs: DEFAULT_ADDRESS_BOOK_TTL_SECS,
+            max_dial_failures: DEFAULT_ADDRESS_BOOK_MAX_DIAL_FAILURES,
+        }
+    }
+}
+
+impl AddressBookConfig {
+    /// Builds an empty address book with these bounds.
+    pub fn to_address_book(&self) -> AddressBook {
+        AddressBook::new(
+            self.max_peers,
+            self.max_addrs_per_peer,
+            Duration::from_secs(self.ttl_secs),
+            self.max_dial_failures,
+        )
+    }
+}
+
+/// HTTP server serving `/relay` to support staff. It lists the PeerId of every
+/// reserved client and circuit, so it listens apart from the public web server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Serve the admin endpoints
+    pub enabled: bool,
+    /// Address the admin server listens on, e.g. `"127.0.0.1:8001"`
+    pub listen: SocketAddr,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: DEFAULT_ADMIN_LISTEN,
+        }
+    }
+}
+
+/// Identify protocol settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentifyConfig {
+    /// Interval between periodic identify exchanges, in seconds
+    pub interval_secs: u64,
+}
+
+impl Default for IdentifyConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_IDENTIFY_INTERVAL_SECS,
+        }
+    }
+}
+
+/// Persistence and rotation of the WebRTC certificate (and thus the certhash).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebRtcConfig {
+    /// File the certificate is stored in; ignored when `RELAY_WEBRTC_CERTIFICATE` is set
+    pub certificate_file: PathBuf,
+    /// Rotate the certificate this often, in seconds; never rotated when unset
+    pub rotation_interval_secs: Option<u64>,
+    /// Advertise the successor's certhash this long before rotating, in seconds
+    pub rotation_overlap_secs: u64,
+    /// Exit once the rotation time passes so a supervisor restarts the relay with the new certificate
+    pub exit_on_rotation: bool,
+}
+
+impl Default for WebRtcConfig {
+    fn default() -> Self {
+        Self {
+            certificate_file: PathBuf::from(DEFAULT_WEBRTC_CERTIFICATE_FILE),
+            rotation_interval_secs: None,
+            rotation_overlap_secs: DEFAULT_WEBRTC_ROTATION_OVERLAP_SECS,
+            exit_on_rotation: false,
+        }
+    }
+}
+
+impl WebRtcConfig {
+    /// The rotation schedule, or `None` when rotation is disabled.
+    pub fn rotation_schedule(&self) -> Option<RotationSchedule> {
+        self.rotation_interval_secs
+            .map(|interval| RotationSchedule {
+                interval: Duration::from_secs(interval),
+                overlap: Duration::from_secs(self.rotation_overlap_secs.min(interval)),
+            })
+    }
+}
+
+/// Native TLS termination for `/tls/ws` (`/wss`) listen addresses.
+///
+/// Without a certificate, TLS is expected to be terminated in front of the
+/// relay (nginx) and such listen addresses are rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WssConfig {
+    /// PEM certificate chain, e.g. `/etc/letsencrypt/live/<domain>/fullchain.pem`
+    pub certificate_file: Option<PathBuf>,
+    /// PEM private key, e.g. `/etc/letsencrypt/live/<domain>/privkey.pem`
+    pub private_key_file: Option<PathBuf>,
+    /// How often to check both files for changes, in seconds
+    pub reload_interval_secs: u64,
+}
+
+impl Default for WssConfig {
+    fn default() -> Self {
+        Self {
+            certificate_file: None,
+            private_key_file: None,
+            reload_interval_secs: DEFAULT_WSS_RELOAD_INTERVAL_SECS,
+        }
+    }
+}
+
+impl WssConfig {
+    /// The certificate and key files, when native WSS is configured.
+    pub fn files(&self) -> Option<(&Path, &Path)> {
+        match (&self.certificate_file, &self.private_key_file) {
+            (Some(certificate), Some(key)) => Some((certificate, key)),
+            _ => None,
+        }
+    }
+}
+
+/// PROXY protocol (v1 and v2) on selected TCP and WebSocket listeners, so the
+/// remote address of connections arriving through nginx is the client's.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyProtocolConfig {
+    /// Listen addresses whose inbound connections start with a PROXY header
+    pub listen: Vec<String>,
+    /// Source networks whose PROXY headers are honoured; other sources connect as usual
+    pub trusted_sources: Vec<IpNet>,
+    /// How long a trusted source has to send the header, in seconds
+    pub header_timeout_secs: u64,
+}
+
+impl Default for ProxyProtocolConfig {
+    fn default() -> Self {
+        Self {
+            listen: Vec::new(),
+            trusted_sources: DEFAULT_PROXY_PROTOCOL_TRUSTED_SOURCES
+                .iter()
+                .map(|net| net.parse().expect("valid default CIDR"))
+                .collect(),
+            header_timeout_secs: DEFAULT_PROXY_PROTOCOL_HEADER_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl ProxyProtocolConfig {
+    /// Converts this section into the policy applied by the TCP transports.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidAddress`] if a listen address does not parse.
+    pub fn to_policy(&self) -> Result<ProxyProtocolPolicy, ConfigError> {
+        Ok(ProxyProtocolPolicy {
+            listen: parse_addresses("proxy_protocol.listen", &self.listen)?,
+            trusted_sources: self.trusted_sources.clone(),
+            header_timeout: Duration::from_secs(self.header_timeout_secs),
+        })
+    }
+}
+
+/// Listen addresses and per-transport toggles.
+///
+/// A disabled transport skips every listen address of that kind, so e.g.
+/// `quic = false` frees UDP/443 without editing the address list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    /// Addresses to listen on; [`DEFAULT_LISTEN_ADDRESSES`] when not configured
+    pub addresses: Vec<ListenAddress>,
+    /// Listen on plain TCP addresses
+    pub tcp: bool,
+    /// Listen on WebSocket addresses
+    pub websocket: bool,
+    /// Listen on QUIC v1 addresses
+    pub quic: bool,
+    /// Listen on WebRTC-direct addresses
+    pub webrtc_direct: bool,
+    /// Listen on WebTransport addresses
+    pub webtransport: bool,
+    /// Listen on the `/webrtc` shorthand
+    pub webrtc: bool,
+    /// Listen on explicit `<relay>/p2p-circuit` addresses from `addresses`;
+    /// reservations of the relay client are made regardless
+    pub circuit: bool,
+    /// Fixed UDP port for webrtc-direct listeners configured with port 0
+    pub webrtc_direct_port: Option<u16>,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            addresses: DEFAULT_LISTEN_ADDRESSES
+                .iter()
+                .map(|(address, required)| ListenAddress {
+                    address: address.to_string(),
+                    required: *required,
+                    enabled: true,
+                })
+                .collect(),
+            tcp: true,
+            websocket: true,
+            quic: true,
+            webrtc_direct: true,
+            webtransport: true,
+            webrtc: true,
+            circuit: true,
+            webrtc_direct_port: None,
+        }
+    }
+}
+
+impl ListenConfig {
+    /// Whether listeners of the given transport kind should be started.
+    pub fn is_enabled(&self, transport: ListenTransport) -> bool {
+        match transport {
+            ListenTransport::Tcp => self.tcp,
+            ListenTransport::WebSocket => self.websocket,
+            ListenTransport::Quic => self.quic,
+            ListenTransport::WebRtcDirect => self.webrtc_direct,
+            ListenTransport::WebTransport => self.webtransport,
+            ListenTransport::WebRtc => self.webrtc,
+            ListenTransport::Circuit => self.circuit,
+            ListenTransport::Other => true,
+        }
+    }
+
+    /// Returns the addresses that should actually be listened on, with their
+    /// `required` flag, after applying per-address and per-transport toggles
+    /// and the fixed webrtc-direct port.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidAddress`] if an address does not parse.
+    pub fn active_addresses(&self) -> Result<Vec<(Multiaddr, bool)>, ConfigError> {
+        let mut active = Vec::new();
+        for entry in &self.addresses {
+            let address = entry.multiaddr()?;
+            let transport = ListenTransport::of(&address);
+            if !entry.enabled || !self.is_enabled(transport) {
+                continue;
+            }
+            let address = match (transport, self.webrtc_direct_port) {
+                (ListenTransport::WebRtcDirect, Some(port)) => with_fixed_udp_port(address, port),
+                _ => address,
+            };
+            active.push((address, entry.required));
+        }
+        Ok(active)
+    }
+}
+
+/// One configured listen address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenAddress {
+    /// Multiaddr to listen on
+    pub address: String,
+    /// Abort startup if this listener cannot be bound
+    #[serde(default)]
+    pub required: bool,
+    /// Set to false to keep an entry in the file without listening on it
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+impl ListenAddress {
+    /// Parses a `--listen` / `RELAY_LISTEN` entry; a trailing `!` marks it required.
+    pub fn from_flag(value: &str) -> Self {
+        let value = value.trim();
+        let (address, required) = match value.strip_suffix(REQUIRED_LISTEN_SUFFIX) {
+            Some(address) => (address, true),
+            None => (value, false),
+        };
+        Self {
+            address: address.to_string(),
+            required,
+            enabled: true,
+        }
+    }
+
+    /// Parses the configured address.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidAddress`] if the address is not a valid multiaddr.
+    pub fn multiaddr(&self) -> Result<Multiaddr, ConfigError> {
+        self.address
+            .parse()
+            .map_err(|source| ConfigError::InvalidAddress {
+                field: "listen",
+                address: self.address.clone(),
+                source,
+            })
+    }
+}
+
+/// Transport kind of a listen address, used for the per-transport toggles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenTransport {
+    Tcp,
+    WebSocket,
+    Quic,
+    WebRtcDirect,
+    WebTransport,
+    WebRtc,
+    Circuit,
+    Other,
+}
+
+impl ListenTransport {
+    /// Classifies an address by its outermost transport protocol.
+    pub fn of(address: &Multiaddr) -> Self {
+        let mut kind = ListenTransport::Other;
+        for protocol in address.iter() {
+            kind = match protocol {
+                Protocol::P2pCircuit => return ListenTransport::Circuit,
+                Protocol::WebTransport => return ListenTransport::WebTransport,
+                Protocol::WebRTCDirect => return ListenTransport::WebRtcDirect,
+                Protocol::WebRTC => return ListenTransport::WebRtc,
+                Protocol::Ws(_) | Protocol::Wss(_) => return ListenTransport::WebSocket,
+                Protocol::QuicV1 => ListenTransport::Quic,
+                Protocol::Tcp(_) => ListenTransport::Tcp,
+                _ => kind,
+            };
+        }
+        kind
+    }
+}
+
+// Replace an ephemeral `/udp/0` with `port`, leaving explicit ports alone
+fn with_fixed_udp_port(address: Multiaddr, port: u16) -> Multiaddr {
+    address
+        .iter()
+        .map(|protocol| match protocol {
+            Protocol::Udp(0) => Protocol::Udp(port),
+            other => other,
+        })
+        .collect()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl RelayConfig {
+    /// Assembles the effective configuration for `run` / `print-config`.
+    ///
+    /// Reads the config file named in `args` (if any), applies the `--set`
+    /// overrides and finally the dedicated flags and environment variables.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ConfigError`] when the file cannot be read or parsed, when
+    /// an override is not a valid `key=value` TOML assignment, or when the
+    /// merged document does not match the [`RelayConfig`] schema.
+    pub fn from_args(args: &RunArgs) -> Result<Self, ConfigError> {
+        let mut table = match &args.config {
+            Some(path) => read_table(path)?,
+            None => toml::Table::new(),
+        };
+
+        for entry in &args.set {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let overlay: toml::Table =
+                toml::from_str(entry).map_err(|source| ConfigError::InvalidOverride {
+                    entry: entry.to_string(),
+                    source,
+                })?;
+            merge_tables(&mut table, overlay);
+        }
+
+        let mut config: RelayConfig = toml::Value::Table(table).try_into()?;
+        config.apply_args(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values serde cannot check on its own, such as multiaddr syntax.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidAddress`] for the first address that does not parse.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.listen.active_addresses()?;
+        self.announce_addresses()?;
+        self.no_announce_addresses()?;
+        self.relay_client_relays()?;
+        self.proxy_protocol.to_policy()?;
+        self.admission.to_policy()?;
+        self.mirror.to_policy()?;
+        self.gossipsub
+            .peer_score
+            .to_params(|name| self.gossipsub.topic_hashing.hash(name), [])?;
+        for topic in &self.validation.topics {
+            TopicMatcher::new(&topic.topic)?;
+        }
+        self.discovery.to_schedule(
+            &self.pubsub_discovery_topics,
+            |name| self.gossipsub.topic_hashing.hash(name),
+            Instant::now(),
+        )?;
+        self.relay.limit_classes()?;
+        if !self.transport.yamux.enabled && !self.transport.mplex.enabled {
+            return Err(ConfigError::NoMultiplexer);
+        }
+        if self.transport.security.is_empty() {
+            return Err(ConfigError::NoSecurityProtocol);
+        }
+        if self.wss.certificate_file.is_some() != self.wss.private_key_file.is_some() {
+            return Err(ConfigError::IncompleteWss);
+        }
+        if self.wss.files().is_none() {
+            if let Some((address, _)) = self
+                .listen
+                .active_addresses()?
+                .into_iter()
+                .find(|(address, _)| crate::wss::is_wss(address))
+            {
+                return Err(ConfigError::WssWithoutCertificate { address });
+            }
+        }
+        Ok(())
+    }
+
+    /// Addresses to advertise instead of the listen addresses.
+    ///
+    /// The configured `announce` list wins; without one, a configured domain
+    /// yields [`DEFAULT_DOMAIN_ANNOUNCE_ADDRESSES`]. An empty result means the
+    /// listen addresses are advertised as they are reported.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidAddress`] if an address does not parse.
+    pub fn announce_addresses(&self) -> Result<Vec<Multiaddr>, ConfigError> {
+        if !self.announce.is_empty() {
+            return parse_addresses("announce", &self.announce);
+        }
+        match &self.domain {
+            Some(domain) => {
+                let addresses: Vec<String> = DEFAULT_DOMAIN_ANNOUNCE_ADDRESSES
+                    .iter()
+                    .map(|template| template.replace("{domain}", domain))
+                    .collect();
+                parse_addresses("announce", &addresses)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Relays to reserve on when behind a NAT: `relay_client.relays`, or the
+    /// bootstrap list when none are configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidAddress`] if a configured relay does not
+    /// parse; unparseable bootstrap entries are left out, as when dialing them.
+    pub fn relay_client_relays(&self) -> Result<Vec<Multiaddr>, ConfigError> {
+        if !self.relay_client.relays.is_empty() {
+            return parse_addresses("relay_client.relays", &self.relay_client.relays);
+        }
+        Ok(self
+            .bootstrap_list
+            .iter()
+            .filter_map(|address| address.trim().parse().ok())
+            .collect())
+    }
+
+    /// Address prefixes excluded from advertisement.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidAddress`] if an address does not parse.
+    pub fn no_announce_addresses(&self) -> Result<Vec<Multiaddr>, ConfigError> {
+        parse_addresses("no_announce", &self.no_announce)
+    }
+
+    /// Applies the dedicated flags (and their environment variables) on top of
+    /// this configuration. Unset flags leave the current value untouched.
+    pub fn apply_args(&mut self, args: &RunArgs) {
+        if let Some(domain) = &args.domain {
+            self.domain = Some(domain.clone());
+        }
+        if !args.bootstrap_list.is_empty() {
+            self.bootstrap_list = trimmed(&args.bootstrap_list);
+        }
+        if !args.pubsub_discovery_topics.is_empty() {
+            self.pubsub_discovery_topics = trimmed(&args.pubsub_discovery_topics);
+        }
+        if let Some(disable_cert_verification) = args.disable_cert_verification {
+            self.disable_cert_verification = disable_cert_verification;
+        }
+        if let Some(relay_client) = args.relay_client {
+            self.relay_client.enabled = relay_client;
+        }
+        if !args.listen.is_empty() {
+            self.listen.addresses = trimmed(&args.listen)
+                .iter()
+                .map(|entry| ListenAddress::from_flag(entry))
+                .collect();
+        }
+        if let Some(port) = args.webrtc_direct_port {
+            self.listen.webrtc_direct_port = Some(port);
+        }
+        if let Some(path) = &args.access_list {
+            self.access_list.file = Some(path.clone());
+        }
+        if let Some(path) = &args.wss_certificate {
+            self.wss.certificate_file = Some(path.clone());
+        }
+        if let Some(path) = &args.wss_private_key {
+            self.wss.private_key_file = Some(path.clone());
+        }
+        if !args.proxy_protocol.is_empty() {
+            self.proxy_protocol.listen = trimmed(&args.proxy_protocol);
+        }
+        if !args.proxy_protocol_trusted.is_empty() {
+            self.proxy_protocol.trusted_sources = args.proxy_protocol_trusted.clone();
+        }
+        if !args.announce.is_empty() {
+            self.announce = trimmed(&args.announce);
+        }
+        if !args.no_announce.is_empty() {
+            self.no_announce = trimmed(&args.no_announce);
+        }
+    }
+}
+
+// Read a TOML file into a raw table so overrides can be merged before deserializing
+fn read_table(path: &Path) -> Result<toml::Table, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// Recursively merge `overlay` into `base`; nested tables are merged, anything else is replaced
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_child)), toml::Value::Table(overlay_child)) => {
+                merge_tables(base_child, overlay_child);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn parse_addresses(field: &'static str, values: &[String]) -> Result<Vec<Multiaddr>, ConfigError> {
+    values
+        .iter()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|source| ConfigError::InvalidAddress {
+                    field,
+                    address: address.clone(),
+                    source,
+                })
+        })
+        .collect()
+}
+
+fn trimmed(values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_config(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_defaults_match_previous_hardcoded_values() {
+        let config = RelayConfig::default();
+        let relay_config = config.relay.to_relay_config().unwrap();
+        assert!(relay_config.limit_selector.is_none());
+        assert_eq!(relay_config.max_reservations, usize::MAX);
+        assert_eq!(relay_config.max_circuit_bytes, 1024 * 1024);
+        assert_eq!(config.gossipsub.mesh_n, 8);
+        assert_eq!(
+            config.transport.yamux.receive_window_bytes,
+            16 * 1024 * 1024
+        );
+        assert_eq!(config.swarm.max_negotiating_inbound_streams, 10_000);
+        assert_eq!(config.identify.interval_secs, 600);
+    }
+
+    #[test]
+    fn test_layering_file_then_set_then_flags() {
+        let file = write_config(
+            r#"
+            domain = "file.example.com"
+            pubsub_discovery_topics = ["from-file"]
+            disable_cert_verification = true
+
+            [relay_client]
+            enabled = true
+
+            [relay]
+            max_circuits = 64
+            max_circuit_bytes = 2048
+
+            [gossipsub]
+            mesh_n = 4
+            "#,
+        );
+        let args = RunArgs {
+            config: Some(file.path().to_path_buf()),
+            set: vec!["relay.max_circuits = 128".to_string()],
+            domain: Some("flag.example.com".to_string()),
+            disable_cert_verification: Some(false),
+            relay_client: Some(false),
+            ..Default::default()
+        };
+
+        let config = RelayConfig::from_args(&args).unwrap();
+        assert_eq!(config.domain.as_deref(), Some("flag.example.com"));
+        assert!(!config.disable_cert_verification);
+        assert!(!config.relay_client.enabled);
+        assert_eq!(
+            config.pubsub_discovery_topics,
+            vec!["from-file".to_string()]
+        );
+        assert_eq!(config.relay.max_circuits, 128);
+        assert_eq!(config.relay.max_circuit_bytes, 2048);
+        // Untouched fields of a partially specified section keep their defaults
+        assert_eq!(
+            config.relay.max_circuits_per_peer,
+            DEFAULT_MAX_CIRCUITS_PER_PEER
+        );
+        assert_eq!(config.gossipsub.mesh_n, 4);
+        assert_eq!(config.gossipsub.gossip_lazy, DEFAULT_GOSSIPSUB_GOSSIP_LAZY);
+    }
+
+    #[test]
+    fn test_listen_flags_and_transport_toggles() {
+        let args = RunArgs {
+            listen: vec![
+                "/ip4/0.0.0.0/tcp/4001!".to_string(),
+                "/ip4/0.0.0.0/tcp/4002/ws".to_string(),
+                "/ip4/0.0.0.0/udp/4003/quic-v1".to_string(),
+            ],
+            set: vec!["listen.quic = false".to_string()],
+            ..Default::default()
+        };
+
+        let config = RelayConfig::from_args(&args).unwrap();
+        let active = config.listen.active_addresses().unwrap();
+        assert_eq!(
+            active,
+            vec![
+                ("/ip4/0.0.0.0/tcp/4001".parse().unwrap(), true),
+                ("/ip4/0.0.0.0/tcp/4002/ws".parse().unwrap(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fixed_webrtc_direct_port() {
+        let args = RunArgs {
+            listen: vec![
+                "/ip4/0.0.0.0/udp/0/webrtc-direct".to_string(),
+                "/ip4/0.0.0.0/udp/0/quic-v1".to_string(),
+            ],
+            webrtc_direct_port: Some(9090),
+            ..Default::default()
+        };
+
+        let config = RelayConfig::from_args(&args).unwrap();
+        let active: Vec<Multiaddr> = config
+            .listen
+            .active_addresses()
+            .unwrap()
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect();
+        assert_eq!(
+            active,
+            vec![
+                "/ip4/0.0.0.0/udp/9090/webrtc-direct".parse().unwrap(),
+                "/ip4/0.0.0.0/udp/0/quic-v1".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listen_transport_classification() {
+        let kind = |addr: &str| ListenTransport::of(&addr.parse().unwrap());
+        assert_eq!(kind("/ip4/0.0.0.0/tcp/0"), ListenTransport::Tcp);
+        assert_eq!(
+            kind("/ip4/0.0.0.0/tcp/443/tls/ws"),
+            ListenTransport::WebSocket
+        );
+        assert_eq!(kind("/ip4/0.0.0.0/udp/443/quic-v1"), ListenTransport::Quic);
+        assert_eq!(
+            kind("/ip4/0.0.0.0/udp/443/quic-v1/webtransport"),
+            ListenTransport::WebTransport
+        );
+        assert_eq!(
+            kind("/ip4/0.0.0.0/udp/0/webrtc-direct"),
+            ListenTransport::WebRtcDirect
+        );
+        assert_eq!(kind("/p2p-circuit"), ListenTransport::Circuit);
+    }
+
+    #[test]
+    fn test_invalid_listen_address_is_rejected() {
+        let args = RunArgs {
+            listen: vec!["/ip4/0.0.0.0/tcp/notaport".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            RelayConfig::from_args(&args),
+            Err(ConfigError::InvalidAddress { .. })
+        ));
+    }
+
+    #[test]
+    fn test_domain_implies_default_announce_addresses() {
+        let mut config = RelayConfig {
+            domain: Some("relay.example.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.announce_addresses().unwrap(),
+            vec![
+                "/dns4/relay.example.com/tcp/443/wss".parse().unwrap(),
+                "/dns4/relay.example.com/tcp/80/ws".parse().unwrap(),
+            ]
+        );
+
+        config.announce = vec!["/dns4/other.example.com/tcp/443/wss".to_string()];
+        assert_eq!(config.announce_addresses().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_mplex_limits_are_parsed() {
+        let args = RunArgs {
+            set: vec![
+                "transport.mplex.max_num_streams = 64".to_string(),
+                "transport.mplex.max_buffer_frames = 16".to_string(),
+                "transport.mplex.max_buffer_behaviour = \"reset_stream\"".to_string(),
+                "transport.mplex.split_send_size_bytes = 4096".to_string(),
+            ],
+            ..Default::default()
+        };
+        let mplex = RelayConfig::from_args(&args).unwrap().transport.mplex;
+        assert!(mplex.enabled);
+        assert_eq!(mplex.max_num_streams, 64);
+        assert_eq!(mplex.max_buffer_frames, 16);
+        assert_eq!(
+            mplex.max_buffer_behaviour,
+            MplexBufferBehaviour::ResetStream
+        );
+        assert_eq!(mplex.split_send_size_bytes, 4096);
+    }
+
+    #[test]
+    fn test_disabling_both_multiplexers_is_rejected() {
+        let args = RunArgs {
+            set: vec!["transport.yamux.enabled = false".to_string()],
+            ..Default::default()
+        };
+        assert!(RelayConfig::from_args(&args).is_ok());
+
+        let args = RunArgs {
+            set: vec![
+                "transport.yamux.enabled = false".to_string(),
+                "transport.mplex.enabled = false".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert!(matches!(
+            RelayConfig::from_args(&args),
+            Err(ConfigError::NoMultiplexer)
+        ));
+    }
+
+    #[test]
+    fn test_wss_listen_address_requires_certificate() {
+        let args = RunArgs {
+            listen: vec!["/ip4/0.0.0.0/tcp/443/tls/ws".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            RelayConfig::from_args(&args),
+            Err(ConfigError::WssWithoutCertificate { .. })
+        ));
+
+        let args = RunArgs {
+            wss_certificate: Some(PathBuf::from("fullchain.pem")),
+            ..args
+        };
+        assert!(matches!(
+            RelayConfig::from_args(&args),
+            Err(ConfigError::IncompleteWss)
+        ));
+
+        let args = RunArgs {
+            wss_private_key: Some(PathBuf::from("privkey.pem")),
+            ..args
+        };
+        let config = RelayConfig::from_args(&args).unwrap();
+        assert_eq!(
+            config.wss.files(),
+            Some((Path::new("fullchain.pem"), Path::new("privkey.pem")))
+        );
+    }
+
+    #[test]
+    fn test_mirror_topic_patterns() {
+        let args = RunArgs {
+            set: vec![
+                r#"mirror.deny = [{ prefix = "spam/" }, { regex = "^[0-9]+$" }]"#.to_string(),
+                r#"mirror.allow = [{ exact = "spam/ok" }]"#.to_string(),
+            ],
+            ..Default::default()
+        };
+        let config = RelayConfig::from_args(&args).unwrap();
+        assert_eq!(
+            config.mirror.deny[0],
+            TopicPattern::Prefix("spam/".to_string())
+        );
+        assert_eq!(config.mirror.max_topics, DEFAULT_MIRROR_MAX_TOPICS);
+
+        let args = RunArgs {
+            set: vec![r#"mirror.deny = [{ regex = "(" }]"#.to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            RelayConfig::from_args(&args),
+            Err(ConfigError::TopicPolicy(_))
+        ));
+    }
+
+    #[test]
+    fn test_peer_score_topics_and_thresholds() {
+        let args = RunArgs {
+            set: vec![
+                r#"gossipsub.peer_score.topics."app/db".invalid_message_deliveries_weight = -20.0"#
+                    .to_string(),
+            ],
+            ..Default::default()
+        };
+        let config = RelayConfig::from_args(&args).unwrap();
+        let own = TopicHash::from_raw("own");
+        let (params, thresholds) = config
+            .gossipsub
+            .peer_score
+            .to_params(|name| TopicHash::from_raw(name), [own.clone()])
+            .unwrap();
+        assert_eq!(
+            params.topics[&TopicHash::from_raw("app/db")].invalid_message_deliveries_weight,
+            -20.0
+        );
+        assert_eq!(params.topics[&own].mesh_message_deliveries_weight, 0.0);
+        assert_eq!(params.ip_colocation_factor_weight, 0.0);
+        assert_eq!(
+            thresholds.graylist_threshold,
+            DEFAULT_GOSSIPSUB_GRAYLIST_THRESHOLD
+        );
+
+        let args = RunArgs {
+            set: vec!["gossipsub.peer_score.gossip_threshold = 5.0".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            RelayConfig::from_args(&args),
+            Err(ConfigError::PeerScore(_))
+        ));
+    }
+
+    #[test]
+    fn test_topic_hashing_defaults_to_identity() {
+        let config = RelayConfig::from_args(&RunArgs::default()).unwrap();
+        assert_eq!(config.gossipsub.topic_hashing, TopicHashing::Identity);
+        assert!(config.gossipsub.bridge_topics.is_empty());
+
+        let args = RunArgs {
+            set: vec![
+                r#"gossipsub.topic_hashing = "sha256""#.to_string(),
+                r#"gossipsub.bridge_topics = ["réseau-constellation"]"#.to_string(),
+            ],
+            ..Default::default()
+        };
+        let config = RelayConfig::from_args(&args).unwrap();
+        assert_eq!(config.gossipsub.topic_hashing, TopicHashing::Sha256);
+        assert_eq!(config.gossipsub.bridge_topics, vec!["réseau-constellation"]);
+    }
+
+    #[test]
+    fn test_discovery_format() {
+        let config = RelayConfig::from_args(&RunArgs::default()).unwrap();
+        assert_eq!(config.discovery.format, DiscoveryFormat::Protobuf);
+
+        let args = RunArgs {
+            set: vec![r#"discovery.format = "json""#.to_string()],
+            ..Default::default()
+        };
+        let config = RelayConfig::from_args(&args).unwrap();
+        assert_eq!(config.discovery.format, DiscoveryFormat::Json);
+    }
+
+    #[test]
+    fn test_default_discovery_topics() {
+        let config = RelayConfig::from_args(&RunArgs::default()).unwrap();
+        assert_eq!(
+            config.pubsub_discovery_topics,
+            DEFAULT_PUBSUB_DISCOVERY_TOPICS
+        );
+
+        let args = RunArgs {
+            pubsub_discovery_topics: vec!["app._peer-discovery._p2p._pubsub".to_string()],
+            ..Default::default()
+        };
+        let config = RelayConfig::from_args(&args).unwrap();
+        assert_eq!(
+            config.pubsub_discovery_topics,
+            vec!["app._peer-discovery._p2p._pubsub"]
+        );
+    }
+
+    #[test]
+    fn test_discovery_intervals_per_topic() {
+        let args = RunArgs {
+            set: vec![
+                "discovery.jitter_secs = 0".to_string(),
+                r#"discovery.topics."orbiter._peer-discovery._p2p._pubsub".interval_secs = 10"#
+                    .to_string(),
+            ],
+            ..Default::default()
+        };
+        let config = RelayConfig::from_args(&args).unwrap();
+        let now = Instant::now();
+        let mut schedule = config
+            .discovery
+            .to_schedule(
+                &config.pubsub_discovery_topics,
+                |name| TopicHash::from_raw(name),
+                now,
+            )
+            .unwrap();
+        assert_eq!(schedule.len(), 3);
+        assert_eq!(
+            schedule.due(now + Duration::from_secs(10), &mut rand::thread_rng()),
+            vec![TopicHash::from_raw("orbiter._peer-discovery._p2p._pubsub")]
+        );
+
+        let args = RunArgs {
+            set: vec![r#"discovery.topics."not-discovery".interval_secs = 10"#.to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            RelayConfig::from_args(&args),
+            Err(ConfigError::Discovery(_))
+        ));
+        let args = RunArgs {
+            set: vec!["discovery.interval_secs = 0".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            RelayConfig::from_args(&args),
+            Err(ConfigError::Discovery(_))
+        ));
+    }
+
+    #[test]
+    fn test_admin_server_defaults_to_loopback() {
+        let config = RelayConfig::default();
+        assert!(config.admin.enabled);
+        assert!(config.admin.listen.ip().is_loopback());
+
+        let args = RunArgs {
+            set: vec!["admin.listen = \"10.0.0.1:9000\"".to_string()],
+            ..Default::default()
+        };
+        let config = RelayConfig::from_args(&args).unwrap();
+        assert_eq!(config.admin.listen, "10.0.0.1:9000".parse().unwrap());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let args = RunArgs {
+            set: vec!["relay.max_circuitz = 1".to_string()],
+            ..Default::default()
+        };
+        assert!(RelayConfig::from_args(&args).is_err());
+    }
+}
@@ -0,0 +1,437 @@
+//! Connection limits: total, per peer and per source IP (or subnet), for both
+//! pending and established connections.
+//!
+//! A connection over a limit is refused with a [`ConnectionDenied`] wrapping
+//! [`LimitExceeded`], which the event loop can downcast to log the reason.
+//! Refusals are also counted per [`LimitKind`] for the status log. A limit
+//! of 0 is not enforced.
+//!
+//! Per-IP limits only apply to inbound connections and group source addresses
+//! by `ipv4_prefix_len` / `ipv6_prefix_len`. Sources in `exempt_sources` are
+//! not limited per IP: without the PROXY protocol every connection through a
+//! local nginx shares 127.0.0.1.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt;
+use std::net::IpAddr;
+use std::task::{Context, Poll};
+
+use ipnet::IpNet;
+use libp2p::core::transport::PortUse;
+use libp2p::core::Endpoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{
+    dummy, ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+    THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+
+use crate::config::ConnectionLimitsConfig;
+
+/// The limit a refused connection ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LimitKind {
+    /// `max_pending_incoming` reached
+    PendingIncoming,
+    /// `max_pending_outgoing` reached
+    PendingOutgoing,
+    /// `max_pending_per_ip` reached for the source IP or subnet
+    PendingPerIp,
+    /// `max_established` reached
+    Established,
+    /// `max_established_incoming` reached
+    EstablishedIncoming,
+    /// `max_established_outgoing` reached
+    EstablishedOutgoing,
+    /// `max_established_per_peer` reached for the remote peer
+    EstablishedPerPeer,
+    /// `max_established_per_ip` reached for the source IP or subnet
+    EstablishedPerIp,
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LimitKind::PendingIncoming => "pending_incoming",
+            LimitKind::PendingOutgoing => "pending_outgoing",
+            LimitKind::PendingPerIp => "pending_per_ip",
+            LimitKind::Established => "established",
+            LimitKind::EstablishedIncoming => "established_incoming",
+            LimitKind::EstablishedOutgoing => "established_outgoing",
+            LimitKind::EstablishedPerPeer => "established_per_peer",
+            LimitKind::EstablishedPerIp => "established_per_ip",
+        })
+    }
+}
+
+/// Reason attached to the [`ConnectionDenied`] of a refused connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("connection limit {kind} of {limit} reached")]
+pub struct LimitExceeded {
+    /// Which limit was reached
+    pub kind: LimitKind,
+    /// The configured value of that limit
+    pub limit: u32,
+}
+
+/// Enforces a [`ConnectionLimitsConfig`]. Emits no events.
+pub struct Behaviour {
+    limits: ConnectionLimitsConfig,
+    pending_inbound: HashMap<ConnectionId, Option<IpNet>>,
+    pending_outbound: HashSet<ConnectionId>,
+    established_inbound: HashSet<ConnectionId>,
+    established_outbound: HashSet<ConnectionId>,
+    established_per_peer: HashMap<PeerId, HashSet<ConnectionId>>,
+    established_per_ip: HashMap<IpNet, HashSet<ConnectionId>>,
+    denied: BTreeMap<LimitKind, u64>,
+}
+
+impl Behaviour {
+    /// Enforces `limits`, with no connection counted yet.
+    pub fn new(limits: ConnectionLimitsConfig) -> Self {
+        Self {
+            limits,
+            pending_inbound: HashMap::new(),
+            pending_outbound: HashSet::new(),
+            established_inbound: HashSet::new(),
+            established_outbound: HashSet::new(),
+            established_per_peer: HashMap::new(),
+            established_per_ip: HashMap::new(),
+            denied: BTreeMap::new(),
+        }
+    }
+
+    /// Number of refused connections per limit since startup.
+    pub fn denied_counts(&self) -> &BTreeMap<LimitKind, u64> {
+        &self.denied
+    }
+
+    // The subnet a remote address is limited under, `None` when exempt or not IP-based
+    fn subnet(&self, remote_addr: &Multiaddr) -> Option<IpNet> {
+        let ip: IpAddr = match remote_addr.iter().next()? {
+            Protocol::Ip4(ip) => ip.into(),
+            Protocol::Ip6(ip) => ip.into(),
+            _ => return None,
+        };
+        if self
+            .limits
+            .exempt_sources
+            .iter()
+            .any(|net| net.contains(&ip))
+        {
+            return None;
+        }
+        let prefix_len = match ip {
+            IpAddr::V4(_) => self.limits.ipv4_prefix_len.min(32),
+            IpAddr::V6(_) => self.limits.ipv6_prefix_len.min(128),
+        };
+        IpNet::new(ip, prefix_len).ok().map(|net| net.trunc())
+    }
+
+    fn check(&mut self, checks: &[(LimitKind, u32, usize)]) -> Result<(), ConnectionDenied> {
+        for &(kind, limit, current) in checks {
+            if limit > 0 && current >= limit as usize {
+                *self.denied.entry(kind).or_default() += 1;
+                return Err(ConnectionDenied::new(LimitExceeded { kind, limit }));
+            }
+        }
+        Ok(())
+    }
+
+    fn num_established(&self) -> usize {
+        self.established_inbound.len() + self.established_outbound.len()
+    }
+
+    fn num_established_with(&self, peer: &PeerId) -> usize {
+        self.established_per_peer.get(peer).map_or(0, HashSet::len)
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        let subnet = self.subnet(remote_addr);
+        let pending_from_subnet = subnet.map_or(0, |subnet| {
+            self.pending_inbound
+                .values()
+                .filter(|pending| **pending == Some(subnet))
+                .count()
+        });
+        self.check(&[
+            (
+                LimitKind::PendingIncoming,
+                self.limits.max_pending_incoming,
+                self.pending_inbound.len(),
+            ),
+            (
+                LimitKind::PendingPerIp,
+                subnet.map_or(0, |_| self.limits.max_pending_per_ip),
+                pending_from_subnet,
+            ),
+        ])?;
+        self.pending_inbound.insert(connection_id, subnet);
+        Ok(())
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.pending_inbound.remove(&connection_id);
+        let subnet = self.subnet(remote_addr);
+        let established_from_subnet = subnet
+            .and_then(|subnet| self.established_per_ip.get(&subnet))
+            .map_or(0, HashSet::len);
+        self.check(&[
+            (
+                LimitKind::Established,
+                self.limits.max_established,
+                self.num_established(),
+            ),
+            (
+                LimitKind::EstablishedIncoming,
+                self.limits.max_established_incoming,
+                self.established_inbound.len(),
+            ),
+            (
+                LimitKind::EstablishedPerPeer,
+                self.limits.max_established_per_peer,
+                self.num_established_with(&peer),
+            ),
+            (
+                LimitKind::EstablishedPerIp,
+                subnet.map_or(0, |_| self.limits.max_established_per_ip),
+                established_from_subnet,
+            ),
+        ])?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        _maybe_peer: Option<PeerId>,
+        _addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.check(&[(
+            LimitKind::PendingOutgoing,
+            self.limits.max_pending_outgoing,
+            self.pending_outbound.len(),
+        )])?;
+        self.pending_outbound.insert(connection_id);
+        Ok(Vec::new())
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.pending_outbound.remove(&connection_id);
+        self.check(&[
+            (
+                LimitKind::Established,
+                self.limits.max_established,
+                self.num_established(),
+            ),
+            (
+                LimitKind::EstablishedOutgoing,
+                self.limits.max_established_outgoing,
+                self.established_outbound.len(),
+            ),
+            (
+                LimitKind::EstablishedPerPeer,
+                self.limits.max_established_per_peer,
+                self.num_established_with(&peer),
+            ),
+        ])?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                let id = established.connection_id;
+                self.established_per_peer
+                    .entry(established.peer_id)
+                    .or_default()
+                    .insert(id);
+                if established.endpoint.is_dialer() {
+                    self.established_outbound.insert(id);
+                } else {
+                    self.established_inbound.insert(id);
+                    if let Some(subnet) = self.subnet(established.endpoint.get_remote_address()) {
+                        self.established_per_ip
+                            .entry(subnet)
+                            .or_default()
+                            .insert(id);
+                    }
+                }
+            }
+            FromSwarm::ConnectionClosed(ConnectionClosed {
+                peer_id,
+                connection_id,
+                ..
+            }) => {
+                self.established_inbound.remove(&connection_id);
+                self.established_outbound.remove(&connection_id);
+                if let Some(ids) = self.established_per_peer.get_mut(&peer_id) {
+                    ids.remove(&connection_id);
+                    if ids.is_empty() {
+                        self.established_per_peer.remove(&peer_id);
+                    }
+                }
+                self.established_per_ip.retain(|_, ids| {
+                    ids.remove(&connection_id);
+                    !ids.is_empty()
+                });
+            }
+            FromSwarm::ListenFailure(failure) => {
+                self.pending_inbound.remove(&failure.connection_id);
+            }
+            FromSwarm::DialFailure(failure) => {
+                self.pending_outbound.remove(&failure.connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    fn peer_id() -> PeerId {
+        PeerId::from(libp2p::identity::Keypair::generate_ed25519().public())
+    }
+
+    fn denied_kind(result: Result<(), ConnectionDenied>) -> LimitKind {
+        result
+            .unwrap_err()
+            .downcast::<LimitExceeded>()
+            .expect("denied by a connection limit")
+            .kind
+    }
+
+    #[test]
+    fn test_pending_per_subnet_limit() {
+        let mut behaviour = Behaviour::new(ConnectionLimitsConfig {
+            max_pending_per_ip: 2,
+            ipv6_prefix_len: 64,
+            ..Default::default()
+        });
+        let local = addr("/ip4/0.0.0.0/tcp/4001");
+        for i in 0..2 {
+            behaviour
+                .handle_pending_inbound_connection(
+                    ConnectionId::new_unchecked(i),
+                    &local,
+                    &addr(&format!("/ip6/2001:db8::{}/tcp/5000", i + 1)),
+                )
+                .unwrap();
+        }
+
+        // Same /64, over the limit
+        let denied = behaviour.handle_pending_inbound_connection(
+            ConnectionId::new_unchecked(2),
+            &local,
+            &addr("/ip6/2001:db8::ff/tcp/5000"),
+        );
+        assert_eq!(denied_kind(denied), LimitKind::PendingPerIp);
+        // Another /64 and the exempt loopback are still accepted
+        for (i, remote) in ["/ip6/2001:db8:1::1/tcp/5000", "/ip4/127.0.0.1/tcp/5000"]
+            .iter()
+            .enumerate()
+        {
+            behaviour
+                .handle_pending_inbound_connection(
+                    ConnectionId::new_unchecked(3 + i),
+                    &local,
+                    &addr(remote),
+                )
+                .unwrap();
+        }
+        assert_eq!(behaviour.denied_counts()[&LimitKind::PendingPerIp], 1);
+    }
+
+    #[test]
+    fn test_established_per_peer_limit() {
+        let mut behaviour = Behaviour::new(ConnectionLimitsConfig {
+            max_established_per_peer: 1,
+            ..Default::default()
+        });
+        let peer = peer_id();
+        let local = addr("/ip4/0.0.0.0/tcp/4001");
+        let remote = addr("/ip4/203.0.113.9/tcp/5000");
+        let endpoint = libp2p::core::ConnectedPoint::Listener {
+            local_addr: local.clone(),
+            send_back_addr: remote.clone(),
+        };
+        let first = ConnectionId::new_unchecked(0);
+        behaviour
+            .handle_established_inbound_connection(first, peer, &local, &remote)
+            .unwrap();
+        behaviour.on_swarm_event(FromSwarm::ConnectionEstablished(
+            libp2p::swarm::behaviour::ConnectionEstablished {
+                peer_id: peer,
+                connection_id: first,
+                endpoint: &endpoint,
+                failed_addresses: &[],
+                other_established: 0,
+            },
+        ));
+
+        let denied = behaviour
+            .handle_established_inbound_connection(
+                ConnectionId::new_unchecked(1),
+                peer,
+                &local,
+                &remote,
+            )
+            .map(|_| ());
+        assert_eq!(denied_kind(denied), LimitKind::EstablishedPerPeer);
+        behaviour
+            .handle_established_inbound_connection(
+                ConnectionId::new_unchecked(2),
+                peer_id(),
+                &local,
+                &remote,
+            )
+            .unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! Encoding of peer discovery announcements on the discovery topics.
+//!
+//! `@libp2p/pubsub-peer-discovery`, used by `src/relai.ts`, publishes one
+//! protobuf `Peer { publicKey, addrs }` per announcement, describing the
+//! announcing peer. That is [`DiscoveryFormat::Protobuf`], the default. The
+//! legacy [`DiscoveryFormat::Json`] is a JSON list of `{ id, addrs }` peers,
+//! which older Rust relays publish for their connected peers.
+//!
+//! [`decode`] accepts both: JSON announcements start with `[`, which can never
+//! start a `Peer` message.
+
+use libp2p::identity::{DecodingError, PublicKey};
+use libp2p::{multiaddr, Multiaddr, PeerId};
+use prost::Message as ProstMessage;
+use serde::{Deserialize, Serialize};
+
+mod peer_discovery_proto {
+    include!(concat!(env!("OUT_DIR"), "/peer_discovery.rs"));
+}
+use self::peer_discovery_proto::Peer as PeerProto;
+
+/// Format of the announcements the relay publishes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryFormat {
+    /// `@libp2p/pubsub-peer-discovery` protobuf, announcing the relay itself
+    #[default]
+    Protobuf,
+    /// JSON list of `{ id, addrs }`, announcing the relay's connected peers
+    Json,
+}
+
+/// Errors decoding an announcement.
+#[derive(Debug, thiserror::Error)]
+pub enum DiscoveryError {
+    #[error("invalid protobuf announcement: {0}")]
+    Protobuf(#[from] prost::DecodeError),
+    #[error("invalid JSON announcement: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid public key: {0}")]
+    PublicKey(#[from] DecodingError),
+    #[error("invalid peer id '{0}'")]
+    PeerId(String),
+    #[error("invalid multiaddr: {0}")]
+    Multiaddr(#[from] multiaddr::Error),
+}
+
+/// A peer and its addresses, as announced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredPeer {
+    pub peer_id: PeerId,
+    pub addrs: Vec<Multiaddr>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonPeer {
+    id: String,
+    addrs: Vec<String>,
+}
+
+/// Encodes the announcement of the peer owning `public_key`, reachable at `addrs`.
+pub fn encode_protobuf(public_key: &PublicKey, addrs: &[Multiaddr]) -> Vec<u8> {
+    PeerProto {
+        public_key: public_key.encode_protobuf(),
+        addrs: addrs.iter().map(Multiaddr::to_vec).collect(),
+    }
+    .encode_to_vec()
+}
+
+/// Encodes a legacy JSON announcement of `peers`.
+pub fn encode_json(peers: &[DiscoveredPeer]) -> Vec<u8> {
+    let peers: Vec<JsonPeer> = peers
+        .iter()
+        .map(|peer| JsonPeer {
+            id: peer.peer_id.to_string(),
+            addrs: peer.addrs.iter().map(Multiaddr::to_string).collect(),
+        })
+        .collect();
+    serde_json::to_vec(&peers).expect("a list of strings always serializes")
+}
+
+/// Decodes an announcement in either format, returning the format it was in.
+///
+/// # Errors
+///
+/// Returns a [`DiscoveryError`] if the announcement or any peer in it is invalid.
+pub fn decode(data: &[u8]) -> Result<(DiscoveryFormat, Vec<DiscoveredPeer>), DiscoveryError> {
+    if data.trim_ascii_start().starts_with(b"[") {
+        let peers = serde_json::from_slice::<Vec<JsonPeer>>(data)?
+            .into_iter()
+            .map(|peer| {
+                Ok(DiscoveredPeer {
+                    peer_id: peer
+                        .id
+                        .parse()
+                        .map_err(|_| DiscoveryError::PeerId(peer.id.clone()))?,
+                    addrs: peer
+                        .addrs
+                        .iter()
+                        .map(|addr| addr.parse())
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<Result<_, DiscoveryError>>()?;
+        return Ok((DiscoveryFormat::Json, peers));
+    }
+
+    let peer = PeerProto::decode(data)?;
+    let peer = DiscoveredPeer {
+        peer_id: PublicKey::try_decode_protobuf(&peer.public_key)?.to_peer_id(),
+        addrs: peer
+            .addrs
+            .into_iter()
+            .map(Multiaddr::try_from)
+            .collect::<Result<_, _>>()?,
+    };
+    Ok((DiscoveryFormat::Protobuf, vec![peer]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    #[test]
+    fn test_protobuf_round_trip() {
+        let keypair = Keypair::generate_ed25519();
+        let addrs: Vec<Multiaddr> = vec![
+            "/ip4/192.0.2.1/tcp/4001".parse().unwrap(),
+            "/dns4/relay.example.com/tcp/443/wss".parse().unwrap(),
+        ];
+        let data = encode_protobuf(&keypair.public(), &addrs);
+
+        let (format, peers) = decode(&data).unwrap();
+        assert_eq!(format, DiscoveryFormat::Protobuf);
+        assert_eq!(
+            peers,
+            vec![DiscoveredPeer {
+                peer_id: keypair.public().to_peer_id(),
+                addrs
+            }]
+        );
+
+        // A missing public key is no peer at all
+        assert!(matches!(
+            decode(&PeerProto::default().encode_to_vec()),
+            Err(DiscoveryError::PublicKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_json_is_detected() {
+        let peer = DiscoveredPeer {
+            peer_id: PeerId::random(),
+            addrs: vec!["/ip4/192.0.2.1/udp/4001/quic-v1".parse().unwrap()],
+        };
+        let data = encode_json(std::slice::from_ref(&peer));
+
+        assert_eq!(decode(&data).unwrap(), (DiscoveryFormat::Json, vec![peer]));
+        assert!(matches!(
+            decode(br#" [{"id":"nope","addrs":[]}]"#),
+            Err(DiscoveryError::PeerId(_))
+        ));
+        assert!(decode(br#"[{"id":"x","addrs":[],"extra":1}]"#).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! When peer discovery announcements are published, per discovery topic.
+//!
+//! Each topic is announced every `interval` plus a random delay of up to
+//! `jitter`, so relays started together do not announce in lockstep.
+//! [`DiscoverySchedule::spawn`] polls the schedule on a dedicated task, from its
+//! own `tokio::time::interval` of [`DISCOVERY_TICK`], and hands the due topics
+//! to the event loop through [`DiscoveryTicks`]: publishing stays in the event
+//! loop, which owns the swarm. [`DiscoveryTicks::announce_now`] is called when
+//! the relay's own addresses change, as `pubsub-peer-discovery` would announce
+//! them on its next 1 s tick.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use libp2p::gossipsub::TopicHash;
+use rand::Rng;
+use tokio::sync::{mpsc, Notify};
+
+/// Resolution at which the schedule is polled.
+pub const DISCOVERY_TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+struct ScheduledTopic {
+    topic: TopicHash,
+    interval: Duration,
+    jitter: Duration,
+    next: Instant,
+}
+
+/// Next announcement time of each discovery topic.
+#[derive(Debug, Clone, Default)]
+pub struct DiscoverySchedule {
+    topics: Vec<ScheduledTopic>,
+}
+
+impl DiscoverySchedule {
+    /// Creates an empty schedule.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Announces on `topic` every `interval` plus up to `jitter`, the first
+    /// time one such period after `now`.
+    pub fn with_topic(
+        mut self,
+        topic: TopicHash,
+        interval: Duration,
+        jitter: Duration,
+        now: Instant,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let next = now + interval + random_delay(jitter, rng);
+        self.topics.push(ScheduledTopic {
+            topic,
+            interval,
+            jitter,
+            next,
+        });
+        self
+    }
+
+    /// Topics to announce on at `now`, each rescheduled one period later.
+    pub fn due(&mut self, now: Instant, rng: &mut impl Rng) -> Vec<TopicHash> {
+        let mut due = Vec::new();
+        for scheduled in &mut self.topics {
+            if scheduled.next <= now {
+                scheduled.next = now + scheduled.interval + random_delay(scheduled.jitter, rng);
+                due.push(scheduled.topic.clone());
+            }
+        }
+        due
+    }
+
+    /// Makes every topic due at `now`, e.g. after a listen address change.
+    pub fn announce_now(&mut self, now: Instant) {
+        for scheduled in &mut self.topics {
+            scheduled.next = now;
+        }
+    }
+
+    /// Number of scheduled topics.
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    /// Whether no topic is scheduled.
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    /// Polls the schedule every [`DISCOVERY_TICK`] on a dedicated tokio task,
+    /// sending the due topics to the returned [`DiscoveryTicks`]. The task
+    /// stops once they are dropped.
+    pub fn spawn(mut self) -> DiscoveryTicks {
+        let (due_tx, due_rx) = mpsc::channel(8);
+        let announce = Arc::new(Notify::new());
+        let notified = announce.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(DISCOVERY_TICK);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = notified.notified() => self.announce_now(Instant::now()),
+                    _ = due_tx.closed() => break,
+                }
+                let due = self.due(Instant::now(), &mut rand::thread_rng());
+                if !due.is_empty() && due_tx.send(due).await.is_err() {
+                    break;
+                }
+            }
+        });
+        DiscoveryTicks {
+            due: due_rx,
+            announce,
+        }
+    }
+}
+
+/// Due discovery topics, received from the task of [`DiscoverySchedule::spawn`].
+#[derive(Debug)]
+pub struct DiscoveryTicks {
+    due: mpsc::Receiver<Vec<TopicHash>>,
+    announce: Arc<Notify>,
+}
+
+impl DiscoveryTicks {
+    /// Waits for the next topics to announce on.
+    pub async fn next(&mut self) -> Option<Vec<TopicHash>> {
+        self.due.recv().await
+    }
+
+    /// Makes every topic due right away, e.g. after a listen address change.
+    pub fn announce_now(&self) {
+        self.announce.notify_one();
+    }
+}
+
+fn random_delay(jitter: Duration, rng: &mut impl Rng) -> Duration {
+    if jitter.is_zero() {
+        Duration::ZERO
+    } else {
+        rng.gen_range(Duration::ZERO..=jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topics_follow_their_own_interval() {
+        let mut rng = rand::thread_rng();
+        let (fast, slow) = (TopicHash::from_raw("fast"), TopicHash::from_raw("slow"));
+        let start = Instant::now();
+        let mut schedule = DiscoverySchedule::new()
+            .with_topic(
+                fast.clone(),
+                Duration::from_secs(10),
+                Duration::ZERO,
+                start,
+                &mut rng,
+            )
+            .with_topic(
+                slow.clone(),
+                Duration::from_secs(60),
+                Duration::ZERO,
+                start,
+                &mut rng,
+            );
+
+        assert!(schedule.due(start, &mut rng).is_empty());
+        assert_eq!(
+            schedule.due(start + Duration::from_secs(10), &mut rng),
+            vec![fast.clone()]
+        );
+        assert!(schedule
+            .due(start + Duration::from_secs(15), &mut rng)
+            .is_empty());
+        assert_eq!(
+            schedule.due(start + Duration::from_secs(60), &mut rng),
+            vec![fast.clone(), slow.clone()]
+        );
+
+        // An address change announces everywhere at once
+        schedule.announce_now(start + Duration::from_secs(61));
+        assert_eq!(
+            schedule.due(start + Duration::from_secs(61), &mut rng),
+            vec![fast, slow]
+        );
+    }
+
+    #[test]
+    fn test_jitter_delays_within_bounds() {
+        let mut rng = rand::thread_rng();
+        let topic = TopicHash::from_raw("topic");
+        let interval = Duration::from_secs(30);
+        let jitter = Duration::from_secs(5);
+        let start = Instant::now();
+
+        for _ in 0..20 {
+            let mut schedule = DiscoverySchedule::new().with_topic(
+                topic.clone(),
+                interval,
+                jitter,
+                start,
+                &mut rng,
+            );
+            assert!(schedule
+                .due(start + interval - Duration::from_millis(1), &mut rng)
+                .is_empty());
+            assert_eq!(
+                schedule.due(start + interval + jitter, &mut rng),
+                vec![topic.clone()]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawned_schedule_announces_on_demand() {
+        let topic = TopicHash::from_raw("topic");
+        let mut ticks = DiscoverySchedule::new()
+            .with_topic(
+                topic.clone(),
+                Duration::from_secs(3600),
+                Duration::ZERO,
+                Instant::now(),
+                &mut rand::thread_rng(),
+            )
+            .spawn();
+
+        ticks.announce_now();
+        let due = tokio::time::timeout(Duration::from_secs(5), ticks.next()).await;
+        assert_eq!(due.unwrap(), Some(vec![topic]));
+    }
+}
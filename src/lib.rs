@@ -1,2 +1,23 @@
 // Export our implementation modules
-pub mod webrtc_signaling; 
\ No newline at end of file
+pub mod access_list;
+pub mod address_book;
+pub mod addresses;
+pub mod admission;
+pub mod cli;
+pub mod config;
+pub mod connection_limits;
+pub mod discovery_codec;
+pub mod discovery_schedule;
+pub mod limit_classes;
+pub mod message_validation;
+pub mod muxer;
+pub mod proxy_protocol;
+pub mod relay_client;
+pub mod relay_registry;
+pub mod security;
+pub mod topic_hashing;
+pub mod topic_interest;
+pub mod topic_policy;
+pub mod webrtc_certificate;
+pub mod webrtc_signaling;
+pub mod wss;
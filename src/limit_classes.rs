@@ -0,0 +1,253 @@
+//! Relay limit classes selected per PeerId.
+//!
+//! Every peer belongs to the `default` class (the `[relay]` limits) unless it
+//! is listed in one of the named classes, e.g. `trusted` or `unlimited`. A
+//! reservation gets the class of the reserving peer; a circuit gets, limit by
+//! limit, the most restrictive of its source and destination classes, so an
+//! unknown peer cannot reach a trusted one with more than the default limits.
+//!
+//! A limit of zero means unlimited. Unlimited circuit byte and duration limits
+//! are left out of the limit sent to the peers, like the TypeScript relay with
+//! `applyDefaultLimit: false`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use libp2p::{relay, PeerId};
+
+/// Name of the class of peers not listed in any other class.
+pub const DEFAULT_CLASS: &str = "default";
+
+/// Errors in the limit class configuration.
+#[derive(Debug, thiserror::Error)]
+pub enum LimitClassError {
+    #[error("limit class '{DEFAULT_CLASS}' is configured by the [relay] limits themselves")]
+    ReservedName,
+    #[error("invalid PeerId '{peer}' in limit class '{class}'")]
+    InvalidPeerId { class: String, peer: String },
+    #[error("peer {peer} is listed in both limit classes '{first}' and '{second}'")]
+    DuplicatePeer {
+        peer: String,
+        first: String,
+        second: String,
+    },
+}
+
+/// Limits of one class, as configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassLimits {
+    /// Maximum bytes relayed per circuit, 0 for unlimited
+    pub max_circuit_bytes: u64,
+    /// Maximum circuit lifetime, zero for unlimited
+    pub max_circuit_duration: Duration,
+    /// Circuits a single peer may have open, 0 for unlimited
+    pub max_circuits_per_peer: usize,
+    /// Reservations a single peer may hold, 0 for unlimited
+    pub max_reservations_per_peer: usize,
+}
+
+impl ClassLimits {
+    // Most restrictive of both, limit by limit, zero being the least restrictive
+    fn min(self, other: Self) -> Self {
+        fn min_limit<T: Ord + Default + Copy>(a: T, b: T) -> T {
+            match (a == T::default(), b == T::default()) {
+                (true, _) => b,
+                (_, true) => a,
+                _ => a.min(b),
+            }
+        }
+        Self {
+            max_circuit_bytes: min_limit(self.max_circuit_bytes, other.max_circuit_bytes),
+            max_circuit_duration: min_limit(self.max_circuit_duration, other.max_circuit_duration),
+            max_circuits_per_peer: min_limit(
+                self.max_circuits_per_peer,
+                other.max_circuits_per_peer,
+            ),
+            max_reservations_per_peer: min_limit(
+                self.max_reservations_per_peer,
+                other.max_reservations_per_peer,
+            ),
+        }
+    }
+}
+
+/// Maps PeerIds to limit classes; plugs into `relay::Config::limit_selector`.
+#[derive(Debug, Clone)]
+pub struct LimitClasses {
+    reservation_duration: Duration,
+    default: ClassLimits,
+    classes: BTreeMap<String, ClassLimits>,
+    peers: HashMap<PeerId, String>,
+}
+
+impl LimitClasses {
+    /// Creates the classes from `(name, limits, peers)` entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`LimitClassError`] if a class is named [`DEFAULT_CLASS`], a
+    /// PeerId does not parse or a peer is listed in two classes.
+    pub fn new<'a>(
+        reservation_duration: Duration,
+        default: ClassLimits,
+        classes: impl IntoIterator<Item = (&'a str, ClassLimits, &'a [String])>,
+    ) -> Result<Self, LimitClassError> {
+        let mut limit_classes = Self {
+            reservation_duration,
+            default,
+            classes: BTreeMap::new(),
+            peers: HashMap::new(),
+        };
+        for (class, limits, peers) in classes {
+            if class == DEFAULT_CLASS {
+                return Err(LimitClassError::ReservedName);
+            }
+            for peer in peers {
+                let peer_id = peer
+                    .trim()
+                    .parse()
+                    .map_err(|_| LimitClassError::InvalidPeerId {
+                        class: class.to_string(),
+                        peer: peer.clone(),
+                    })?;
+                if let Some(first) = limit_classes.peers.insert(peer_id, class.to_string()) {
+                    return Err(LimitClassError::DuplicatePeer {
+                        peer: peer.clone(),
+                        first,
+                        second: class.to_string(),
+                    });
+                }
+            }
+            limit_classes.classes.insert(class.to_string(), limits);
+        }
+        Ok(limit_classes)
+    }
+
+    /// Whether no peer is assigned to a class other than [`DEFAULT_CLASS`].
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Name of the class `peer` belongs to.
+    pub fn class_of(&self, peer: &PeerId) -> &str {
+        self.peers.get(peer).map_or(DEFAULT_CLASS, String::as_str)
+    }
+
+    fn limits_of(&self, peer: &PeerId) -> ClassLimits {
+        self.peers
+            .get(peer)
+            .and_then(|class| self.classes.get(class))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    fn relay_limits(&self, limits: ClassLimits) -> relay::Limits {
+        fn unlimited_if_zero(limit: usize) -> usize {
+            if limit == 0 {
+                usize::MAX
+            } else {
+                limit
+            }
+        }
+        relay::Limits {
+            reservation_duration: self.reservation_duration,
+            max_reservations_per_peer: unlimited_if_zero(limits.max_reservations_per_peer),
+            max_circuits_per_peer: unlimited_if_zero(limits.max_circuits_per_peer),
+            max_circuit_duration: limits.max_circuit_duration,
+            max_circuit_bytes: limits.max_circuit_bytes,
+        }
+    }
+}
+
+impl relay::LimitSelector for LimitClasses {
+    fn reservation_limits(&mut self, peer: PeerId) -> Option<relay::Limits> {
+        Some(self.relay_limits(self.limits_of(&peer)))
+    }
+
+    fn circuit_limits(&mut self, src: PeerId, dst: PeerId) -> Option<relay::Limits> {
+        Some(self.relay_limits(self.limits_of(&src).min(self.limits_of(&dst))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::relay::LimitSelector;
+
+    const DEFAULT: ClassLimits = ClassLimits {
+        max_circuit_bytes: 1024 * 1024,
+        max_circuit_duration: Duration::from_secs(120),
+        max_circuits_per_peer: 16,
+        max_reservations_per_peer: 1,
+    };
+    const UNLIMITED: ClassLimits = ClassLimits {
+        max_circuit_bytes: 0,
+        max_circuit_duration: Duration::ZERO,
+        max_circuits_per_peer: 0,
+        max_reservations_per_peer: 0,
+    };
+
+    fn peer_id() -> PeerId {
+        PeerId::from(libp2p::identity::Keypair::generate_ed25519().public())
+    }
+
+    #[test]
+    fn test_circuit_gets_most_restrictive_class() {
+        let (app_a, app_b, stranger) = (peer_id(), peer_id(), peer_id());
+        let app_peers = [app_a.to_string(), app_b.to_string()];
+        let mut classes = LimitClasses::new(
+            Duration::from_secs(3600),
+            DEFAULT,
+            [("unlimited", UNLIMITED, &app_peers[..])],
+        )
+        .unwrap();
+
+        assert_eq!(classes.class_of(&app_a), "unlimited");
+        assert_eq!(classes.class_of(&stranger), DEFAULT_CLASS);
+
+        let between_app_peers = classes.circuit_limits(app_a, app_b).unwrap();
+        assert_eq!(between_app_peers.max_circuit_bytes, 0);
+        assert_eq!(between_app_peers.max_circuit_duration, Duration::ZERO);
+        assert_eq!(between_app_peers.max_circuits_per_peer, usize::MAX);
+
+        let to_app_peer = classes.circuit_limits(stranger, app_b).unwrap();
+        assert_eq!(to_app_peer.max_circuit_bytes, DEFAULT.max_circuit_bytes);
+        assert_eq!(to_app_peer.max_circuits_per_peer, 16);
+
+        let reservation = classes.reservation_limits(app_a).unwrap();
+        assert_eq!(reservation.max_reservations_per_peer, usize::MAX);
+        assert_eq!(reservation.reservation_duration, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_invalid_classes_are_rejected() {
+        let peer = [peer_id().to_string()];
+        assert!(matches!(
+            LimitClasses::new(
+                Duration::ZERO,
+                DEFAULT,
+                [(DEFAULT_CLASS, UNLIMITED, &peer[..])]
+            ),
+            Err(LimitClassError::ReservedName)
+        ));
+        assert!(matches!(
+            LimitClasses::new(
+                Duration::ZERO,
+                DEFAULT,
+                [
+                    ("trusted", DEFAULT, &peer[..]),
+                    ("unlimited", UNLIMITED, &peer[..])
+                ]
+            ),
+            Err(LimitClassError::DuplicatePeer { .. })
+        ));
+        assert!(matches!(
+            LimitClasses::new(
+                Duration::ZERO,
+                DEFAULT,
+                [("trusted", DEFAULT, &["nope".to_string()][..])]
+            ),
+            Err(LimitClassError::InvalidPeerId { .. })
+        ));
+    }
+}
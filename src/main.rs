@@ -1,17 +1,18 @@
 use futures::stream::StreamExt;
 use futures::future::Either;
 use libp2p::{
-    core::transport::{upgrade::Version, Transport as CoreTransport}, // Keep CoreTransport trait
+    core::transport::{upgrade::Version, ListenerId, OptionalTransport, Transport as CoreTransport}, // Keep CoreTransport trait
     identity::{Keypair},
-    noise, ping, relay, identify, autonat, dcutr,
-    swarm::{NetworkBehaviour, SwarmEvent},
+    ping, relay, identify, autonat, dcutr,
+    swarm::{behaviour::toggle::Toggle, dial_opts::DialOpts, DialError, ListenError, NetworkBehaviour, SwarmEvent},
     Multiaddr, PeerId, SwarmBuilder, StreamProtocol, // Add StreamProtocol
     quic, // <-- Import the quic module
     // Removed top-level Transport trait import
 };
-use std::{env, error::Error, time::Duration, str::FromStr};
+use std::{env, error::Error, time::{Duration, SystemTime, UNIX_EPOCH}, str::FromStr};
 use std::collections::{HashMap}; // Removed HashSet
 use std::sync::Arc;
+use std::convert::Infallible;
 use std::fs;
 use std::io::Write;
 use parking_lot::Mutex;
@@ -21,10 +22,30 @@ use warp::Filter;
 use dotenvy::dotenv;
 use libp2p::core::muxing::StreamMuxerBox;
 use base64::{engine::general_purpose::{STANDARD as base64_engine, STANDARD_NO_PAD}, Engine as _};
-use libp2p::gossipsub::{Behaviour as Gossipsub, MessageAuthenticity, Sha256Topic, Event as GossipsubEvent, ValidationMode};
+use libp2p::gossipsub::{Behaviour as Gossipsub, MessageAuthenticity, Event as GossipsubEvent, ValidationMode};
 use prost::Message;
 use bytes::Bytes;
-use rand;
+
+use clap::Parser;
+use rust_libp2p_relay::cli::{
+    Cli, Command, InspectKeyArgs, KeygenArgs, RunArgs, LEGACY_TEST_RELAY_ENV, PRIVATE_KEY_ENV,
+};
+use rust_libp2p_relay::addresses::AdvertisedAddresses;
+use rust_libp2p_relay::access_list::{self, AccessDenied};
+use rust_libp2p_relay::admission;
+use rust_libp2p_relay::config::{ListenConfig, RelayConfig};
+use rust_libp2p_relay::connection_limits::{self, LimitExceeded};
+use rust_libp2p_relay::discovery_codec::{self, DiscoveredPeer, DiscoveryFormat};
+use rust_libp2p_relay::message_validation::Verdict;
+use rust_libp2p_relay::proxy_protocol::ProxyProtocolTransport;
+use rust_libp2p_relay::relay_client::AutoRelay;
+use rust_libp2p_relay::relay_registry::RelayRegistry;
+use rust_libp2p_relay::topic_hashing::{self, TopicBridge};
+use rust_libp2p_relay::topic_interest::TopicInterest;
+use rust_libp2p_relay::topic_policy::Decision;
+use rust_libp2p_relay::webrtc_certificate::WebRtcCertificates;
+use rust_libp2p_relay::webrtc_signaling;
+use rust_libp2p_relay::wss;
 
 use libp2p_webrtc::tokio::{Transport as WebRtcTransport, Certificate as WebRtcCertificate};
 use libp2p_webtransport_websys::{Transport as WebTransport, Config as WebTransportConfig};
@@ -37,49 +58,22 @@ pub mod identity_proto {
 }
 
 // Add these imports at the top of the file
-use std::collections::hash_map::Entry;
-use rand::seq::SliceRandom;
 use libp2p::core::ConnectedPoint;
 
-// Add serde support for PeerId and Multiaddr
-use serde::{Deserialize, Serialize};
-
-// Define wrapper types for PeerId and Multiaddr that can be serialized
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SerializablePeer {
-    id: String,
-    addrs: Vec<String>,
-}
-
-impl From<(PeerId, Vec<Multiaddr>)> for SerializablePeer {
-    fn from((peer_id, addrs): (PeerId, Vec<Multiaddr>)) -> Self {
-        SerializablePeer {
-            id: peer_id.to_string(),
-            addrs: addrs.into_iter().map(|a| a.to_string()).collect(),
-        }
-    }
-}
-
-impl SerializablePeer {
-    fn to_peer_info(&self) -> Result<(PeerId, Vec<Multiaddr>), Box<dyn std::error::Error>> {
-        let peer_id = self.id.parse::<PeerId>()?;
-        let addrs = self.addrs
-            .iter()
-            .filter_map(|a| a.parse::<Multiaddr>().ok())
-            .collect();
-        
-        Ok((peer_id, addrs))
-    }
-}
-
 // Define the network behaviour combining multiple protocols
 #[derive(NetworkBehaviour)]
 // The `relay::Behaviour` emits `void::Void`, so we don't need a custom event for it.
 // The overall event type `RelayEvent` will be generated by the derive macro.
 #[behaviour(to_swarm = "RelayEvent")]
 struct RelayBehaviour {
+    // First, so denied and over-limit connections are refused before other behaviours see them
+    access: access_list::Behaviour,
+    limits: connection_limits::Behaviour,
+    admission: admission::Behaviour,
     // Note: relay::Behaviour<RelayReservation> is the full type, but Behaviour often suffices.
     relay: relay::Behaviour,
+    // Reservations on upstream relays, only with `relay_client.enabled`
+    relay_client: Toggle<relay::client::Behaviour>,
     ping: ping::Behaviour,
     identify: identify::Behaviour,
     pubsub: Gossipsub, // Added gossipsub behaviour
@@ -97,6 +91,8 @@ enum RelayEvent {
     Ping(ping::Event),
     Identify(identify::Event),
     Relay(relay::Event), // Added Relay variant
+    RelayClient(relay::client::Event),
+    Admission(admission::Event),
     Pubsub(GossipsubEvent), // Added PubSub variant
     Dcutr(dcutr::Event),       // Added DCUtR variant
     AutoNat(autonat::Event),   // Added AutoNat variant
@@ -111,6 +107,18 @@ impl From<relay::Event> for RelayEvent {
     }
 }
 
+impl From<relay::client::Event> for RelayEvent {
+    fn from(event: relay::client::Event) -> Self {
+        RelayEvent::RelayClient(event)
+    }
+}
+
+impl From<admission::Event> for RelayEvent {
+    fn from(event: admission::Event) -> Self {
+        RelayEvent::Admission(event)
+    }
+}
+
 impl From<ping::Event> for RelayEvent {
     fn from(event: ping::Event) -> Self {
         RelayEvent::Ping(event)
@@ -141,6 +149,13 @@ impl From<autonat::Event> for RelayEvent {
     }
 }
 
+// The access list and connection limits emit no events
+impl From<Infallible> for RelayEvent {
+    fn from(event: Infallible) -> Self {
+        match event {}
+    }
+}
+
 // NEW From impl for signaling behaviour
 impl From<webrtc_signaling::Event> for RelayEvent {
     fn from(event: webrtc_signaling::Event) -> Self {
@@ -152,11 +167,13 @@ impl From<webrtc_signaling::Event> for RelayEvent {
 // use libp2p::dcutr::Event as DcutrEvent;
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)] // The tests keep their place near the top of the file
 mod tests {
     use super::*;
     use base64::engine::general_purpose::STANDARD as base64_engine;
     use base64::Engine;
     use serial_test::serial; // Import the serial attribute
+    use rust_libp2p_relay::security::SecurityProtocol;
     // Removed duplicate super import
     use libp2p::{
         ping, identify, // Removed relay import as it's not used directly in tests now
@@ -266,11 +283,16 @@ mod tests {
            max_circuits_per_peer: 16,
            circuit_src_rate_limiters: Default::default(),
            reservation_rate_limiters: Default::default(),
+           limit_selector: None,
        };
        let autonat_config = autonat::Config::default();
 
        let _behaviour = RelayBehaviour {
+           access: access_list::Behaviour::new(Default::default()),
+           limits: connection_limits::Behaviour::new(Default::default()),
+           admission: admission::AdmissionPolicy::new(Default::default(), Default::default(), &[], &[], Duration::from_secs(60)).unwrap().1,
            relay: relay::Behaviour::new(local_peer_id, relay_config),
+           relay_client: Toggle::from(None),
            ping: ping::Behaviour::new(ping::Config::new()),
            identify: identify::Behaviour::new(identify_config.clone()),
            pubsub: Gossipsub::new(
@@ -283,7 +305,7 @@ mod tests {
            // webrtc: libp2p_webrtc::tokio::Behaviour::new(), // REMOVED - Type doesn't exist in 0.9.0-alpha
        };
 
-       assert!(true);
+       // Constructing the behaviour without panicking is the assertion here.
    }
 
    #[test]
@@ -432,11 +454,11 @@ mod tests {
             // 1. Setup: Generate keys and build swarms
             let relay_key = Keypair::generate_ed25519();
             let relay_peer_id = PeerId::from(relay_key.public());
-            let mut relay_swarm = build_swarm(relay_key, None).await.expect("Relay swarm build failed"); // No pubsub topics needed for this test
+            let mut relay_swarm = build_swarm(relay_key, &RelayConfig::default(), WebRtcCertificate::generate(&mut rand::thread_rng()).unwrap()).await.expect("Relay swarm build failed"); // No pubsub topics needed for this test
 
             let client_key = Keypair::generate_ed25519();
             let client_peer_id = PeerId::from(client_key.public());
-            let mut client_swarm = build_swarm(client_key, None).await.expect("Client swarm build failed"); // No pubsub topics needed
+            let mut client_swarm = build_swarm(client_key, &RelayConfig::default(), WebRtcCertificate::generate(&mut rand::thread_rng()).unwrap()).await.expect("Client swarm build failed"); // No pubsub topics needed
 
             // 2. Start Relay Listener
             relay_swarm.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).expect("Relay listen failed");
@@ -506,111 +528,267 @@ mod tests {
 
         }).await.expect("Test timed out"); // Panic if the outer timeout expires
     }
-}
 
-// Type alias for the shared state of listening addresses
-type ListeningAddresses = Arc<Mutex<Vec<Multiaddr>>>;
+    // Build a relay and a client from the given configs, dial over TCP and wait until both sides see the connection
+    async fn assert_client_connects(relay_config: RelayConfig, client_config: RelayConfig) {
+        tokio::time::timeout(Duration::from_secs(15), async {
+            let relay_key = Keypair::generate_ed25519();
+            let relay_peer_id = PeerId::from(relay_key.public());
+            let mut relay_swarm = build_swarm(relay_key, &relay_config, WebRtcCertificate::generate(&mut rand::thread_rng()).unwrap()).await.expect("Relay swarm build failed");
 
-// Function now returns a Result
-fn load_keypair_from_env() -> Result<Keypair, Box<dyn Error>> {
-    match env::var("CLEF_PRIVEE_RELAI") {
-        Ok(key_b64) => {
-            // 1. Decode Base64
-            let key_bytes = base64_engine.decode(key_b64.as_bytes())
-                .or_else(|_| STANDARD_NO_PAD.decode(key_b64.as_bytes()))
-                .map_err(|e| format!("Failed to decode CLEF_PRIVEE_RELAI from base64: {}", e))?;
-
-            // --- Attempt 1: Check if the decoded bytes look like the JS library's raw Ed25519 key (64 bytes: secret + public) ---
-            if key_bytes.len() == 64 {
-                info!("Decoded base64 data is 64 bytes long. Assuming JS format (secret + public). Attempting to load using first 32 bytes as Ed25519 secret key.");
-                // Extract the first 32 bytes, assuming they are the secret key.
-                let mut secret_bytes_array: [u8; 32] = key_bytes[0..32].try_into()
-                    .map_err(|_| "Internal error: Failed to extract first 32 bytes from 64-byte key data".to_string())?; // Should not fail
-
-                match Keypair::ed25519_from_bytes(&mut secret_bytes_array) {
-                    Ok(keypair) => {
-                        info!("Successfully loaded Ed25519 identity from CLEF_PRIVEE_RELAI (extracted 32 secret bytes from 64-byte JS format).");
-                        return Ok(keypair); // Success! Return early.
-                    }
-                    Err(e) => {
-                        warn!("Failed to create Ed25519 keypair from extracted 32 bytes (from 64-byte data): {}. Proceeding to other formats...", e);
-                        // Fall through to try other decoding methods
-                    }
+            let client_key = Keypair::generate_ed25519();
+            let client_peer_id = PeerId::from(client_key.public());
+            let mut client_swarm = build_swarm(client_key, &client_config, WebRtcCertificate::generate(&mut rand::thread_rng()).unwrap()).await.expect("Client swarm build failed");
+
+            relay_swarm.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).expect("Relay listen failed");
+            let relay_addr = loop {
+                if let Some(SwarmEvent::NewListenAddr { address, .. }) = relay_swarm.next().await {
+                    break address;
                 }
-            // --- Attempt 2: Check if the decoded bytes look like *just* the raw Ed25519 secret key (32 bytes) ---
-            } else if key_bytes.len() == 32 {
-                info!("Decoded base64 data is 32 bytes long. Attempting to load as raw Ed25519 secret key.");
-                let mut secret_bytes_array: [u8; 32] = key_bytes.as_slice().try_into()
-                    .map_err(|_| "Internal error: Failed to convert 32-byte Vec<u8> to [u8; 32]".to_string())?;
-
-                match Keypair::ed25519_from_bytes(&mut secret_bytes_array) {
-                    Ok(keypair) => {
-                        info!("Successfully loaded Ed25519 identity from CLEF_PRIVEE_RELAI (raw 32 secret bytes format).");
-                        return Ok(keypair); // Success! Return early.
-                    }
-                    Err(e) => {
-                        warn!("Failed to create Ed25519 keypair from 32-byte raw data: {}. Proceeding to other formats...", e);
-                        // Fall through
-                    }
+            };
+            client_swarm.dial(relay_addr).expect("Client dial failed");
+
+            let (mut client_connected, mut relay_saw_client) = (false, false);
+            while !(client_connected && relay_saw_client) {
+                tokio::select! {
+                    event = relay_swarm.select_next_some() => {
+                        if let SwarmEvent::ConnectionEstablished { peer_id, .. } = event {
+                            relay_saw_client |= peer_id == client_peer_id;
+                        }
+                    },
+                    event = client_swarm.select_next_some() => match event {
+                        SwarmEvent::ConnectionEstablished { peer_id, .. } => client_connected |= peer_id == relay_peer_id,
+                        SwarmEvent::OutgoingConnectionError { error, .. } => panic!("Client failed to connect: {}", error),
+                        _ => {}
+                    },
                 }
-            } else {
-                info!("Decoded base64 data is {} bytes long. Not treating as raw Ed25519 secret (32 or 64 bytes). Trying protobuf formats...", key_bytes.len());
             }
+        }).await.expect("Test timed out");
+    }
+
+    #[tokio::test]
+    async fn test_tls_only_peer_connects_to_relay() {
+        let mut client_config = RelayConfig::default();
+        client_config.transport.security = vec![SecurityProtocol::Tls];
+        assert_client_connects(RelayConfig::default(), client_config).await;
+    }
+
+    #[tokio::test]
+    async fn test_noise_only_peer_connects_to_relay() {
+        let mut client_config = RelayConfig::default();
+        client_config.transport.security = vec![SecurityProtocol::Noise];
+        assert_client_connects(RelayConfig::default(), client_config).await;
+    }
+
+    #[tokio::test]
+    async fn test_mplex_only_peer_connects_to_relay() {
+        let mut client_config = RelayConfig::default();
+        client_config.transport.yamux.enabled = false;
+        assert_client_connects(RelayConfig::default(), client_config).await;
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_header_sets_remote_address() {
+        let mut relay_config = RelayConfig::default();
+        relay_config.proxy_protocol.listen = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
 
-            // --- Attempt 3: Decode using standard libp2p Keypair protobuf encoding ---
-            match Keypair::from_protobuf_encoding(&key_bytes) {
-                Ok(keypair) => {
-                    info!("Successfully loaded identity from CLEF_PRIVEE_RELAI (standard libp2p protobuf format).");
-                    Ok(keypair)
+        tokio::time::timeout(Duration::from_secs(15), async {
+            let mut relay_swarm = build_swarm(Keypair::generate_ed25519(), &relay_config, WebRtcCertificate::generate(&mut rand::thread_rng()).unwrap()).await.expect("Relay swarm build failed");
+            relay_swarm.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).expect("Relay listen failed");
+            let port = loop {
+                if let Some(SwarmEvent::NewListenAddr { address, .. }) = relay_swarm.next().await {
+                    break address.iter().find_map(|p| match p {
+                        libp2p::multiaddr::Protocol::Tcp(port) => Some(port),
+                        _ => None,
+                    }).unwrap();
                 }
-                Err(libp2p_decode_err) => {
-                    // Standard decoding failed, log it and try the custom format
-                    warn!("Failed to decode CLEF_PRIVEE_RELAI as standard libp2p keypair ({}). Trying custom protobuf format...", libp2p_decode_err);
-
-                    // --- Attempt 3: Decode using custom identity.proto definition ---
-                    // Need Bytes::from for prost decoding. Clone key_bytes as it might be needed if this fails.
-                    match identity_proto::PrivateKey::decode(Bytes::from(key_bytes.clone())) {
-                        Ok(private_key_proto) => {
-                            // Custom protobuf decoded, now extract the key material
-                            match identity_proto::KeyType::try_from(private_key_proto.r#type) {
-                                Ok(identity_proto::KeyType::Ed25519) => {
-                                    if private_key_proto.data.len() == 32 {
-                                        let mut secret_bytes: [u8; 32] = private_key_proto.data.as_slice().try_into()
-                                            .map_err(|_| format!("Custom protobuf: Failed to convert data Vec<u8> (len {}) to [u8; 32]", private_key_proto.data.len()))?;
-                                        let keypair = Keypair::ed25519_from_bytes(&mut secret_bytes)
-                                            .map_err(|e| format!("Custom protobuf: Failed to create Ed25519 keypair from data bytes: {}", e))?;
-                                        info!("Successfully loaded Ed25519 identity from CLEF_PRIVEE_RELAI (custom protobuf format).");
-                                        Ok(keypair)
-                                    } else {
-                                        Err(format!("Custom protobuf: Extracted Ed25519 data is not 32 bytes long (found {} bytes)", private_key_proto.data.len()).into())
-                                    }
-                                }
-                                Ok(other_type) => Err(format!("Custom protobuf: Unsupported key type ({:?})", other_type).into()),
-                                Err(_) => Err(format!("Custom protobuf: Invalid key type enum value ({})", private_key_proto.r#type).into()),
+            };
+
+            let mut socket = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            tokio::io::AsyncWriteExt::write_all(&mut socket, format!("PROXY TCP4 203.0.113.9 127.0.0.1 40000 {}\r\n", port).as_bytes()).await.unwrap();
+
+            let send_back_addr = loop {
+                if let Some(SwarmEvent::IncomingConnection { send_back_addr, .. }) = relay_swarm.next().await {
+                    break send_back_addr;
+                }
+            };
+            assert_eq!(send_back_addr, "/ip4/203.0.113.9/tcp/40000".parse::<Multiaddr>().unwrap());
+        }).await.expect("Test timed out");
+    }
+
+    #[tokio::test]
+    async fn test_wss_listener_terminates_tls() {
+        let dir = tempfile::tempdir().unwrap();
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let certificate_file = dir.path().join("fullchain.pem");
+        let private_key_file = dir.path().join("privkey.pem");
+        fs::write(&certificate_file, generated.cert.pem()).unwrap();
+        fs::write(&private_key_file, generated.key_pair.serialize_pem()).unwrap();
+
+        let mut relay_config = RelayConfig::default();
+        relay_config.wss.certificate_file = Some(certificate_file);
+        relay_config.wss.private_key_file = Some(private_key_file);
+
+        tokio::time::timeout(Duration::from_secs(15), async {
+            let mut relay_swarm = build_swarm(Keypair::generate_ed25519(), &relay_config, WebRtcCertificate::generate(&mut rand::thread_rng()).unwrap()).await.expect("Relay swarm build failed");
+            relay_swarm.listen_on("/ip4/127.0.0.1/tcp/0/tls/ws".parse().unwrap()).expect("Relay listen failed");
+            let relay_addr = loop {
+                if let Some(SwarmEvent::NewListenAddr { address, .. }) = relay_swarm.next().await {
+                    break address;
+                }
+            };
+            assert!(relay_addr.to_string().ends_with("/tls/ws"), "unexpected listen address {}", relay_addr);
+            let port = relay_addr.iter().find_map(|p| match p {
+                libp2p::multiaddr::Protocol::Tcp(port) => Some(port),
+                _ => None,
+            }).unwrap();
+
+            // Plain rustls client trusting only the self-signed certificate
+            let expected = generated.cert.der().clone();
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(expected.clone()).unwrap();
+            let handshake = tokio::task::spawn_blocking(move || {
+                let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                    .with_safe_default_protocol_versions().unwrap()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth();
+                let mut connection = rustls::ClientConnection::new(Arc::new(client_config), "localhost".try_into().unwrap()).unwrap();
+                let mut socket = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+                while connection.is_handshaking() {
+                    connection.complete_io(&mut socket).expect("TLS handshake failed");
+                }
+                connection.peer_certificates().unwrap()[0].clone().into_owned()
+            });
+            tokio::pin!(handshake);
+            let served = loop {
+                tokio::select! {
+                    result = &mut handshake => break result.unwrap(),
+                    _ = relay_swarm.select_next_some() => {},
+                }
+            };
+            assert_eq!(served, expected);
+        }).await.expect("Test timed out");
+    }
+}
+
+// Type alias for the advertised addresses shared with the web server
+type SharedAdvertisedAddresses = Arc<Mutex<AdvertisedAddresses>>;
+// Reservations and circuits, shared with the web server
+type SharedRelayRegistry = Arc<Mutex<RelayRegistry>>;
+
+// Function now returns a Result
+// Env-only entry point; `run` goes through `load_keypair` with the value clap resolved.
+#[cfg(test)]
+fn load_keypair_from_env() -> Result<Keypair, Box<dyn Error>> {
+    match env::var(PRIVATE_KEY_ENV) {
+        Ok(key_b64) => load_keypair(Some(&key_b64)),
+        Err(env::VarError::NotPresent) => load_keypair(None),
+        Err(e) => {
+            // Other error reading environment variable
+            Err(format!("Error reading CLEF_PRIVEE_RELAI environment variable: {}", e).into())
+        }
+    }
+}
+
+// Load the node identity from a base64 key, or generate a random one when none is configured
+fn load_keypair(key_b64: Option<&str>) -> Result<Keypair, Box<dyn Error>> {
+    match key_b64 {
+        Some(key_b64) => decode_keypair(key_b64),
+        None => {
+            // No key configured, generate a new random keypair
+            info!("CLEF_PRIVEE_RELAI not set. Generating random identity.");
+            Ok(Keypair::generate_ed25519())
+        }
+    }
+}
+
+// Decode a base64 private key in any of the formats accepted for CLEF_PRIVEE_RELAI
+fn decode_keypair(key_b64: &str) -> Result<Keypair, Box<dyn Error>> {
+    // 1. Decode Base64
+    let key_bytes = base64_engine.decode(key_b64.as_bytes())
+        .or_else(|_| STANDARD_NO_PAD.decode(key_b64.as_bytes()))
+        .map_err(|e| format!("Failed to decode CLEF_PRIVEE_RELAI from base64: {}", e))?;
+
+    // --- Attempt 1: Check if the decoded bytes look like the JS library's raw Ed25519 key (64 bytes: secret + public) ---
+    if key_bytes.len() == 64 {
+        info!("Decoded base64 data is 64 bytes long. Assuming JS format (secret + public). Attempting to load using first 32 bytes as Ed25519 secret key.");
+        // Extract the first 32 bytes, assuming they are the secret key.
+        let mut secret_bytes_array: [u8; 32] = key_bytes[0..32].try_into()
+            .map_err(|_| "Internal error: Failed to extract first 32 bytes from 64-byte key data".to_string())?; // Should not fail
+
+        match Keypair::ed25519_from_bytes(&mut secret_bytes_array) {
+            Ok(keypair) => {
+                info!("Successfully loaded Ed25519 identity from CLEF_PRIVEE_RELAI (extracted 32 secret bytes from 64-byte JS format).");
+                return Ok(keypair); // Success! Return early.
+            }
+            Err(e) => {
+                warn!("Failed to create Ed25519 keypair from extracted 32 bytes (from 64-byte data): {}. Proceeding to other formats...", e);
+                // Fall through to try other decoding methods
+            }
+        }
+    // --- Attempt 2: Check if the decoded bytes look like *just* the raw Ed25519 secret key (32 bytes) ---
+    } else if key_bytes.len() == 32 {
+        info!("Decoded base64 data is 32 bytes long. Attempting to load as raw Ed25519 secret key.");
+        let mut secret_bytes_array: [u8; 32] = key_bytes.as_slice().try_into()
+            .map_err(|_| "Internal error: Failed to convert 32-byte Vec<u8> to [u8; 32]".to_string())?;
+
+        match Keypair::ed25519_from_bytes(&mut secret_bytes_array) {
+            Ok(keypair) => {
+                info!("Successfully loaded Ed25519 identity from CLEF_PRIVEE_RELAI (raw 32 secret bytes format).");
+                return Ok(keypair); // Success! Return early.
+            }
+            Err(e) => {
+                warn!("Failed to create Ed25519 keypair from 32-byte raw data: {}. Proceeding to other formats...", e);
+                // Fall through
+            }
+        }
+    } else {
+        info!("Decoded base64 data is {} bytes long. Not treating as raw Ed25519 secret (32 or 64 bytes). Trying protobuf formats...", key_bytes.len());
+    }
+
+    // --- Attempt 3: Decode using standard libp2p Keypair protobuf encoding ---
+    match Keypair::from_protobuf_encoding(&key_bytes) {
+        Ok(keypair) => {
+            info!("Successfully loaded identity from CLEF_PRIVEE_RELAI (standard libp2p protobuf format).");
+            Ok(keypair)
+        }
+        Err(libp2p_decode_err) => {
+            // Standard decoding failed, log it and try the custom format
+            warn!("Failed to decode CLEF_PRIVEE_RELAI as standard libp2p keypair ({}). Trying custom protobuf format...", libp2p_decode_err);
+
+            // --- Attempt 3: Decode using custom identity.proto definition ---
+            // Need Bytes::from for prost decoding. Clone key_bytes as it might be needed if this fails.
+            match identity_proto::PrivateKey::decode(Bytes::from(key_bytes.clone())) {
+                Ok(private_key_proto) => {
+                    // Custom protobuf decoded, now extract the key material
+                    match identity_proto::KeyType::try_from(private_key_proto.r#type) {
+                        Ok(identity_proto::KeyType::Ed25519) => {
+                            if private_key_proto.data.len() == 32 {
+                                let mut secret_bytes: [u8; 32] = private_key_proto.data.as_slice().try_into()
+                                    .map_err(|_| format!("Custom protobuf: Failed to convert data Vec<u8> (len {}) to [u8; 32]", private_key_proto.data.len()))?;
+                                let keypair = Keypair::ed25519_from_bytes(&mut secret_bytes)
+                                    .map_err(|e| format!("Custom protobuf: Failed to create Ed25519 keypair from data bytes: {}", e))?;
+                                info!("Successfully loaded Ed25519 identity from CLEF_PRIVEE_RELAI (custom protobuf format).");
+                                Ok(keypair)
+                            } else {
+                                Err(format!("Custom protobuf: Extracted Ed25519 data is not 32 bytes long (found {} bytes)", private_key_proto.data.len()).into())
                             }
                         }
-                        Err(custom_decode_err) => {
-                            // All decoding attempts failed (raw 64, raw 32, standard proto, custom proto)
-                            Err(format!("Failed to decode CLEF_PRIVEE_RELAI: Not valid raw 64-byte JS format, not valid raw 32-byte secret, Standard proto decode error: '{}', Custom proto decode error: '{}'", libp2p_decode_err, custom_decode_err).into())
-                        }
+                        Ok(other_type) => Err(format!("Custom protobuf: Unsupported key type ({:?})", other_type).into()),
+                        Err(_) => Err(format!("Custom protobuf: Invalid key type enum value ({})", private_key_proto.r#type).into()),
                     }
                 }
+                Err(custom_decode_err) => {
+                    // All decoding attempts failed (raw 64, raw 32, standard proto, custom proto)
+                    Err(format!("Failed to decode CLEF_PRIVEE_RELAI: Not valid raw 64-byte JS format, not valid raw 32-byte secret, Standard proto decode error: '{}', Custom proto decode error: '{}'", libp2p_decode_err, custom_decode_err).into())
+                }
             }
-        },
-        Err(env::VarError::NotPresent) => {
-            // Environment variable not set, generate a new random keypair
-            info!("CLEF_PRIVEE_RELAI not set. Generating random identity.");
-            Ok(Keypair::generate_ed25519())
-        },
-        Err(e) => {
-            // Other error reading environment variable
-            Err(format!("Error reading CLEF_PRIVEE_RELAI environment variable: {}", e).into())
         }
     }
 }
 
 // Helper function to build a configured Swarm
-async fn build_swarm(local_key: Keypair, pubsub_topics: Option<String>) -> Result<libp2p::swarm::Swarm<RelayBehaviour>, Box<dyn Error>> {
+async fn build_swarm(local_key: Keypair, config: &RelayConfig, webrtc_cert: WebRtcCertificate) -> Result<libp2p::swarm::Swarm<RelayBehaviour>, Box<dyn Error>> {
     let local_peer_id = PeerId::from(local_key.public());
     info!("Building swarm for Peer ID: {}", local_peer_id);
 
@@ -621,25 +799,43 @@ async fn build_swarm(local_key: Keypair, pubsub_topics: Option<String>) -> Resul
     )
     .with_agent_version(format!("rust-libp2p-relay/{}", env!("CARGO_PKG_VERSION")))
     .with_push_listen_addr_updates(true) // Enable IdentifyPush
-    .with_interval(Duration::from_secs(600));
+    .with_interval(Duration::from_secs(config.identify.interval_secs))
+    // Only send the external addresses, which `run_relay` keeps in sync with the advertised set
+    .with_hide_listen_addrs(true);
 
     // Note: We can't currently adjust the max message size and stream limits via the Config API.
 
+    // Multiplexers shared by the TCP and WebSocket upgrades: yamux first, mplex as fallback
+    let muxer_upgrade = config.transport.muxer_upgrade();
+    // Security protocols offered on TCP and WebSocket (Noise and/or TLS, in configured order)
+    let security_upgrade = config.transport.security_upgrade(&local_key)?;
+    let upgrade_timeout = Duration::from_secs(config.transport.timeout_secs);
+    // PROXY protocol on the TCP-based listeners selected in `proxy_protocol.listen`
+    let proxy_protocol = config.proxy_protocol.to_policy()?;
+    let new_tcp_transport = || libp2p::tcp::tokio::Transport::new(libp2p::tcp::Config::default().nodelay(true));
+
+    // Relay client transport and behaviour, to reserve on upstream relays when behind a NAT
+    let (relay_client_transport, relay_client) = if config.relay_client.enabled {
+        let (transport, behaviour) = relay::client::new(local_peer_id);
+        let transport = transport
+            .upgrade(Version::V1Lazy)
+            .authenticate(security_upgrade.clone())
+            .multiplex(muxer_upgrade.clone())
+            .timeout(upgrade_timeout)
+            .boxed();
+        (OptionalTransport::some(transport), Some(behaviour))
+    } else {
+        (OptionalTransport::none(), None)
+    };
+
     // Build the transport stack
     let transport = {
         // TCP Transport
-        let tcp_transport = libp2p::tcp::tokio::Transport::new(libp2p::tcp::Config::default().nodelay(true))
+        let tcp_transport = ProxyProtocolTransport::new(new_tcp_transport(), proxy_protocol.clone())
             .upgrade(Version::V1Lazy)
-            .authenticate(noise::Config::new(&local_key)?)
-            // Re-add increased Yamux limits
-            .multiplex({
-                let mut yamux_config = libp2p::yamux::Config::default();
-                yamux_config.set_receive_window_size(16 * 1024 * 1024); // 16 MiB
-                // set_max_num_streams is still valid if needed, but often window size is primary
-                // yamux_config.set_max_num_streams(2048);
-                yamux_config
-            })
-            .timeout(Duration::from_secs(20))
+            .authenticate(security_upgrade.clone())
+            .multiplex(muxer_upgrade.clone())
+            .timeout(upgrade_timeout)
             .boxed();
 
         // QUIC Transport
@@ -649,10 +845,7 @@ async fn build_swarm(local_key: Keypair, pubsub_topics: Option<String>) -> Resul
         // WebSocket Transport - configure with proper DNS handling
         let ws_transport = {
             // First create a TCP transport
-            let tcp_transport = libp2p::tcp::tokio::Transport::new(
-                libp2p::tcp::Config::default()
-                    .nodelay(true)
-            );
+            let tcp_transport = ProxyProtocolTransport::new(new_tcp_transport(), proxy_protocol.clone());
             
             // CRITICAL: DNS resolution must happen BEFORE WebSocket layer
             // so original DNS name is preserved for TLS hostname verification
@@ -666,23 +859,39 @@ async fn build_swarm(local_key: Keypair, pubsub_topics: Option<String>) -> Resul
             
             // Then wrap it with WebSocket 
             // Now DNS->TCP->WS ensures hostname is preserved for TLS validation
-            libp2p::websocket::WsConfig::new(dns_tcp)
+            let ws = libp2p::websocket::WsConfig::new(dns_tcp)
                 .upgrade(Version::V1Lazy)
-                .authenticate(noise::Config::new(&local_key)?)
-                // Re-add increased Yamux limits
-                .multiplex({
-                    let mut yamux_config = libp2p::yamux::Config::default();
-                    yamux_config.set_receive_window_size(16 * 1024 * 1024); // 16 MiB
-                    // set_max_num_streams is still valid if needed, but often window size is primary
-                    // yamux_config.set_max_num_streams(2048);
-                    yamux_config
-                })
-                .timeout(Duration::from_secs(20))
+                .authenticate(security_upgrade.clone())
+                .multiplex(muxer_upgrade.clone())
+                .timeout(upgrade_timeout)
+                .boxed();
+
+            // Native TLS termination for `/tls/ws` listeners when a certificate is configured.
+            // The plain WebSocket transport rejects those listen addresses, so they fall through here.
+            let wss = match config.wss.files() {
+                Some((certificate_file, private_key_file)) => {
+                    let resolver = Arc::new(wss::CertificateResolver::load(certificate_file, private_key_file)?);
+                    info!("Serving WSS with the certificate from {}", certificate_file.display());
+                    let server_config = resolver.server_config()?;
+                    resolver.spawn_reloader(Duration::from_secs(config.wss.reload_interval_secs));
+                    OptionalTransport::some(
+                        wss::listen_transport(ProxyProtocolTransport::new(new_tcp_transport(), proxy_protocol), server_config)
+                            .upgrade(Version::V1Lazy)
+                            .authenticate(security_upgrade)
+                            .multiplex(muxer_upgrade)
+                            .timeout(upgrade_timeout)
+                            .boxed(),
+                    )
+                }
+                None => OptionalTransport::none(),
+            };
+
+            ws.or_transport(wss)
+                .map(|either_output, _| either_output.into_inner())
                 .boxed()
         };
 
-        // WebRTC Transport
-        let webrtc_cert = WebRtcCertificate::generate(&mut rand::thread_rng())?;
+        // WebRTC Transport, with the persisted certificate so the certhash survives restarts
         let webrtc_transport = WebRtcTransport::new(local_key.clone(), webrtc_cert)
             // No extra mapping needed, the default is fine
             .boxed();
@@ -717,32 +926,35 @@ async fn build_swarm(local_key: Keypair, pubsub_topics: Option<String>) -> Resul
 
         // No need for a final DNS layer - it's already included in the individual transports
         combined_transport
+            .or_transport(relay_client_transport)
+            .map(|either_output, _| either_output.into_inner())
+            .boxed()
     };
 
     // Create the behaviour
     let behaviour = {
         // Configure GossipSub with parameters similar to the TypeScript implementation
         // Aligning closer to TS defaults/settings where possible
-        let score_params = libp2p::gossipsub::PeerScoreParams {
-            ip_colocation_factor_weight: 0.0, // Match TS: disable IP colocation penalty
-            ..Default::default()
-        };
-
-        let gossipsub_config = libp2p::gossipsub::ConfigBuilder::default()
-            .max_transmit_size(1024 * 1024) // Keep this, matched TS 1e6 approx and fixed earlier issues
+        let mut gossipsub_builder = libp2p::gossipsub::ConfigBuilder::default();
+        gossipsub_builder
+            .max_transmit_size(config.gossipsub.max_transmit_size) // Default matches TS 1e6 approx and fixed earlier issues
             // .heartbeat_interval(Duration::from_secs(20)) // Use Rust default (1s, matches TS default)
-            .validation_mode(ValidationMode::Permissive) // Matches TS `canRelayMessage: true`
+            .validation_mode(ValidationMode::Permissive) // Matches TS `canRelayMessage: true`; only governs signatures
             // .mesh_outbound_min(2) // Use Rust default (likely closer to TS default Dout=6 than our previous 2)
             // .mesh_n_low(2) // Use Rust default (likely closer to TS default Dlo=6 than our previous 2)
             // .allow_self_origin(true) // Use Rust default
             // .duplicate_cache_time(Duration::from_secs(1)) // Use Rust default (likely closer to TS default 120s)
-            .mesh_n(8) // Keep matching TS default D=8
-            .fanout_ttl(Duration::from_secs(60)) // Keep matching TS default 60s
-            .gossip_lazy(6) // Keep matching TS default D_lazy=6
-            .gossip_factor(0.25) // Keep matching TS default 0.25
-            // REMOVED: .peer_score_params(score_params) // Keeping default score params for now
+            .mesh_n(config.gossipsub.mesh_n) // Default matches TS D=8
+            .fanout_ttl(Duration::from_secs(config.gossipsub.fanout_ttl_secs)) // Default matches TS 60s
+            .gossip_lazy(config.gossipsub.gossip_lazy) // Default matches TS D_lazy=6
+            .gossip_factor(config.gossipsub.gossip_factor); // Default matches TS 0.25
+        if config.validation.enabled {
+            // Hold messages until `message_validation` reports on them
+            gossipsub_builder.validate_messages();
+        }
+        let gossipsub_config = gossipsub_builder
             .build()
-            .expect("Valid GossipSub configuration");
+            .map_err(|e| format!("Invalid GossipSub configuration: {}", e))?;
         
         // Create parameters similar to TypeScript version
         let mut gossipsub = Gossipsub::new(
@@ -751,45 +963,65 @@ async fn build_swarm(local_key: Keypair, pubsub_topics: Option<String>) -> Resul
         )?;
         
         // Subscribe to topics if provided
-        if let Some(topics_str) = &pubsub_topics {
-            for name in topics_str.split(',') {
-                let topic = Sha256Topic::new(name.trim());
-                if gossipsub.subscribe(&topic).is_err() {
-                     error!("Failed to subscribe to topic: {}", name.trim());
-                } else {
-                     info!("Peer {} subscribed to topic: {}", local_peer_id, name.trim());
-                }
+        let hashing = config.gossipsub.topic_hashing;
+        for name in &config.pubsub_discovery_topics {
+            let topic = topic_hashing::subscription(&hashing.hash(name));
+            if gossipsub.subscribe(&topic).is_err() {
+                 error!("Failed to subscribe to topic: {}", name);
+            } else {
+                 info!("Peer {} subscribed to topic: {}", local_peer_id, name);
             }
         }
 
         // Always subscribe to the réseau-constellation topic like in TypeScript implementation
-        let constellation_topic = Sha256Topic::new("réseau-constellation");
+        let constellation_topic = topic_hashing::subscription(&hashing.hash("réseau-constellation"));
         if gossipsub.subscribe(&constellation_topic).is_err() {
             error!("Failed to subscribe to réseau-constellation topic");
         } else {
             info!("Peer {} subscribed to topic: réseau-constellation", local_peer_id);
         }
 
-        // Configure the relay behaviour - add unlimited reservations like in TypeScript
-        let relay_config = relay::Config {
-             max_circuits: 32,
-             max_reservations: usize::MAX, // Set to unlimited like TypeScript's Infinity
-             reservation_duration: Duration::from_secs(60 * 60), // Default
-             max_circuit_duration: Duration::from_secs(2 * 60),
-             max_circuit_bytes: 1024 * 1024, // 1MB to match TypeScript's maxMessageSize
-             max_reservations_per_peer: 1,
-             max_circuits_per_peer: 16,
-             circuit_src_rate_limiters: Default::default(),
-             reservation_rate_limiters: Default::default(),
-        };
+        // Peer scoring, so peers forwarding invalid messages end up ignored; the relay's own topics are always scored
+        if config.gossipsub.peer_score.enabled {
+            let (score_params, score_thresholds) = config.gossipsub.peer_score.to_params(
+                |name| hashing.hash(name),
+                config
+                    .pubsub_discovery_topics
+                    .iter()
+                    .map(|name| hashing.hash(name))
+                    .chain([constellation_topic.hash()])
+                    .chain(TopicBridge::new(config.gossipsub.bridge_topics.iter().map(String::as_str)).topics().cloned()),
+            )?;
+            gossipsub.with_peer_score(score_params, score_thresholds)?;
+        }
+
+        // Configure the relay behaviour - unlimited reservations by default like in TypeScript
+        let mut relay_config = config.relay.to_relay_config()?;
+
+        // Open, allowlisted or token-gated reservations and circuits
+        let (admission_policy, admission) = config.admission.to_policy()?;
+        if !admission_policy.is_open() {
+            relay_config.reservation_rate_limiters.push(admission_policy.reservation_limiter());
+            relay_config.circuit_src_rate_limiters.push(admission_policy.circuit_limiter());
+        }
 
         // Configure AutoNAT
         let autonat_config = autonat::Config { 
             ..Default::default()
         };
 
+        // Allow/deny lists, reloaded from the file on the status tick
+        let access = match &config.access_list.file {
+            Some(path) => access_list::Behaviour::from_file(path)?,
+            None => access_list::Behaviour::new(Default::default()),
+        };
+
         RelayBehaviour {
+           access,
+           limits: connection_limits::Behaviour::new(config.connection_limits.clone()),
+           admission,
            relay: relay::Behaviour::new(local_peer_id, relay_config),
+           relay_client: Toggle::from(relay_client),
            ping: ping::Behaviour::new(ping::Config::new()),
            identify: identify::Behaviour::new(identify_config),
            pubsub: gossipsub,
@@ -806,23 +1038,73 @@ async fn build_swarm(local_key: Keypair, pubsub_topics: Option<String>) -> Resul
         .with_other_transport(|_| Ok(transport))? // Pass the built transport
         .with_behaviour(|_| Ok(behaviour))?
         .with_swarm_config(|c| c
-            .with_idle_connection_timeout(Duration::from_secs(60))
-            .with_max_negotiating_inbound_streams(10000)
+            .with_idle_connection_timeout(Duration::from_secs(config.swarm.idle_connection_timeout_secs))
+            .with_max_negotiating_inbound_streams(config.swarm.max_negotiating_inbound_streams)
         )
         .build();
 
     Ok(swarm)
 }
 
-// Function to test connection to a specific relay hostname
-async fn test_dns_relay_connection(hostname: &str) -> Result<(), Box<dyn Error>> {
-    info!("Testing connection to relay at {}", hostname);
-    
-    // Make sure we have a domain set in the environment
-    if env::var("DOMAINE").is_err() {
-        info!("Setting DOMAINE to {} for this test", hostname);
-        env::set_var("DOMAINE", hostname);
+// Start every active listener from the configuration.
+// Returns the listeners marked `required` so the event loop can abort if one of them closes with an error.
+fn start_listeners(
+    swarm: &mut libp2p::swarm::Swarm<RelayBehaviour>,
+    listen_config: &ListenConfig,
+) -> Result<HashMap<ListenerId, Multiaddr>, Box<dyn Error>> {
+    let mut required_listeners = HashMap::new();
+    for (address, required) in listen_config.active_addresses()? {
+        match swarm.listen_on(address.clone()) {
+            Ok(listener_id) => {
+                info!("Listening on {} with listener ID: {:?}{}", address, listener_id, if required { " (required)" } else { "" });
+                if required {
+                    required_listeners.insert(listener_id, address);
+                }
+            }
+            Err(e) if required => {
+                error!("Failed to listen on required address {}: {}", address, e);
+                return Err(format!("failed to listen on required address {}: {}", address, e).into());
+            }
+            Err(e) => warn!("Failed to listen on {}: {}", address, e),
+        }
+    }
+    Ok(required_listeners)
+}
+
+// Listen through each relay still lacking a reservation, which only happens once AutoNAT reported a private address.
+// Failed reservations close their listener and are retried from the status tick.
+fn reserve_on_relays(swarm: &mut libp2p::swarm::Swarm<RelayBehaviour>, auto_relay: &mut AutoRelay) {
+    for circuit_addr in auto_relay.missing_reservations() {
+        match swarm.listen_on(circuit_addr.clone()) {
+            Ok(listener_id) => {
+                info!("Reserving on relay: {}", circuit_addr);
+                auto_relay.reserving(circuit_addr, listener_id);
+            }
+            Err(e) => warn!("Failed to reserve on relay {}: {}", circuit_addr, e),
+        }
+    }
+}
+
+// Make the swarm's external addresses (what Identify sends, listen addresses being hidden)
+// match the advertised set exactly
+fn sync_external_addresses(swarm: &mut libp2p::swarm::Swarm<RelayBehaviour>, advertised: &AdvertisedAddresses) {
+    let wanted = advertised.addresses();
+    let stale: Vec<Multiaddr> = swarm.external_addresses().filter(|a| !wanted.contains(a)).cloned().collect();
+    for addr in stale {
+        info!("No longer advertising: {}", addr);
+        swarm.remove_external_address(&addr);
     }
+    for addr in wanted {
+        if !swarm.external_addresses().any(|a| *a == addr) {
+            info!("Advertising address: {}", addr);
+            swarm.add_external_address(addr);
+        }
+    }
+}
+
+// Function to test connection to a specific relay hostname (`probe` subcommand)
+async fn test_dns_relay_connection(hostname: &str, timeout_duration: Duration) -> Result<(), Box<dyn Error>> {
+    info!("Testing connection to relay at {}", hostname);
     
     // Generate a new key for this test
     let local_key = Keypair::generate_ed25519();
@@ -830,7 +1112,8 @@ async fn test_dns_relay_connection(hostname: &str) -> Result<(), Box<dyn Error>>
     info!("Test client peer ID: {}", local_peer_id);
     
     // Build a minimal swarm focused on just the connection test
-    let mut swarm = build_swarm(local_key, None).await?;
+    // Throwaway identity, so an ephemeral WebRTC certificate is fine too
+    let mut swarm = build_swarm(local_key, &RelayConfig::default(), WebRtcCertificate::generate(&mut rand::thread_rng())?).await?;
     
     // CRITICAL: Create relay address explicitly using dns4 protocol
     // This ensures DNS lookup happens at the TLS layer rather than at IP layer
@@ -855,7 +1138,6 @@ async fn test_dns_relay_connection(hostname: &str) -> Result<(), Box<dyn Error>>
     let mut connected = false;
     let mut timeout = tokio::time::interval(Duration::from_secs(1));
     let start_time = std::time::Instant::now();
-    let timeout_duration = Duration::from_secs(10);
     
     while !connected && start_time.elapsed() < timeout_duration {
         tokio::select! {
@@ -899,41 +1181,86 @@ async fn test_dns_relay_connection(hostname: &str) -> Result<(), Box<dyn Error>>
     }
 }
 
-// Define constants for Orbiter-related topics and protocols
+// Define constants for Orbiter-related protocols
 const RIFFCC_PROTOCOL: &str = "/riffcc/1.0.0";
-const ORBITER_DEVICE_DISCOVERY_TOPIC: &str = "orbiter._peer-discovery._p2p._pubsub";
-const ORBITER_CONTENT_DISCOVERY_TOPIC: &str = "orbiter._content-discovery._p2p._pubsub";
-const CONSTELLATION_PEER_DISCOVERY_TOPIC: &str = "constellation._peer-discovery._p2p._pubsub";
 
-// Pre-calculate topic hashes (assuming Sha256Topic is the correct type)
-// Removed redundant use statement
 use libp2p::gossipsub::{TopicHash};
-lazy_static::lazy_static! {
-    static ref CONSTELLATION_TOPIC_HASH: TopicHash = Sha256Topic::new(CONSTELLATION_PEER_DISCOVERY_TOPIC).hash();
-    static ref ORBITER_DEVICE_TOPIC_HASH: TopicHash = Sha256Topic::new(ORBITER_DEVICE_DISCOVERY_TOPIC).hash();
-    static ref ORBITER_CONTENT_TOPIC_HASH: TopicHash = Sha256Topic::new(ORBITER_CONTENT_DISCOVERY_TOPIC).hash();
-}
 
-mod webrtc_signaling; // <<< ADD THIS LINE
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     // Load environment variables from .env file, ignore errors (e.g., file not found)
+    // This must happen before parsing the CLI so that .env values act as flag defaults.
     dotenv().ok();
     env_logger::builder().filter_level(LevelFilter::Info).init();
 
-    // Check if certificate verification should be disabled (for development/testing only!)
-    let disable_cert_verification = env::var("DISABLE_CERT_VERIFICATION")
-        .map(|val| val.to_lowercase() == "true")
-        .unwrap_or(false);
-    
-    // Check if we're just testing a connection to a specific relay
-    let test_relay = env::var("TEST_RELAY").ok();
-    if let Some(hostname) = test_relay {
-        info!("Running in relay test mode for hostname: {}", hostname);
-        return test_dns_relay_connection(&hostname).await;
+    match Cli::parse().command_or_default() {
+        Command::Run(args) => run_relay(args).await,
+        Command::Keygen(args) => keygen(args),
+        Command::InspectKey(args) => inspect_key(args),
+        Command::Probe(args) => {
+            info!("Running in relay test mode for hostname: {}", args.relay);
+            test_dns_relay_connection(&args.relay, Duration::from_secs(args.timeout_secs)).await
+        }
+        Command::PrintConfig(args) => print_config(args),
     }
-    
+}
+
+// `keygen` subcommand: print (and optionally persist) a fresh identity
+fn keygen(args: KeygenArgs) -> Result<(), Box<dyn Error>> {
+    let keypair = Keypair::generate_ed25519();
+    let key_b64 = base64_engine.encode(keypair.to_protobuf_encoding()?);
+
+    println!("# Peer ID: {}", PeerId::from(keypair.public()));
+    println!("{}={}", PRIVATE_KEY_ENV, key_b64);
+
+    if args.write_env {
+        save_keypair_to_env_file(&key_b64)?;
+        eprintln!("Key appended to .env");
+    }
+    Ok(())
+}
+
+// `inspect-key` subcommand: show which identity a private key corresponds to
+fn inspect_key(args: InspectKeyArgs) -> Result<(), Box<dyn Error>> {
+    let keypair = decode_keypair(&args.private_key)?;
+    let public_key = keypair.public();
+
+    println!("Peer ID:    {}", PeerId::from(public_key.clone()));
+    println!("Key type:   {:?}", keypair.key_type());
+    println!("Public key: {}", base64_engine.encode(public_key.encode_protobuf()));
+    Ok(())
+}
+
+// `print-config` subcommand: dump the merged configuration `run` would use, as TOML
+fn print_config(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let config = RelayConfig::from_args(&args)?;
+    print!("{}", toml::to_string_pretty(&config)?);
+    Ok(())
+}
+
+// Append a base64-encoded key to the .env file (like the TypeScript implementation)
+fn save_keypair_to_env_file(key_b64: &str) -> std::io::Result<()> {
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(".env")
+        .and_then(|mut file| writeln!(file, "\n{}={}", PRIVATE_KEY_ENV, key_b64))
+}
+
+// `run` subcommand: start the relay node
+async fn run_relay(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let config = RelayConfig::from_args(&args)?;
+    if let Some(path) = &args.config {
+        info!("Loaded configuration from {}", path.display());
+    }
+    let disable_cert_verification = config.disable_cert_verification;
+
+    // TEST_RELAY used to silently turn the node into a connection probe
+    if let Ok(hostname) = env::var(LEGACY_TEST_RELAY_ENV) {
+        warn!("{} is ignored; use `rust-libp2p-relay probe {}` to test a relay connection", LEGACY_TEST_RELAY_ENV, hostname);
+    }
+
     if disable_cert_verification {
         warn!("⚠️ SECURITY WARNING: Certificate verification is DISABLED. This is insecure and should only be used for development/testing!");
         // Set environment variable for rustls to disable certificate verification
@@ -943,33 +1270,29 @@ async fn main() -> Result<(), Box<dyn Error>> {
     info!("Starting Rust libp2p relay node...");
 
     // --- Configuration Loading ---
-    let domain_name = env::var("DOMAINE").ok();
-    let pubsub_topics = env::var("RELAY_PUBSUB_PEER_DISCOVERY_TOPICS").ok();
-    let bootstrap_peers_str = env::var("RELAY_BOOTSTRAP_LIST").ok(); // Load bootstrap list
+    let domain_name = config.domain.clone();
+    let pubsub_topics = Some(config.pubsub_discovery_topics.join(",")).filter(|t| !t.is_empty());
+    let bootstrap_peers_str = Some(config.bootstrap_list.join(",")).filter(|p| !p.is_empty());
 
     // Log the loaded optional config
     if let Some(domain) = &domain_name {
         info!("Using domain: {}", domain);
     } else {
-        info!("No DOMAINE environment variable found.");
+        info!("No domain configured (--domain / DOMAINE).");
     }
     if let Some(topics) = &pubsub_topics {
         info!("PubSub discovery topics configured: {}", topics);
-        // TODO: Use these topics when pubsub is implemented
     } else {
-        info!("No RELAY_PUBSUB_PEER_DISCOVERY_TOPICS environment variable found.");
+        info!("No pubsub discovery topics configured (--discovery-topics / RELAY_PUBSUB_PEER_DISCOVERY_TOPICS), peer discovery disabled.");
     }
     if let Some(peers) = &bootstrap_peers_str {
         info!("Bootstrap peers configured: {}", peers);
     } else {
-        info!("No RELAY_BOOTSTRAP_LIST environment variable found.");
+        info!("No bootstrap peers configured (--bootstrap / RELAY_BOOTSTRAP_LIST).");
     }
 
-    // Create shared state for listening addresses
-    let listening_addresses: ListeningAddresses = Arc::new(Mutex::new(Vec::new()));
-
     // Create keypair for the node's identity, handling potential errors
-    let local_key = match load_keypair_from_env() {
+    let local_key = match load_keypair(args.private_key.as_deref()) {
         Ok(kp) => kp,
         Err(e) => {
             // Log the specific error and exit
@@ -979,19 +1302,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
     };
 
     // If this was a newly generated keypair, save it to .env file (like TypeScript implementation)
-    if env::var("CLEF_PRIVEE_RELAI").is_err() {
+    if args.private_key.is_none() {
         match local_key.to_protobuf_encoding() {
             Ok(key_bytes) => {
                 // Encode to base64
                 let key_b64 = base64_engine.encode(&key_bytes);
                 
                 // Append to .env file
-                if let Err(e) = fs::OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(".env")
-                    .and_then(|mut file| writeln!(file, "\nCLEF_PRIVEE_RELAI={}", key_b64)) 
-                {
+                if let Err(e) = save_keypair_to_env_file(&key_b64) {
                     warn!("Failed to save generated keypair to .env file: {}", e);
                 } else {
                     info!("New keypair saved to .env file for future use.");
@@ -1004,38 +1322,39 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let local_peer_id = PeerId::from(local_key.public());
     info!("Local peer ID: {}", local_peer_id);
 
-    // Pre-populate the listening addresses with domain addresses if domain is set
-    if let Some(domain) = &domain_name {
-        let mut addrs = listening_addresses.lock();
-        // Add WSS and WebRTC domain addresses explicitly
-        let local_peer_id_str = local_peer_id.to_string();
-        
-        // Add WSS address
-        if let Ok(wss_addr) = format!("/dns4/{}/tcp/443/wss/p2p/{}", domain, local_peer_id_str).parse::<Multiaddr>() {
-            addrs.push(wss_addr);
-            info!("PRE-ADDED WSS DOMAIN ADDRESS to listening addresses: /dns4/{}/tcp/443/wss/p2p/{}", domain, local_peer_id_str);
-        } else {
-            error!("Failed to parse WSS domain address");
-        }
-        
-        // Add WebRTC address
-        if let Ok(webrtc_addr) = format!("/dns4/{}/udp/443/webrtc-direct/p2p/{}", domain, local_peer_id_str).parse::<Multiaddr>() {
-            addrs.push(webrtc_addr);
-            info!("PRE-ADDED WebRTC DOMAIN ADDRESS to listening addresses: /dns4/{}/udp/443/webrtc-direct/p2p/{}", domain, local_peer_id_str);
-        } else {
-            error!("Failed to parse WebRTC domain address");
-        }
-        
-        info!("Pre-populated listening_addresses with {} domain-specific addresses", addrs.len());
+    // Single source of truth for what Identify and /adresses advertise
+    let announce = config.announce_addresses()?;
+    if announce.is_empty() {
+        info!("No announce addresses configured, advertising listen addresses.");
+    } else {
+        info!("Announcing {} configured addresses instead of listen addresses.", announce.len());
     }
+    let advertised_addresses: SharedAdvertisedAddresses = Arc::new(Mutex::new(AdvertisedAddresses::new(
+        local_peer_id,
+        announce,
+        config.no_announce_addresses()?,
+    ).with_domain(domain_name.clone())));
+    let relay_registry: SharedRelayRegistry = Arc::new(Mutex::new(RelayRegistry::new(
+        Duration::from_secs(config.relay.reservation_duration_secs),
+    )));
+
+    // Load the persisted WebRTC certificate so the webrtc-direct certhash is stable
+    let mut webrtc_certificates = WebRtcCertificates::load(&config.webrtc, args.webrtc_certificate.as_deref(), SystemTime::now())?;
+    info!("WebRTC certhashes: {:?}", webrtc_certificates.certhashes());
+    if let Some(rotate_at) = webrtc_certificates.rotate_at() {
+        info!("WebRTC certificate rotates at {} (unix time)", rotate_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+    }
+    advertised_addresses.lock().set_certhashes(webrtc_certificates.certhashes());
+    let mut rotation_warned = false;
 
     // Build the Swarm
-    let mut swarm = build_swarm(local_key.clone(), pubsub_topics).await?;
+    let mut swarm = build_swarm(local_key.clone(), &config, webrtc_certificates.certificate()).await?;
     let always_relay: Vec<String> = vec!["réseau-constellation".to_string()];
+    let hashing = config.gossipsub.topic_hashing;
 
     // Explicitly subscribe to always_relay topics
     for topic_name in &always_relay {
-        let topic = Sha256Topic::new(topic_name.clone());
+        let topic = topic_hashing::subscription(&hashing.hash(topic_name));
         if let Err(e) = swarm.behaviour_mut().pubsub.subscribe(&topic) {
             error!("Failed to subscribe to initial always_relay topic {}: {}", topic_name, e);
         } else {
@@ -1043,88 +1362,22 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    // Define listening addresses
-    // Listen on all interfaces for TCP (port 0 asks OS for a free port)
-    let listen_addr_tcp = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>()?;
-    swarm.listen_on(listen_addr_tcp)?;
-
-    // Listen on WebSocket port 12345 for nginx to connect to (but don't advertise it externally)
-    let listen_addr_ws = "/ip4/0.0.0.0/tcp/12345/ws".parse::<Multiaddr>()?;
-    swarm.listen_on(listen_addr_ws)?;
-    info!("Listening on WebSocket port 12345 for internal nginx connections (not advertised externally)");
-
-    // Also listen on WebRTC multiaddr for direct peer connections
-    match swarm.listen_on("/ip4/0.0.0.0/udp/0/webrtc-direct".parse()?) {
-        Ok(listener_id) => info!("Listening on WebRTC-direct with listener ID: {:?}", listener_id),
-        Err(e) => warn!("Failed to listen on WebRTC-direct: {}", e),
-    }
-    
-    // Listen on QUIC v1 on UDP/443
-    match swarm.listen_on("/ip4/0.0.0.0/udp/443/quic-v1".parse()?) {
-        Ok(listener_id) => info!("Listening on QUIC v1 (UDP/443) with listener ID: {:?}", listener_id),
-        Err(e) => warn!("Failed to listen on QUIC v1 (UDP/443): {}", e),
-    }
+    // Start the configured listeners; only those marked `required` abort startup on failure
+    let required_listeners = start_listeners(&mut swarm, &config.listen)?;
 
-    // Listen on WebTransport on UDP/443
-    match swarm.listen_on("/ip4/0.0.0.0/udp/443/webtransport".parse()?) { // Corrected: Removed /quic-v1
-        Ok(listener_id) => info!("Listening on WebTransport (UDP/443) with listener ID: {:?}", listener_id),
-        Err(e) => warn!("Failed to listen on WebTransport (UDP/443): {}", e),
-    }
-
-    // Also listen on generic shorthand transports (like JS defaults)
-    match swarm.listen_on("/webrtc".parse()?) {
-        Ok(listener_id) => info!("Listening on /webrtc with listener ID: {:?}", listener_id),
-        Err(e) => warn!("Failed to listen on /webrtc: {}", e),
-    }
-    match swarm.listen_on("/webtransport".parse()?) {
-        Ok(listener_id) => info!("Listening on /webtransport with listener ID: {:?}", listener_id),
-        Err(e) => warn!("Failed to listen on /webtransport: {}", e),
-    }
-    match swarm.listen_on("/p2p-circuit".parse()?) {
-        Ok(listener_id) => info!("Listening on /p2p-circuit with listener ID: {:?}", listener_id),
-        Err(e) => warn!("Failed to listen on /p2p-circuit: {}", e),
-    }
-
-    // If a domain name is provided, add external addresses for Nginx proxying
-    if let Some(domain) = &domain_name {
-        // Add WSS over 443 address (for secure WebSocket connections through Nginx)
-        let wss_addr = format!("/dns4/{}/tcp/443/wss/p2p/{}", domain, local_peer_id).parse::<Multiaddr>();
-        match wss_addr {
-            Ok(addr) => {
-                swarm.add_external_address(addr.clone());
-                info!("Advertising WSS address: {}", addr);
-            }
-            Err(e) => {
-                error!("Failed to parse WSS address from domain {}: {}", domain, e);
-            }
-        }
-
-        // Add QUIC v1 DNS address
-        let quic_addr = format!("/dns4/{}/udp/443/quic-v1/p2p/{}", domain, local_peer_id).parse::<Multiaddr>();
-        match quic_addr {
-            Ok(addr) => {
-                swarm.add_external_address(addr.clone());
-                info!("Advertising QUIC v1 DNS address: {}", addr);
-            }
-            Err(e) => {
-                error!("Failed to parse QUIC v1 DNS address from domain {}: {}", domain, e);
-            }
-        }
-
-        // Add WebTransport DNS address
-        let wt_addr = format!("/dns4/{}/udp/443/webtransport/p2p/{}", domain, local_peer_id).parse::<Multiaddr>();
-        match wt_addr {
-            Ok(addr) => {
-                swarm.add_external_address(addr.clone());
-                info!("Advertising WebTransport DNS address: {}", addr);
-            }
-            Err(e) => {
-                error!("Failed to parse WebTransport DNS address from domain {}: {}", domain, e);
-            }
+    // Relays to reserve on should AutoNAT find us behind a NAT
+    let mut auto_relay = if config.relay_client.enabled {
+        let (auto_relay, skipped) = AutoRelay::new(local_peer_id, &config.relay_client_relays()?);
+        for relay in skipped {
+            warn!("Not reserving on relay {}: it does not end in another peer's /p2p/<PeerId>", relay);
         }
+        Some(auto_relay)
     } else {
-        info!("No domain name provided. Only advertising direct IP-based addresses.");
-    }
+        None
+    };
+
+    // Announced addresses are known up front; listen addresses follow as they are reported
+    sync_external_addresses(&mut swarm, &advertised_addresses.lock());
 
     // --- Bootstrap Dialing ---
     // Parse bootstrap peers AFTER starting listeners
@@ -1197,29 +1450,70 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // swarm.listen_on("/ip4/0.0.0.0/udp/443/quic-v1/webtransport".parse()?)?;
 
     // Clone Arc for the web server task
-    let server_listening_addresses = listening_addresses.clone();
-    let server_local_peer_id = local_peer_id.to_string(); // Clone peer ID for the web server
+    let server_advertised_addresses = advertised_addresses.clone();
+    let admin_relay_registry = relay_registry.clone();
+    let admission_policy = swarm.behaviour().admission.policy().clone();
+
+    // Announcements are read from and published to the configured discovery topics, joined in `build_swarm`
+    let discovery_topics: Vec<TopicHash> = config
+        .pubsub_discovery_topics
+        .iter()
+        .map(|name| hashing.hash(name))
+        .collect();
+
+    // Bridged topics are joined under both hash forms, so JS and Rust peers meet
+    let mut topic_bridge = TopicBridge::new(config.gossipsub.bridge_topics.iter().map(String::as_str));
+    for topic in topic_bridge.topics() {
+        if let Err(e) = swarm.behaviour_mut().pubsub.subscribe(&topic_hashing::subscription(topic)) {
+            error!("Failed to subscribe to bridged topic {}: {}", topic, e);
+        } else {
+            info!("Bridging topic {}", topic);
+        }
+    }
 
-    // Set up peer discovery via PubSub for constellation peers
-    let peer_disc_topic = Sha256Topic::new(CONSTELLATION_PEER_DISCOVERY_TOPIC);
-    info!("Peer {} subscribed to topic: {}", local_peer_id, peer_disc_topic);
-    swarm.behaviour_mut().pubsub.subscribe(&peer_disc_topic).expect("Failed to subscribe to peer discovery topic");
-    
-    // Also subscribe to Orbiter-specific discovery topics
-    let orbiter_disc_topic = Sha256Topic::new(ORBITER_DEVICE_DISCOVERY_TOPIC);
-    info!("Peer {} subscribed to Orbiter device discovery topic: {}", local_peer_id, orbiter_disc_topic);
-    swarm.behaviour_mut().pubsub.subscribe(&orbiter_disc_topic).expect("Failed to subscribe to Orbiter device discovery topic");
-    
-    let orbiter_content_topic = Sha256Topic::new(ORBITER_CONTENT_DISCOVERY_TOPIC);
-    info!("Peer {} subscribed to Orbiter content discovery topic: {}", local_peer_id, orbiter_content_topic);
-    swarm.behaviour_mut().pubsub.subscribe(&orbiter_content_topic).expect("Failed to subscribe to Orbiter content discovery topic");
+    // Discovery messages are parsed whichever form of a bridged topic they arrived on
+    let with_counterparts = |topics: Vec<TopicHash>| -> Vec<TopicHash> {
+        let counterparts: Vec<TopicHash> = topics.iter().filter_map(|topic| topic_bridge.counterpart(topic).cloned()).collect();
+        topics.into_iter().chain(counterparts).collect()
+    };
+    let discovery_topic_hashes = with_counterparts(discovery_topics.clone());
+
+    // The relay's own topics: always mirrored, never left and outside the mirror cap
+    let own_topics: Vec<TopicHash> = always_relay
+        .iter()
+        .chain(&config.pubsub_discovery_topics)
+        .map(|name| hashing.hash(name))
+        .chain(topic_bridge.topics().cloned())
+        .collect();
+    // Topics mirrored for peers are left once nobody connected wants them
+    let mut topic_interest = TopicInterest::new(
+        Duration::from_secs(config.mirror.unsubscribe_grace_secs),
+        own_topics.clone(),
+    );
+    // Which topics peers can make the relay mirror, and how many at once
+    let mut topic_policy = config.mirror.to_policy()?.with_exempt(own_topics);
+    // Checks received messages must pass before gossipsub forwards them
+    let mut message_validation = if config.validation.enabled {
+        let validation = config.validation.to_pipeline(discovery_topic_hashes.clone())?;
+        info!("Validating gossipsub messages with {} validators", validation.len());
+        Some(validation)
+    } else {
+        warn!("Message validation disabled, forwarding every gossipsub message");
+        None
+    };
 
-    // Track last time we published peer info
-    let mut last_peer_discovery = std::time::Instant::now();
-    let peer_discovery_interval = Duration::from_secs(60); // Publish every minute
+    // Discovery topics come due on their own task; announcements are published from this loop
+    let mut discovery_ticks = config
+        .discovery
+        .to_schedule(
+            &config.pubsub_discovery_topics,
+            |name| hashing.hash(name),
+            std::time::Instant::now(),
+        )?
+        .spawn();
     
     // Store peer IDs and their multiaddresses for discovery
-    let mut known_peers: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+    let mut known_peers = config.address_book.to_address_book();
 
     // Spawn the web server task
     tokio::spawn(async move {
@@ -1230,101 +1524,63 @@ async fn main() -> Result<(), Box<dyn Error>> {
         let index_route = warp::path::end() // Match the root path "/"
             .map(move || warp::reply::html(index_html_content));
 
-        // Route for serving listening addresses at /adresses
-        let server_peer_id = server_local_peer_id.clone(); // Clone for the closure
+        // Route for serving the advertised addresses at /adresses (same set Identify sends)
         let addresses_route = warp::path("adresses")
             .and(warp::get()) // Match GET requests
             .map(move || {
-                let addrs = server_listening_addresses.lock();
-                
-                // Get the domain name from environment
-                let domain_name = std::env::var("DOMAINE").ok();
-                
-                // Log all addresses for debugging
-                info!("All available addresses before filtering (count: {}):", addrs.len());
-                for (i, addr) in addrs.iter().enumerate() {
-                    info!("  {}: {}", i, addr);
-                }
+                let addrs = server_advertised_addresses.lock().addresses();
+                debug!("Serving {} advertised addresses: {:?}", addrs.len(), addrs);
+                warp::reply::json(&addrs)
+            });
 
-                // Our filtered addresses list
-                let filtered_addrs: Vec<Multiaddr>;
-                
-                // If we have a domain, ONLY use addresses with that domain
-                if let Some(domain) = &domain_name {
-                    info!("Looking for addresses with domain: {}", domain);
-                    
-                    // Get all addresses containing the domain
-                    let domain_addrs: Vec<_> = addrs.iter()
-                        .filter(|addr| addr.to_string().contains(domain))
-                        .cloned()
-                        .collect();
-                    
-                    if !domain_addrs.is_empty() {
-                        // We found domain addresses, ONLY use these
-                        info!("Found {} addresses with domain {}", domain_addrs.len(), domain);
-                        filtered_addrs = domain_addrs;
-                    } else {
-                        // No domain addresses found - this shouldn't happen now that we pre-populate them,
-                        // but just in case, recreate and use them
-                        warn!("NO DOMAIN ADDRESSES FOUND FOR: {}", domain);
-                        
-                        // Create minimal set with just domain addresses
-                        let mut manual_addrs = Vec::new();
-                        
-                        // Add WSS address
-                        if let Ok(wss_addr) = format!("/dns4/{}/tcp/443/wss/p2p/{}", 
-                                                    domain, server_peer_id).parse::<Multiaddr>() {
-                            manual_addrs.push(wss_addr.clone());
-                            error!("MANUALLY ADDING MISSING WSS ADDRESS: {}", wss_addr);
-                        }
-                        
-                        // Add WebRTC address
-                        if let Ok(webrtc_addr) = format!("/dns4/{}/udp/443/webrtc-direct/p2p/{}", 
-                                                      domain, server_peer_id).parse::<Multiaddr>() {
-                            manual_addrs.push(webrtc_addr.clone());
-                            error!("MANUALLY ADDING MISSING WEBRTC ADDRESS: {}", webrtc_addr);
-                        }
-                        
-                        filtered_addrs = manual_addrs;
+        // Route for handing in an access token before reserving (token admission mode)
+        let token_route = warp::path!("reservations" / "token")
+            .and(warp::post())
+            .and(warp::body::content_length_limit(4096))
+            .and(warp::body::bytes())
+            .map(move |body: bytes::Bytes| {
+                let token = String::from_utf8_lossy(&body);
+                match admission_policy.submit_token(&token, std::time::SystemTime::now()) {
+                    Ok((peer_id, expires_at)) => {
+                        info!("Access token accepted for {}", peer_id);
+                        let expires_at = expires_at.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({ "peer": peer_id.to_string(), "expires_at": expires_at })),
+                            warp::http::StatusCode::OK,
+                        )
                     }
-                } else {
-                    // No domain set, use regular filtering logic
-                    filtered_addrs = addrs.iter()
-                        .filter(|addr| {
-                            let addr_str = addr.to_string();
-                            let is_secure = addr_str.contains("/wss/") || addr_str.contains("/webrtc");
-                            let is_external = !addr_str.contains("/ip4/0.0.0.0") && 
-                                              !addr_str.contains("/ip4/127.0.0.1");
-                            is_secure && is_external
-                        })
-                        .cloned()
-                        .collect();
-                }
-                
-                // Log what we're advertising
-                info!("Advertising {} filtered addresses:", filtered_addrs.len());
-                for (i, addr) in filtered_addrs.iter().enumerate() {
-                    info!("  {}: {}", i, addr);
-                }
-                
-                if let Some(domain) = &domain_name {
-                    if filtered_addrs.iter().any(|addr| addr.to_string().contains(domain)) {
-                        info!("✓ Domain {} addresses are included in advertised multiaddrs", domain);
-                    } else {
-                        error!("✗ CRITICAL ERROR: No addresses with domain {} in advertised multiaddrs!", domain);
+                    Err(e) => {
+                        debug!("Access token refused: {}", e);
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+                            warp::http::StatusCode::FORBIDDEN,
+                        )
                     }
                 }
-                
-                warp::reply::json(&filtered_addrs)
             });
 
-        let routes = index_route.or(addresses_route);
+        let routes = index_route.or(addresses_route).or(token_route);
 
         warp::serve(routes)
             .run(([0, 0, 0, 0], 8000)) // Listen on all interfaces, port 8000
             .await;
     });
 
+    // Spawn the admin server task, apart from the public port since it lists every reserved peer
+    if config.admin.enabled {
+        let admin_listen = config.admin.listen;
+        tokio::spawn(async move {
+            info!("Starting admin server on {}...", admin_listen);
+
+            // Route for the live reservations and circuits, for support staff
+            let relay_route = warp::path!("relay")
+                .and(warp::get())
+                .map(move || warp::reply::json(&admin_relay_registry.lock().snapshot(std::time::SystemTime::now())));
+
+            warp::serve(relay_route).run(admin_listen).await;
+        });
+    }
+
     // Create a periodic timer for status logging
     let mut status_interval = interval(Duration::from_secs(30));
     // Initial status log to avoid waiting for first interval tick
@@ -1346,16 +1602,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // --- RE-ADD Log Topic Hashes for Debugging ---
     {
-        use libp2p::gossipsub::Sha256Topic;
-
-        let constellation_topic = Sha256Topic::new(CONSTELLATION_PEER_DISCOVERY_TOPIC);
-        let orbiter_device_topic = Sha256Topic::new(ORBITER_DEVICE_DISCOVERY_TOPIC);
-        let orbiter_content_topic = Sha256Topic::new(ORBITER_CONTENT_DISCOVERY_TOPIC);
-
-        info!("DEBUG Topic Hashes (Display Format):");
-        info!("  Constellation Peer Discovery: {}", constellation_topic.hash());
-        info!("  Orbiter Device Discovery:     {}", orbiter_device_topic.hash());
-        info!("  Orbiter Content Discovery:    {}", orbiter_content_topic.hash());
+        info!("DEBUG Topic Hashes ({:?} hashing):", hashing);
+        for (name, topic) in config.pubsub_discovery_topics.iter().zip(&discovery_topics) {
+            info!("  {}: {}", name, topic);
+        }
+        if !config.gossipsub.bridge_topics.is_empty() {
+            info!("  Bridged to {:?} hashing:      {:?}", hashing.other(), config.gossipsub.bridge_topics);
+        }
     }
     // --- End Log Topic Hashes ---
 
@@ -1367,9 +1620,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 let info = swarm.network_info();
                 let counters = info.connection_counters();
                 let peers: Vec<_> = swarm.connected_peers().cloned().collect();
-                // Log mesh peers for the discovery topic
-                // Corrected: Pass the hash of the topic, not the topic itself
-                let mesh_peers_count = swarm.behaviour().pubsub.mesh_peers(&peer_disc_topic.hash()).count();
+                // Log distinct mesh peers across the discovery topics
+                let mesh_peers_count = discovery_topics
+                    .iter()
+                    .flat_map(|topic| swarm.behaviour().pubsub.mesh_peers(topic))
+                    .collect::<std::collections::HashSet<_>>()
+                    .len();
                 info!(
                     "Status: Connected Peers: {} {:?}, Discovery Mesh Peers: {}, Connections: {{ pending_in: {}, pending_out: {}, established_in: {}, established_out: {}, established: {} }}",
                     peers.len(),
@@ -1381,19 +1637,136 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     counters.num_established_outgoing(),
                     counters.num_established()
                 );
+                let denied: Vec<String> = swarm.behaviour().access.denied_counts().iter().map(|(reason, count)| format!("{}: {}", reason, count))
+                    .chain(swarm.behaviour().limits.denied_counts().iter().map(|(kind, count)| format!("{}: {}", kind, count)))
+                    .collect();
+                if !denied.is_empty() {
+                    info!("Connections refused: {{ {} }}", denied.join(", "));
+                }
+                // Lowest scores first, so misbehaving peers stand out
+                let mut scores: Vec<(f64, PeerId)> = peers
+                    .iter()
+                    .filter_map(|peer| swarm.behaviour().pubsub.peer_score(peer).map(|score| (score, *peer)))
+                    .collect();
+                if !scores.is_empty() {
+                    scores.sort_by(|a, b| a.0.total_cmp(&b.0));
+                    let scores: Vec<String> = scores.iter().map(|(score, peer)| format!("{}: {:.2}", peer, score)).collect();
+                    info!("Peer scores: {{ {} }}", scores.join(", "));
+                }
+
+                // Pick up edits to the access list file
+                match swarm.behaviour_mut().access.reload_if_changed() {
+                    Ok(true) => {
+                        let (deny, allow) = swarm.behaviour().access.list().active_len(SystemTime::now());
+                        info!("Reloaded access list: {} deny and {} allow entries in force", deny, allow);
+                    }
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to reload the access list, keeping the current one: {}", e),
+                }
+
+                // Forget addresses nobody announced or dialed for the TTL
+                let expired = known_peers.evict_expired(std::time::Instant::now());
+                if expired > 0 {
+                    info!("Forgot {} expired peer addresses, {} peers left in the address book", expired, known_peers.len());
+                }
+
+                // Leave mirrored topics no connected peer has wanted for the grace period
+                for topic in topic_interest.take_abandoned(std::time::Instant::now()) {
+                    info!("No connected peer is subscribed to {} any more, unsubscribing", topic);
+                    swarm.behaviour_mut().pubsub.unsubscribe(&topic_hashing::subscription(&topic));
+                    topic_policy.remove(&topic);
+                }
+
+                // Retry reservations lost or failed since the last tick
+                if let Some(auto_relay) = auto_relay.as_mut() {
+                    reserve_on_relays(&mut swarm, auto_relay);
+                }
+
+                // Advertise the successor certhash once the rotation overlap starts
+                let now = SystemTime::now();
+                match webrtc_certificates.prepare_next(now) {
+                    Ok(true) => {
+                        let mut advertised = advertised_addresses.lock();
+                        advertised.set_certhashes(webrtc_certificates.certhashes());
+                        sync_external_addresses(&mut swarm, &advertised);
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!("Failed to prepare the next WebRTC certificate: {}", e),
+                }
+                // The transport keeps its certificate until restart
+                if webrtc_certificates.rotation_due(now) && !rotation_warned {
+                    rotation_warned = true;
+                    if config.webrtc.exit_on_rotation {
+                        info!("WebRTC certificate rotation is due, exiting so the relay restarts with the new certificate");
+                        return Ok(());
+                    }
+                    warn!("WebRTC certificate rotation is due; restart the relay to switch certificates");
+                }
+            }
+            // Announce on the discovery topics that are due
+            Some(due) = discovery_ticks.next() => {
+                // Like pubsub-peer-discovery, the protobuf format announces the relay itself.
+                // The legacy JSON format lists connected peers, so it has nothing to say without any.
+                let announcement = match config.discovery.format {
+                    DiscoveryFormat::Protobuf => Some(discovery_codec::encode_protobuf(&local_key.public(), &advertised_addresses.lock().addresses())),
+                    DiscoveryFormat::Json => {
+                        // Get our current peers from the swarm
+                        let connected_peers: Vec<DiscoveredPeer> = swarm
+                            .connected_peers()
+                            .map(|&peer_id| DiscoveredPeer {
+                                peer_id,
+                                // Get addresses for this peer
+                                addrs: known_peers.addresses(&peer_id),
+                            })
+                            .collect();
+                        if connected_peers.is_empty() {
+                            info!("Skipping peer discovery publish: no connected peers to announce");
+                            None
+                        } else {
+                            Some(discovery_codec::encode_json(&connected_peers))
+                        }
+                    }
+                };
+                if let Some(announcement) = announcement {
+                    // Publish to the due discovery topics
+                    // Bridged topics get the announcement under both hash forms
+                    let topics_to_publish: Vec<TopicHash> = due
+                        .into_iter()
+                        .flat_map(|topic| {
+                            let counterpart = topic_bridge.counterpart(&topic).cloned();
+                            std::iter::once(topic).chain(counterpart)
+                        })
+                        .collect();
+                    for topic in topics_to_publish {
+                        match swarm.behaviour_mut().pubsub.publish(
+                            topic.clone(), // Clone topic for publish call
+                            announcement.clone(),
+                        ) {
+                            Ok(_) => info!("Published peer discovery info on topic {}", topic),
+                            // Changed from error! to warn!
+                            Err(e) => warn!("Failed to publish peer discovery info on topic {}: {}", topic, e),
+                        }
+                    }
+                }
             }
             event = swarm.select_next_some() => {
-                let addresses_for_event = listening_addresses.clone();
                 match event {
                     SwarmEvent::NewListenAddr { address, .. } => {
                         info!("Local node listening on: {}", address);
-                        // Add the new address to our shared list
-                        addresses_for_event.lock().push(address.clone());
+                        let mut advertised = advertised_addresses.lock();
+                        if advertised.add_listen_addr(address) {
+                            sync_external_addresses(&mut swarm, &advertised);
+                            // Announce the new address right away
+                            discovery_ticks.announce_now();
+                        }
                     }
                      SwarmEvent::ExpiredListenAddr { address, .. } => {
                         info!("Local node stopped listening on: {}", address);
-                        // Remove the expired address from our shared list
-                        addresses_for_event.lock().retain(|a| *a != address);
+                        let mut advertised = advertised_addresses.lock();
+                        if advertised.remove_listen_addr(&address) {
+                            sync_external_addresses(&mut swarm, &advertised);
+                            discovery_ticks.announce_now();
+                        }
                     }
                     SwarmEvent::Behaviour(behaviour) => {
                         match behaviour {
@@ -1403,16 +1776,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                         info!("Identified Peer: {} with agent version: {}", peer_id, info.agent_version);
                                         // Log the full received info struct
                                         info!("[IDENTIFY RECV] Received Identify::Info from {}: {:#?}", peer_id, info);
-                                        for addr in info.listen_addrs {
-                                            // Keep duplicate check before adding
-                                            let current_addrs = swarm.external_addresses().cloned().collect::<Vec<_>>();
-                                            if !current_addrs.contains(&addr) {
-                                                swarm.add_external_address(addr.clone());
-                                                info!("Added external address from Identify: {}", addr);
-                                            } else {
-                                                // info!("Skipping duplicate external address from Identify: {}", addr);
-                                            }
-                                        }
+                                        // The remote's listen addresses are its own, never ours to advertise;
+                                        // what we announce comes solely from `advertised_addresses`.
                                     }
                                     _ => info!("Other Identify event: {:?}", identify_event),
                                 }
@@ -1421,6 +1786,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 info!("Ping event: {:?}", ping_event);
                             }
                             RelayEvent::Relay(relay_event) => {
+                                relay_registry.lock().on_event(&relay_event, SystemTime::now());
                                 match relay_event {
                                     relay::Event::ReservationReqAccepted { src_peer_id, renewed, .. } => {
                                         if renewed {
@@ -1432,8 +1798,33 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                     relay::Event::ReservationTimedOut { src_peer_id, .. } => {
                                         warn!("Relay reservation timed out for client: {}", src_peer_id);
                                     }
+                                    relay::Event::ReservationReqDenied { src_peer_id, .. } => {
+                                        info!("Relay reservation denied for client: {}", src_peer_id);
+                                    }
+                                    relay::Event::CircuitReqAccepted { src_peer_id, dst_peer_id } => {
+                                        info!("Circuit opened from {} to {}", src_peer_id, dst_peer_id);
+                                    }
+                                    relay::Event::CircuitReqDenied { src_peer_id, dst_peer_id } => {
+                                        info!("Circuit denied from {} to {}", src_peer_id, dst_peer_id);
+                                    }
+                                    relay::Event::CircuitClosed { src_peer_id, dst_peer_id, bytes, error } => {
+                                        info!("Circuit closed from {} to {} after {:?} bytes: {:?}", src_peer_id, dst_peer_id, bytes, error);
+                                    }
                                     _ => {
-                                        info!("Other Relay event: {:?}", relay_event);
+                                        debug!("Other Relay event: {:?}", relay_event);
+                                    }
+                                }
+                            }
+                            RelayEvent::RelayClient(event) => {
+                                info!("Relay client event: {:?}", event);
+                            }
+                            RelayEvent::Admission(admission_event) => {
+                                match admission_event {
+                                    admission::Event::ReservationDenied { peer_id, addr, reason } => {
+                                        warn!("Reservation refused for {} ({}): {}", peer_id, addr, reason);
+                                    }
+                                    admission::Event::CircuitDenied { src_peer_id, addr, reason } => {
+                                        warn!("Circuit refused for {} ({}): {}", src_peer_id, addr, reason);
                                     }
                                 }
                             }
@@ -1448,26 +1839,36 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                             info!("IMPORTANT: Detected Orbiter discovery topic: {}", topic_name);
                                         }
 
-                                        // Recreate the Topic type from the hash/name for subscribe call
-                                        let topic_to_subscribe = Sha256Topic::new(topic_name.clone());
+                                        // ** Mimic TypeScript: Relay subscribes to any topic a peer subscribes to, within the mirror policy **
+                                        match topic_policy.admit(topic.clone()) {
+                                            Decision::Denied => {
+                                                debug!("Not mirroring topic {} requested by {}: denied by the mirror policy", topic_name, peer_id);
+                                            }
+                                            Decision::Mirror { evicted } => {
+                                                if let Some(evicted) = evicted {
+                                                    info!("Mirror cap reached, unsubscribing from least recently used topic {}", evicted);
+                                                    swarm.behaviour_mut().pubsub.unsubscribe(&topic_hashing::subscription(&evicted));
+                                                    topic_interest.forget(&evicted);
+                                                }
 
-                                        // ** Mimic TypeScript: Relay subscribes to any topic a peer subscribes to **
-                                        if let Err(e) = swarm.behaviour_mut().pubsub.subscribe(&topic_to_subscribe) {
-                                            error!("Error subscribing relay to topic {} after peer subscription: {}", topic_name, e);
-                                        } else {
-                                            debug!("Relay ensuring subscription to topic requested by peer: {}", topic_name);
+                                                // Join under the peer's exact hash, whichever hashing it uses
+                                                let topic_to_subscribe = topic_hashing::subscription(&topic);
+
+                                                if let Err(e) = swarm.behaviour_mut().pubsub.subscribe(&topic_to_subscribe) {
+                                                    error!("Error subscribing relay to topic {} after peer subscription: {}", topic_name, e);
+                                                } else {
+                                                    debug!("Relay ensuring subscription to topic requested by peer: {}", topic_name);
+                                                }
+                                                topic_interest.subscribed(peer_id, topic);
+                                            }
                                         }
                                     }
                                     GossipsubEvent::Unsubscribed { peer_id, topic } => {
                                         debug!("Peer {} unsubscribed from topic: {}", peer_id, topic);
-                                        // REMOVED: Logic to track peer unsubscriptions and potentially unsubscribe relay
-                                        // let topic_name = topic.to_string();
-                                        // let topic_to_unsubscribe = Sha256Topic::new(topic_name.clone());
-                                        // ... removal of relay_reqs entry ...
-                                        // ... check if still_needed ...
-                                        // ... conditional unsubscribe ...
+                                        // The relay leaves the topic from the status tick, once the grace period is over
+                                        topic_interest.unsubscribed(&peer_id, &topic, std::time::Instant::now());
                                     }
-                                    GossipsubEvent::Message { message, .. } => {
+                                    GossipsubEvent::Message { propagation_source, message_id, message } => {
                                         info!("Received PubSub message: {:?}, Topic={}, Data size={}", message.source, message.topic, message.data.len());
                                         // --- TEMP DEBUG: Log ALL received messages ---
                                         info!(
@@ -1476,69 +1877,68 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                         );
                                         // --- END TEMP DEBUG ---
 
+                                        // Report the verdict, so gossipsub forwards the message or penalizes whoever sent it
+                                        let verdict = match message_validation.as_mut() {
+                                            Some(validation) => {
+                                                let verdict = validation.validate(&propagation_source, &message, std::time::Instant::now());
+                                                swarm.behaviour_mut().pubsub.report_message_validation_result(&message_id, &propagation_source, verdict.acceptance());
+                                                verdict
+                                            }
+                                            None => Verdict::Accept,
+                                        };
+
+                                        // Republish on the other hash form of a bridged topic
+                                        if verdict == Verdict::Accept {
+                                            if let Some(counterpart) = topic_bridge.forward(&message.topic, &message.data, std::time::Instant::now()) {
+                                                match swarm.behaviour_mut().pubsub.publish(counterpart.clone(), message.data.clone()) {
+                                                    Ok(_) => debug!("Bridged message {} from topic {} to {}", message_id, message.topic, counterpart),
+                                                    Err(e) => warn!("Failed to bridge message {} from topic {} to {}: {}", message_id, message.topic, counterpart, e),
+                                                }
+                                            }
+                                        }
+
+                                        if verdict != Verdict::Accept {
+                                            warn!("Dropping message {} from {} on topic {}: {:?}", message_id, propagation_source, message.topic, verdict);
                                         // Check if this is a peer discovery message by comparing the TopicHash
-                                        if message.topic == *CONSTELLATION_TOPIC_HASH ||
-                                           message.topic == *ORBITER_DEVICE_TOPIC_HASH ||
-                                           message.topic == *ORBITER_CONTENT_TOPIC_HASH {
+                                        } else if discovery_topic_hashes.contains(&message.topic) {
 
                                             // Use the Display impl of TopicHash (base64) for logging clarity
                                             info!("Received peer discovery message from {:?} on topic {}", message.source, message.topic);
 
-                                            // Log raw content specifically for Orbiter discovery topics
-                                            if message.topic == *ORBITER_DEVICE_TOPIC_HASH ||
-                                               message.topic == *ORBITER_CONTENT_TOPIC_HASH {
-                                                info!(
-                                                    "ORBITER DISCOVERY MESSAGE RAW CONTENT ({} bytes) on topic {}: {}",
-                                                    message.data.len(), message.topic, String::from_utf8_lossy(&message.data)
-                                                );
-                                            }
+                                            debug!(
+                                                "Discovery message raw content ({} bytes) on topic {}: {}",
+                                                message.data.len(), message.topic, String::from_utf8_lossy(&message.data)
+                                            );
                                             
-                                            // Try to parse peer information
-                                            if let Ok(peers_info) = serde_json::from_slice::<Vec<SerializablePeer>>(&message.data) {
-                                                for serializable_peer in peers_info {
-                                                    match serializable_peer.to_peer_info() {
-                                                        Ok((peer_id, addrs)) => {
-                                                            // Skip ourselves
-                                                            if peer_id == local_peer_id {
-                                                                continue;
-                                                            }
-                                                            
-                                                            // Update our known peers
-                                                            match known_peers.entry(peer_id) {
-                                                                Entry::Occupied(mut e) => {
-                                                                    // Add new addresses we don't already know
-                                                                    for addr in addrs {
-                                                                        if !e.get().contains(&addr) {
-                                                                            e.get_mut().push(addr);
-                                                                        }
-                                                                    }
-                                                                }
-                                                                Entry::Vacant(e) => {
-                                                                    e.insert(addrs);
-                                                                }
-                                                            }
-                                                            
-                                                            // If not already connected, try to dial this peer
-                                                            if !swarm.is_connected(&peer_id) {
-                                                                if let Some(peer_addrs) = known_peers.get(&peer_id) {
-                                                                    if !peer_addrs.is_empty() {
-                                                                        // Choose a random address to try
-                                                                        if let Some(addr) = peer_addrs.choose(&mut rand::thread_rng()) {
-                                                                            info!("Discovered new peer via pubsub, dialing {} at {}", peer_id, addr);
-                                                                            match swarm.dial(addr.clone()) {
-                                                                                Ok(_) => info!("Dialing peer {} discovered via PubSub", peer_id),
-                                                                                Err(e) => warn!("Failed to dial discovered peer {}: {}", peer_id, e),
-                                                                            }
-                                                                        }
-                                                                    }
+                                            // Either format is understood, whichever the relay publishes
+                                            match discovery_codec::decode(&message.data) {
+                                                Ok((format, peers_info)) => {
+                                                    debug!("Peer discovery message in {:?} format", format);
+                                                    for DiscoveredPeer { peer_id, addrs } in peers_info {
+                                                        // Skip ourselves
+                                                        if peer_id == local_peer_id {
+                                                            continue;
+                                                        }
+                                                    
+                                                        // Update our known peers
+                                                        known_peers.add(peer_id, addrs, std::time::Instant::now());
+                                                    
+                                                        // If not already connected, try to dial this peer
+                                                        if !swarm.is_connected(&peer_id) {
+                                                            // The address with the fewest failed dials, so dead ones are not retried first
+                                                            if let Some(addr) = known_peers.best_address(&peer_id) {
+                                                                info!("Discovered new peer via pubsub, dialing {} at {}", peer_id, addr);
+                                                                // Dialing by peer id ties a failure to this peer's address book entry
+                                                                let dial = DialOpts::peer_id(peer_id).addresses(vec![addr.clone()]).build();
+                                                                match swarm.dial(dial) {
+                                                                    Ok(_) => info!("Dialing peer {} discovered via PubSub", peer_id),
+                                                                    Err(e) => warn!("Failed to dial discovered peer {}: {}", peer_id, e),
                                                                 }
                                                             }
-                                                        },
-                                                        Err(e) => warn!("Failed to parse peer info: {}", e),
+                                                        }
                                                     }
                                                 }
-                                            } else {
-                                                warn!("Received invalid peer discovery message");
+                                                Err(e) => warn!("Received invalid peer discovery message: {}", e),
                                             }
                                         } else {
                                             // Only display message data for small messages to avoid flooding logs
@@ -1549,11 +1949,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                             // The message is automatically relayed by GossipSub to all subscribed peers
                                             info!("Relaying message for topic {} to all subscribed peers", message.topic);
 
-                                            // Ensure we are still subscribed to this topic to relay properly
-                                            let topic_to_ensure = Sha256Topic::new(message.topic.to_string());
-                                            if let Err(e) = swarm.behaviour_mut().pubsub.subscribe(&topic_to_ensure) {
-                                                warn!("Error ensuring subscription to topic {}: {}", message.topic, e);
-                                            }
+                                            // Keeps the topic from being evicted first when the mirror cap is reached
+                                            topic_policy.touch(&message.topic);
                                         }
                                     }
                                     _ => {
@@ -1577,6 +1974,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 match autonat_event {
                                     autonat::Event::StatusChanged { old, new } => {
                                         info!("AutoNAT status changed from {:?} to {:?}", old, new);
+                                        if let Some(auto_relay) = auto_relay.as_mut() {
+                                            for listener_id in auto_relay.set_nat_status(&new) {
+                                                info!("Publicly reachable, releasing relay reservation {:?}", listener_id);
+                                                swarm.remove_listener(listener_id);
+                                            }
+                                            reserve_on_relays(&mut swarm, auto_relay);
+                                        }
                                     }
                                     autonat::Event::OutboundProbe(e) => {
                                         info!("AutoNAT outbound probe event: {:?}", e); 
@@ -1602,50 +2006,74 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         
                         // Store this peer's address
                         if let ConnectedPoint::Dialer { address, .. } = endpoint {
-                            match known_peers.entry(peer_id) {
-                                Entry::Occupied(mut e) => {
-                                    if !e.get().contains(&address) {
-                                        e.get_mut().push(address);
-                                    }
-                                }
-                                Entry::Vacant(e) => {
-                                    e.insert(vec![address]);
-                                }
-                            }
+                            known_peers.connected(peer_id, address, std::time::Instant::now());
                         }
                     }
-                     SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
+                     SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
+                        if num_established == 0 {
+                            // The relay drops the reservations of disconnected peers without an event
+                            relay_registry.lock().on_peer_disconnected(&peer_id);
+                            topic_interest.peer_disconnected(&peer_id, std::time::Instant::now());
+                        }
                         info!(
                             "Connection closed to peer: {}, cause: {:?}",
                             peer_id, cause
                         );
                          let connected_peers_count = swarm.connected_peers().count();
                         info!("Connected peers count after closure: {}", connected_peers_count);
-
-                        // When a peer disconnects, check if we can unsubscribe the relay from topics they were the *last* user of.
-                        // REMOVED: Logic to check/unsubscribe topics when peer disconnects based on relay_reqs
-                        // if let Some(disconnected_peer_topics) = relay_reqs.remove(&peer_id) {
-                        // ... loop through topics ...
-                        // ... check if still_needed ...
-                        // ... conditional unsubscribe ...
-                        // }
                     }
                     // Added `..` to ignore unmentioned fields like connection_id
                     SwarmEvent::IncomingConnection { local_addr, send_back_addr, .. } => {
                         info!("Incoming connection from {} to {}", send_back_addr, local_addr);
                     }
                     // Added `..` to ignore unmentioned fields like connection_id
+                    SwarmEvent::IncomingConnectionError { local_addr, send_back_addr, error: ListenError::Denied { cause }, .. } if cause.downcast_ref::<LimitExceeded>().is_some() || cause.downcast_ref::<AccessDenied>().is_some() => {
+                        warn!("Refused incoming connection from {} to {}: {}", send_back_addr, local_addr, cause);
+                    }
                     SwarmEvent::IncomingConnectionError { local_addr, send_back_addr, error, .. } => {
                         error!("Incoming connection error from {} to {}: {}", send_back_addr, local_addr, error);
                     }
+                    SwarmEvent::OutgoingConnectionError { peer_id, error: DialError::Denied { cause }, .. } if cause.downcast_ref::<LimitExceeded>().is_some() || cause.downcast_ref::<AccessDenied>().is_some() => {
+                        warn!("Refused outgoing connection to {:?}: {}", peer_id, cause);
+                    }
                     SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
                         error!("Outgoing connection error to {:?}: {}", peer_id, error);
+                        // Demote the addresses that failed, so the next dial tries another one
+                        if let Some(peer_id) = peer_id {
+                            let failed: Vec<Multiaddr> = match &error {
+                                DialError::Transport(errors) => errors.iter().map(|(address, _)| address.clone()).collect(),
+                                DialError::WrongPeerId { endpoint, .. } => vec![endpoint.get_remote_address().clone()],
+                                _ => Vec::new(),
+                            };
+                            known_peers.dial_failed(&peer_id, &failed);
+                        }
                     }
                     SwarmEvent::ListenerError { listener_id, error } => {
                         error!("Listener error for {:?}: {}", listener_id, error);
                     }
-                     SwarmEvent::ListenerClosed { listener_id, reason, .. } => {
+                    SwarmEvent::ListenerClosed { listener_id, reason, addresses } => {
                         info!("Listener {:?} closed: {:?}", listener_id, reason);
+                        if auto_relay.as_mut().is_some_and(|auto_relay| auto_relay.listener_closed(listener_id)) {
+                            warn!("Relay reservation {:?} ended, retrying on the next status tick", listener_id);
+                        }
+                        if let (Some(address), Err(e)) = (required_listeners.get(&listener_id), &reason) {
+                            error!("Required listener {} failed: {}", address, e);
+                            return Err(format!("required listener {} failed: {}", address, e).into());
+                        }
+                        // Drop the closed listener's addresses from what we advertise
+                        let mut advertised = advertised_addresses.lock();
+                        let mut changed = false;
+                        for address in &addresses {
+                            changed |= advertised.remove_listen_addr(address);
+                        }
+                        if changed {
+                            sync_external_addresses(&mut swarm, &advertised);
+                        }
+                    }
+                    // AutoNAT confirmations must not widen what we announce
+                    SwarmEvent::ExternalAddrConfirmed { address } if !advertised_addresses.lock().addresses().contains(&address) => {
+                        info!("Ignoring confirmed external address outside the announce set: {}", address);
+                        swarm.remove_external_address(&address);
                     }
                     SwarmEvent::Dialing { peer_id, connection_id } => {
                         // Dialing event now includes peer_id which might be None if dialing an address without knowing the peer ID yet.
@@ -1656,52 +2084,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         // info!("Unhandled Swarm Event: {:?}", event);
                     }
                 }
-
-                // Check if it's time to broadcast our known peers for discovery
-                if last_peer_discovery.elapsed() >= peer_discovery_interval {
-                    last_peer_discovery = std::time::Instant::now();
-                    
-                    // Get our current peers from the swarm
-                    let connected_peers: Vec<SerializablePeer> = swarm
-                        .connected_peers()
-                        .map(|&peer_id| {
-                            // Get addresses for this peer
-                            let addrs = known_peers
-                                .get(&peer_id)
-                                .cloned()
-                                .unwrap_or_default();
-                            SerializablePeer::from((peer_id, addrs))
-                        })
-                        .collect();
-                    
-                    // Only broadcast if we have enough connected peers (heuristic for mesh readiness)
-                    let mesh_n_low = 0; // Get this from your config ideally, but using 0 now
-                    if connected_peers.len() > mesh_n_low { // Equivalent to !is_empty()
-                        // Serialize peer information to JSON
-                        if let Ok(peer_info_json) = serde_json::to_string(&connected_peers) {
-                            // Publish to ALL configured discovery topics
-                            let topics_to_publish = [&peer_disc_topic, &orbiter_disc_topic, &orbiter_content_topic];
-                            for topic in topics_to_publish {
-                                match swarm.behaviour_mut().pubsub.publish(
-                                    topic.clone(), // Clone topic for publish call
-                                    peer_info_json.as_bytes(),
-                                ) {
-                                    Ok(_) => info!("Published peer discovery info on topic {}", topic),
-                                    // Changed from error! to warn!
-                                    Err(e) => warn!("Failed to publish peer discovery info on topic {}: {}", topic, e),
-                                }
-                            }
-                        } else {
-                            error!("Failed to serialize peer discovery info to JSON");
-                        }
-                    } else {
-                        // Log why we skipped publishing
-                        info!(
-                            "Skipping peer discovery publish: connected peers ({}) <= mesh_n_low ({})",
-                            connected_peers.len(), mesh_n_low
-                        );
-                    }
-                }
             }
         }
     }
@@ -0,0 +1,601 @@
+//! Application validation of gossipsub messages before they are forwarded.
+//!
+//! Gossipsub runs with `validate_messages()`, so every received message waits
+//! for a verdict from [`MessageValidation`]. It runs the [`TopicValidator`]s
+//! whose [`TopicSelector`] matches the message topic, in order, and the first
+//! verdict other than `Accept` wins. `Reject` makes gossipsub penalize the
+//! peer that forwarded the message; `Ignore` drops it without penalty.
+//!
+//! Built-in validators:
+//! - [`MaxSize`] rejects messages over a size,
+//! - [`JsonSchema`] rejects messages not matching a JSON schema (the subset
+//!   described on [`JsonSchema`]),
+//! - [`DiscoveryPeers`] rejects discovery messages that are not a valid
+//!   announcement, protobuf or legacy JSON,
+//! - [`SourceRateLimit`] ignores messages once the peer forwarding them
+//!   exceeds a rate.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use libp2p::gossipsub::{Message, MessageAcceptance, TopicHash};
+use libp2p::PeerId;
+use serde_json::Value;
+
+use crate::discovery_codec;
+use crate::topic_policy::{TopicMatcher, TopicPolicyError};
+
+/// Errors building the validation pipeline.
+#[derive(Debug, thiserror::Error)]
+pub enum MessageValidationError {
+    #[error("cannot read JSON schema {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid JSON schema {path}: {reason}")]
+    InvalidSchema { path: PathBuf, reason: String },
+    #[error(transparent)]
+    Topic(#[from] TopicPolicyError),
+}
+
+/// A check applied to the messages of the topics it is registered for.
+pub trait TopicValidator: Send {
+    /// Short name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Judges `message`, received from `propagation_source` at `now`.
+    fn validate(
+        &mut self,
+        propagation_source: &PeerId,
+        message: &Message,
+        now: Instant,
+    ) -> MessageAcceptance;
+}
+
+/// Rejects messages larger than the given number of bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxSize(pub usize);
+
+impl TopicValidator for MaxSize {
+    fn name(&self) -> &'static str {
+        "max size"
+    }
+
+    fn validate(&mut self, _: &PeerId, message: &Message, _: Instant) -> MessageAcceptance {
+        if message.data.len() > self.0 {
+            MessageAcceptance::Reject
+        } else {
+            MessageAcceptance::Accept
+        }
+    }
+}
+
+/// Rejects messages that are not JSON matching a schema.
+///
+/// Supports the [`SUPPORTED_KEYWORDS`], plus the [`ANNOTATION_KEYWORDS`] that
+/// do not constrain anything. [`JsonSchema::from_file`] refuses schemas using
+/// any other keyword, which would otherwise be silently ignored.
+#[derive(Debug, Clone)]
+pub struct JsonSchema {
+    schema: Value,
+}
+
+impl JsonSchema {
+    /// Validates against `schema`, whose keywords are assumed to be supported.
+    pub fn new(schema: Value) -> Self {
+        Self { schema }
+    }
+
+    /// Reads the schema from a JSON file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`MessageValidationError`] if the file cannot be read, is not
+    /// JSON or uses an unsupported keyword.
+    pub fn from_file(path: &Path) -> Result<Self, MessageValidationError> {
+        let contents = std::fs::read(path).map_err(|source| MessageValidationError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let invalid = |reason| MessageValidationError::InvalidSchema {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let schema = serde_json::from_slice(&contents).map_err(|e| invalid(e.to_string()))?;
+        if let Some(pointer) = unsupported_keyword(&schema, "") {
+            return Err(invalid(format!("unsupported keyword at {pointer}")));
+        }
+        Ok(Self::new(schema))
+    }
+
+    /// Whether `value` matches the schema.
+    pub fn matches(&self, value: &Value) -> bool {
+        matches_schema(&self.schema, value)
+    }
+}
+
+impl TopicValidator for JsonSchema {
+    fn name(&self) -> &'static str {
+        "JSON schema"
+    }
+
+    fn validate(&mut self, _: &PeerId, message: &Message, _: Instant) -> MessageAcceptance {
+        match serde_json::from_slice::<Value>(&message.data) {
+            Ok(value) if self.matches(&value) => MessageAcceptance::Accept,
+            _ => MessageAcceptance::Reject,
+        }
+    }
+}
+
+/// Keywords [`JsonSchema`] enforces.
+pub const SUPPORTED_KEYWORDS: &[&str] = &[
+    "type",
+    "enum",
+    "const",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "minItems",
+    "maxItems",
+    "minLength",
+    "maxLength",
+    "minimum",
+    "maximum",
+];
+
+/// Keywords [`JsonSchema`] accepts without enforcing, as they only annotate.
+pub const ANNOTATION_KEYWORDS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+];
+
+// JSON pointer to the first part of `schema` that `matches_schema` would not enforce
+fn unsupported_keyword(schema: &Value, pointer: &str) -> Option<String> {
+    let Value::Object(schema) = schema else {
+        return (!schema.is_boolean()).then(|| pointer.to_string());
+    };
+    for (keyword, value) in schema {
+        let pointer = format!("{pointer}/{keyword}");
+        let nested = match keyword.as_str() {
+            "properties" => {
+                let Value::Object(properties) = value else {
+                    return Some(pointer);
+                };
+                properties.iter().find_map(|(name, property)| {
+                    unsupported_keyword(property, &format!("{pointer}/{name}"))
+                })
+            }
+            // A single schema; the tuple form of `items` is not supported
+            "additionalProperties" | "items" => unsupported_keyword(value, &pointer),
+            keyword
+                if SUPPORTED_KEYWORDS.contains(&keyword)
+                    || ANNOTATION_KEYWORDS.contains(&keyword) =>
+            {
+                None
+            }
+            _ => Some(pointer),
+        };
+        if nested.is_some() {
+            return nested;
+        }
+    }
+    None
+}
+
+fn matches_schema(schema: &Value, value: &Value) -> bool {
+    let Value::Object(schema) = schema else {
+        // `true` accepts anything, `false` nothing
+        return schema.as_bool().unwrap_or(true);
+    };
+    if let Some(expected) = schema.get("type") {
+        let type_matches = |name: &Value| match name.as_str() {
+            Some("null") => value.is_null(),
+            Some("boolean") => value.is_boolean(),
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("number") => value.is_number(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            _ => false,
+        };
+        let matches = match expected {
+            Value::Array(names) => names.iter().any(type_matches),
+            name => type_matches(name),
+        };
+        if !matches {
+            return false;
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            return false;
+        }
+    }
+    if schema
+        .get("const")
+        .is_some_and(|expected| expected != value)
+    {
+        return false;
+    }
+    let limit = |keyword: &str| schema.get(keyword).and_then(Value::as_u64);
+    match value {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            if let Some(Value::Array(required)) = schema.get("required") {
+                if required
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|key| !object.contains_key(key))
+                {
+                    return false;
+                }
+            }
+            for (key, field) in object {
+                let field_schema = properties
+                    .and_then(|properties| properties.get(key))
+                    .or_else(|| schema.get("additionalProperties"));
+                if field_schema.is_some_and(|field_schema| !matches_schema(field_schema, field)) {
+                    return false;
+                }
+            }
+        }
+        Value::Array(items) => {
+            if limit("minItems").is_some_and(|min| (items.len() as u64) < min)
+                || limit("maxItems").is_some_and(|max| items.len() as u64 > max)
+            {
+                return false;
+            }
+            if let Some(item_schema) = schema.get("items") {
+                if !items.iter().all(|item| matches_schema(item_schema, item)) {
+                    return false;
+                }
+            }
+        }
+        Value::String(string) => {
+            let length = string.chars().count() as u64;
+            if limit("minLength").is_some_and(|min| length < min)
+                || limit("maxLength").is_some_and(|max| length > max)
+            {
+                return false;
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or(f64::NAN);
+            let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+            if bound("minimum").is_some_and(|min| number < min)
+                || bound("maximum").is_some_and(|max| number > max)
+            {
+                return false;
+            }
+        }
+        _ => {}
+    }
+    true
+}
+
+/// Rejects discovery messages that [`discovery_codec::decode`] refuses:
+/// peers must have a valid PeerId or public key, and valid multiaddrs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiscoveryPeers;
+
+impl TopicValidator for DiscoveryPeers {
+    fn name(&self) -> &'static str {
+        "discovery peers"
+    }
+
+    fn validate(&mut self, _: &PeerId, message: &Message, _: Instant) -> MessageAcceptance {
+        match discovery_codec::decode(&message.data) {
+            Ok(_) => MessageAcceptance::Accept,
+            Err(_) => MessageAcceptance::Reject,
+        }
+    }
+}
+
+/// Sources tracked before those whose window ended are dropped.
+const RATE_LIMIT_PRUNE_THRESHOLD: usize = 1024;
+
+/// Ignores messages beyond `max_messages` per `window` from one source.
+///
+/// The source is the peer forwarding the message, not its `from` field:
+/// gossipsub runs in permissive mode, where an unsigned `from` is never
+/// verified and could be rotated to dodge the limit. Over-rate messages are
+/// ignored rather than rejected, as the forwarding peer is not necessarily
+/// their author.
+#[derive(Debug, Clone)]
+pub struct SourceRateLimit {
+    max_messages: u32,
+    window: Duration,
+    // Start of the current window and messages seen in it, per source
+    windows: HashMap<PeerId, (Instant, u32)>,
+}
+
+impl SourceRateLimit {
+    /// Allows `max_messages` per `window` and forwarding peer.
+    pub fn new(max_messages: u32, window: Duration) -> Self {
+        Self {
+            max_messages,
+            window,
+            windows: HashMap::new(),
+        }
+    }
+}
+
+impl TopicValidator for SourceRateLimit {
+    fn name(&self) -> &'static str {
+        "source rate limit"
+    }
+
+    fn validate(
+        &mut self,
+        propagation_source: &PeerId,
+        _: &Message,
+        now: Instant,
+    ) -> MessageAcceptance {
+        // Sources seen once keep an entry; drop the stale ones as they pile up
+        if self.windows.len() >= RATE_LIMIT_PRUNE_THRESHOLD {
+            let window = self.window;
+            self.windows
+                .retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+        let (start, count) = self.windows.entry(*propagation_source).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        if *count > self.max_messages {
+            MessageAcceptance::Ignore
+        } else {
+            MessageAcceptance::Accept
+        }
+    }
+}
+
+/// Topics a validator applies to.
+#[derive(Debug, Clone)]
+pub enum TopicSelector {
+    All,
+    Topics(HashSet<TopicHash>),
+    Pattern(TopicMatcher),
+}
+
+impl TopicSelector {
+    fn matches(&self, topic: &TopicHash) -> bool {
+        match self {
+            TopicSelector::All => true,
+            TopicSelector::Topics(topics) => topics.contains(topic),
+            TopicSelector::Pattern(matcher) => matcher.matches(topic.as_str()),
+        }
+    }
+}
+
+/// Result of [`MessageValidation::validate`], naming the deciding validator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Reject(&'static str),
+    Ignore(&'static str),
+}
+
+impl Verdict {
+    /// The acceptance to report to gossipsub.
+    pub fn acceptance(&self) -> MessageAcceptance {
+        match self {
+            Verdict::Accept => MessageAcceptance::Accept,
+            Verdict::Reject(_) => MessageAcceptance::Reject,
+            Verdict::Ignore(_) => MessageAcceptance::Ignore,
+        }
+    }
+}
+
+/// The validators run on received messages.
+#[derive(Default)]
+pub struct MessageValidation {
+    validators: Vec<(TopicSelector, Box<dyn TopicValidator>)>,
+}
+
+impl MessageValidation {
+    /// Creates a pipeline accepting every message.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `validator` on the topics matched by `selector`, after those already added.
+    pub fn with(
+        mut self,
+        selector: TopicSelector,
+        validator: impl TopicValidator + 'static,
+    ) -> Self {
+        self.validators.push((selector, Box::new(validator)));
+        self
+    }
+
+    /// Number of validators.
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    /// Whether every message is accepted.
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    /// Runs the validators for the topic of `message`, received from
+    /// `propagation_source` at `now`, until one does not accept it.
+    pub fn validate(
+        &mut self,
+        propagation_source: &PeerId,
+        message: &Message,
+        now: Instant,
+    ) -> Verdict {
+        for (selector, validator) in &mut self.validators {
+            if !selector.matches(&message.topic) {
+                continue;
+            }
+            match validator.validate(propagation_source, message, now) {
+                MessageAcceptance::Accept => {}
+                MessageAcceptance::Reject => return Verdict::Reject(validator.name()),
+                MessageAcceptance::Ignore => return Verdict::Ignore(validator.name()),
+            }
+        }
+        Verdict::Accept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topic_policy::TopicPattern;
+    use serde_json::json;
+
+    fn peer_id() -> PeerId {
+        PeerId::from(libp2p::identity::Keypair::generate_ed25519().public())
+    }
+
+    fn message(topic: &str, data: &[u8]) -> Message {
+        Message {
+            source: None,
+            data: data.to_vec(),
+            sequence_number: None,
+            topic: TopicHash::from_raw(topic),
+        }
+    }
+
+    #[test]
+    fn test_pipeline_runs_matching_validators_in_order() {
+        let peer = peer_id();
+        let discovery = TopicHash::from_raw("constellation._peer-discovery._p2p._pubsub");
+        let schema = json!({
+            "type": "object",
+            "required": ["kind"],
+            "properties": { "kind": { "enum": ["put", "del"] } },
+        });
+        let mut validation = MessageValidation::new()
+            .with(TopicSelector::All, MaxSize(256))
+            .with(
+                TopicSelector::Topics(HashSet::from([discovery.clone()])),
+                DiscoveryPeers,
+            )
+            .with(
+                TopicSelector::Pattern(
+                    TopicMatcher::new(&TopicPattern::Prefix("app/".to_string())).unwrap(),
+                ),
+                JsonSchema::new(schema),
+            );
+        let now = Instant::now();
+        let mut validate =
+            |topic: &str, data: &[u8]| validation.validate(&peer, &message(topic, data), now);
+
+        assert_eq!(validate("other", &[0; 257]), Verdict::Reject("max size"));
+        assert_eq!(validate("other", b"not json"), Verdict::Accept);
+        assert_eq!(validate("app/db", br#"{"kind":"put"}"#), Verdict::Accept);
+        assert_eq!(
+            validate("app/db", br#"{"kind":"drop"}"#),
+            Verdict::Reject("JSON schema")
+        );
+
+        let peers = format!(r#"[{{"id":"{peer}","addrs":["/ip4/192.0.2.1/tcp/4001"]}}]"#);
+        assert_eq!(
+            validate(discovery.as_str(), peers.as_bytes()),
+            Verdict::Accept
+        );
+        assert_eq!(
+            validate(discovery.as_str(), br#"[{"id":"nope","addrs":[]}]"#),
+            Verdict::Reject("discovery peers")
+        );
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let announcement = discovery_codec::encode_protobuf(&keypair.public(), &[]);
+        assert_eq!(validate(discovery.as_str(), &announcement), Verdict::Accept);
+    }
+
+    #[test]
+    fn test_rate_limit_per_source() {
+        let (forwarder, other) = (peer_id(), peer_id());
+        let mut limit = SourceRateLimit::new(2, Duration::from_secs(1));
+        // A different claimed author on every message
+        let forged = || {
+            let mut message = message("app", b"{}");
+            message.source = Some(peer_id());
+            message
+        };
+        let start = Instant::now();
+
+        assert!(matches!(
+            limit.validate(&forwarder, &forged(), start),
+            MessageAcceptance::Accept
+        ));
+        assert!(matches!(
+            limit.validate(&forwarder, &forged(), start),
+            MessageAcceptance::Accept
+        ));
+        // The forwarding peer is counted, whatever `from` claims
+        assert!(matches!(
+            limit.validate(&forwarder, &forged(), start),
+            MessageAcceptance::Ignore
+        ));
+        assert!(matches!(
+            limit.validate(&other, &forged(), start),
+            MessageAcceptance::Accept
+        ));
+
+        let later = start + Duration::from_secs(1);
+        assert!(matches!(
+            limit.validate(&forwarder, &forged(), later),
+            MessageAcceptance::Accept
+        ));
+    }
+
+    #[test]
+    fn test_json_schema_keywords() {
+        let schema = JsonSchema::new(json!({
+            "type": "array",
+            "maxItems": 2,
+            "items": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "name": { "type": "string", "minLength": 1 },
+                    "size": { "type": "integer", "minimum": 0 },
+                },
+            },
+        }));
+        assert!(schema.matches(&json!([{ "name": "a", "size": 3 }])));
+        assert!(!schema.matches(&json!([{ "name": "" }])));
+        assert!(!schema.matches(&json!([{ "size": -1 }])));
+        assert!(!schema.matches(&json!([{ "extra": true }])));
+        assert!(!schema.matches(&json!([{}, {}, {}])));
+        assert!(!schema.matches(&json!({ "name": "a" })));
+    }
+
+    #[test]
+    fn test_unsupported_schema_keywords_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schema.json");
+        let write = |schema: Value| std::fs::write(&path, schema.to_string()).unwrap();
+
+        write(json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "entry",
+            "type": "object",
+            "properties": { "name": { "type": "string", "description": "Entry name" } },
+        }));
+        assert!(JsonSchema::from_file(&path).is_ok());
+
+        write(json!({ "properties": { "name": { "pattern": "^a" } } }));
+        let error = JsonSchema::from_file(&path).unwrap_err().to_string();
+        assert!(error.contains("/properties/name/pattern"), "{error}");
+
+        write(json!({ "items": [{ "type": "string" }] }));
+        assert!(JsonSchema::from_file(&path).is_err());
+        write(json!({ "oneOf": [{ "type": "string" }] }));
+        assert!(JsonSchema::from_file(&path).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Stream multiplexer negotiation for the TCP and WebSocket transports.
+//!
+//! Yamux is offered first and mplex second, so current peers keep using yamux
+//! while older js-libp2p clients that only speak `/mplex/6.7.0` can still
+//! connect. Either multiplexer can be switched off in the configuration.
+
+use std::io;
+
+use futures::{future::BoxFuture, AsyncRead, AsyncWrite, FutureExt, TryFutureExt};
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade, UpgradeInfo};
+use libp2p::yamux;
+use libp2p_mplex::Config as MplexConfig;
+
+/// Connection upgrade negotiating yamux or mplex, in that order of preference.
+#[derive(Debug, Clone)]
+pub struct MuxerUpgrade {
+    yamux: Option<yamux::Config>,
+    mplex: Option<MplexConfig>,
+}
+
+impl MuxerUpgrade {
+    /// Offers each multiplexer that is `Some`; yamux takes precedence.
+    pub fn new(yamux: Option<yamux::Config>, mplex: Option<MplexConfig>) -> Self {
+        Self { yamux, mplex }
+    }
+
+    fn upgrade<C>(
+        self,
+        socket: C,
+        info: &'static str,
+        inbound: bool,
+    ) -> BoxFuture<'static, Result<StreamMuxerBox, io::Error>>
+    where
+        C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        if let Some(yamux) = self.yamux.filter(|c| c.protocol_info().any(|p| p == info)) {
+            return if inbound {
+                yamux
+                    .upgrade_inbound(socket, info)
+                    .map_ok(StreamMuxerBox::new)
+                    .boxed()
+            } else {
+                yamux
+                    .upgrade_outbound(socket, info)
+                    .map_ok(StreamMuxerBox::new)
+                    .boxed()
+            };
+        }
+        if let Some(mplex) = self.mplex.filter(|c| c.protocol_info().any(|p| p == info)) {
+            return if inbound {
+                mplex
+                    .upgrade_inbound(socket, info)
+                    .map_ok(StreamMuxerBox::new)
+                    .boxed()
+            } else {
+                mplex
+                    .upgrade_outbound(socket, info)
+                    .map_ok(StreamMuxerBox::new)
+                    .boxed()
+            };
+        }
+        futures::future::ready(Err(io::Error::other(format!(
+            "negotiated multiplexer {} is not enabled",
+            info
+        ))))
+        .boxed()
+    }
+}
+
+impl UpgradeInfo for MuxerUpgrade {
+    type Info = &'static str;
+    type InfoIter = Vec<&'static str>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        let yamux = self.yamux.iter().flat_map(|c| c.protocol_info());
+        let mplex = self.mplex.iter().flat_map(|c| c.protocol_info());
+        yamux.chain(mplex).collect()
+    }
+}
+
+impl<C> InboundConnectionUpgrade<C> for MuxerUpgrade
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = StreamMuxerBox;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, socket: C, info: Self::Info) -> Self::Future {
+        self.upgrade(socket, info, true)
+    }
+}
+
+impl<C> OutboundConnectionUpgrade<C> for MuxerUpgrade
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = StreamMuxerBox;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, socket: C, info: Self::Info) -> Self::Future {
+        self.upgrade(socket, info, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yamux_offered_before_mplex() {
+        let both = MuxerUpgrade::new(Some(yamux::Config::default()), Some(MplexConfig::new()));
+        assert_eq!(both.protocol_info(), vec!["/yamux/1.0.0", "/mplex/6.7.0"]);
+
+        let mplex_only = MuxerUpgrade::new(None, Some(MplexConfig::new()));
+        assert_eq!(mplex_only.protocol_info(), vec!["/mplex/6.7.0"]);
+    }
+}
@@ -0,0 +1,368 @@
+//! PROXY protocol (v1 and v2) on selected TCP and WebSocket listeners.
+//!
+//! Behind nginx every inbound connection comes from 127.0.0.1, which hides the
+//! client from IP-based limits, AutoNAT's observed addresses and abuse tracking.
+//! With `proxy_protocol` enabled for a listener, connections from a trusted
+//! source must start with a PROXY header; the inbound connection is reported to
+//! the swarm only once the header is read, with the client address it carries
+//! as the remote address of `ConnectedPoint::Listener`.
+//!
+//! Connections from untrusted sources are passed through untouched, so the
+//! port can still be reached directly.
+
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::{BoxFuture, Future};
+use futures::stream::FuturesUnordered;
+use futures::{AsyncRead, AsyncReadExt, FutureExt, StreamExt};
+use ipnet::IpNet;
+use libp2p::core::transport::{DialOpts, ListenerId, Transport, TransportError, TransportEvent};
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use log::debug;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// Longest v1 header allowed by the specification, CRLF included
+const V1_MAX_LENGTH: usize = 107;
+
+/// Which listeners expect a PROXY header and from which sources it is honoured.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyProtocolPolicy {
+    /// `/ip…/tcp/<port>` prefixes of the listen addresses that expect the header
+    pub listen: Vec<Multiaddr>,
+    /// Source networks whose PROXY headers are trusted
+    pub trusted_sources: Vec<IpNet>,
+    /// How long a trusted source has to send the header
+    pub header_timeout: Duration,
+}
+
+impl ProxyProtocolPolicy {
+    fn applies_to(&self, listen_addr: &Multiaddr) -> bool {
+        let listen_addr = tcp_prefix(listen_addr);
+        self.listen
+            .iter()
+            .any(|addr| tcp_prefix(addr) == listen_addr)
+    }
+
+    fn trusts(&self, remote: &Multiaddr) -> bool {
+        ip_of(remote).is_some_and(|ip| self.trusted_sources.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+type Upgrade<T> = BoxFuture<'static, Result<<T as Transport>::Output, <T as Transport>::Error>>;
+type Incoming<T> = TransportEvent<Upgrade<T>, <T as Transport>::Error>;
+
+/// Reads the PROXY header on the listeners selected by a [`ProxyProtocolPolicy`]
+/// before reporting their inbound connections. Dialing is passed through.
+pub struct ProxyProtocolTransport<T: Transport> {
+    inner: T,
+    policy: ProxyProtocolPolicy,
+    listeners: HashSet<ListenerId>,
+    // Inbound connections of trusted sources whose header is still being read
+    pending: FuturesUnordered<BoxFuture<'static, Option<Incoming<T>>>>,
+}
+
+impl<T: Transport> ProxyProtocolTransport<T> {
+    pub fn new(inner: T, policy: ProxyProtocolPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            listeners: HashSet::new(),
+            pending: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl<T> Transport for ProxyProtocolTransport<T>
+where
+    T: Transport + Unpin,
+    T::Output: AsyncRead + Unpin + Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Error: Send + 'static,
+{
+    type Output = T::Output;
+    type Error = T::Error;
+    type ListenerUpgrade = Upgrade<T>;
+    type Dial = T::Dial;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        let applies = self.policy.applies_to(&addr);
+        self.inner.listen_on(id, addr)?;
+        if applies {
+            self.listeners.insert(id);
+        }
+        Ok(())
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.listeners.remove(&id);
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(
+        &mut self,
+        addr: Multiaddr,
+        opts: DialOpts,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.inner.dial(addr, opts)
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let this = self.get_mut();
+        loop {
+            match this.pending.poll_next_unpin(cx) {
+                Poll::Ready(Some(Some(event))) => return Poll::Ready(event),
+                // A connection was dropped, look for the next one
+                Poll::Ready(Some(None)) => continue,
+                Poll::Ready(None) | Poll::Pending => {}
+            }
+            let Poll::Ready(event) = Pin::new(&mut this.inner).poll(cx) else {
+                return Poll::Pending;
+            };
+            match event {
+                TransportEvent::Incoming {
+                    listener_id,
+                    upgrade,
+                    local_addr,
+                    send_back_addr,
+                } if this.listeners.contains(&listener_id)
+                    && this.policy.trusts(&send_back_addr) =>
+                {
+                    let timeout = this.policy.header_timeout;
+                    this.pending.push(
+                        async move {
+                            let (upgrade, send_back_addr) =
+                                accept(upgrade, send_back_addr, timeout).await?;
+                            Some(TransportEvent::Incoming {
+                                listener_id,
+                                upgrade,
+                                local_addr,
+                                send_back_addr,
+                            })
+                        }
+                        .boxed(),
+                    );
+                }
+                TransportEvent::ListenerClosed {
+                    listener_id,
+                    reason,
+                } => {
+                    this.listeners.remove(&listener_id);
+                    return Poll::Ready(TransportEvent::ListenerClosed {
+                        listener_id,
+                        reason,
+                    });
+                }
+                event => return Poll::Ready(event.map_upgrade(|upgrade| upgrade.boxed())),
+            }
+        }
+    }
+}
+
+// Read the header of an inbound connection; `None` drops the connection
+async fn accept<U, O, E>(
+    upgrade: U,
+    send_back_addr: Multiaddr,
+    timeout: Duration,
+) -> Option<(BoxFuture<'static, Result<O, E>>, Multiaddr)>
+where
+    U: Future<Output = Result<O, E>>,
+    O: AsyncRead + Unpin + Send + 'static,
+    E: Send + 'static,
+{
+    let mut stream = match upgrade.await {
+        Ok(stream) => stream,
+        Err(e) => return Some((futures::future::ready(Err(e)).boxed(), send_back_addr)),
+    };
+    let source = match tokio::time::timeout(timeout, read_header(&mut stream)).await {
+        Ok(Ok(source)) => source,
+        Ok(Err(e)) => {
+            debug!(
+                "Dropping connection from {}: invalid PROXY header: {}",
+                send_back_addr, e
+            );
+            return None;
+        }
+        Err(_) => {
+            debug!(
+                "Dropping connection from {}: no PROXY header within {:?}",
+                send_back_addr, timeout
+            );
+            return None;
+        }
+    };
+    let send_back_addr = match source {
+        Some(source) => with_socket_addr(&send_back_addr, source),
+        None => send_back_addr,
+    };
+    Some((futures::future::ready(Ok(stream)).boxed(), send_back_addr))
+}
+
+/// Reads a PROXY v1 or v2 header and returns the client address it carries,
+/// or `None` for `UNKNOWN` / `LOCAL` headers and non-TCP address families.
+///
+/// Reads exactly the header and nothing past it, so the stream can be handed
+/// on unbuffered.
+///
+/// # Errors
+///
+/// Returns [`io::ErrorKind::InvalidData`] if the stream does not start with a
+/// well-formed header.
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    let mut prefix = [0u8; 6];
+    stream.read_exact(&mut prefix).await?;
+    if prefix == V1_PREFIX {
+        let mut line = prefix.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid("v1 header too long"));
+            }
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await?;
+            line.push(byte[0]);
+        }
+        return parse_v1(&line);
+    }
+    if prefix != V2_SIGNATURE[..6] {
+        return Err(invalid("missing PROXY header"));
+    }
+    let mut header = [0u8; 16];
+    header[..6].copy_from_slice(&prefix);
+    stream.read_exact(&mut header[6..]).await?;
+    if header[..12] != *V2_SIGNATURE {
+        return Err(invalid("bad v2 signature"));
+    }
+    let mut payload = vec![0u8; u16::from_be_bytes([header[14], header[15]]) as usize];
+    stream.read_exact(&mut payload).await?;
+    parse_v2(header[12], header[13], &payload)
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("v1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("bad v1 source address"))?;
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| invalid("bad v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed v1 header")),
+    }
+}
+
+fn parse_v2(version_command: u8, family: u8, payload: &[u8]) -> io::Result<Option<SocketAddr>> {
+    match version_command {
+        // LOCAL: health checks from the proxy itself
+        0x20 => return Ok(None),
+        0x21 => {}
+        _ => return Err(invalid("unsupported v2 version or command")),
+    }
+    match family {
+        // TCP over IPv4: source, destination, source port, destination port
+        0x11 if payload.len() >= 12 => {
+            let ip = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // TCP over IPv6
+        0x21 if payload.len() >= 36 => {
+            let octets: [u8; 16] = payload[..16].try_into().expect("slice of 16 bytes");
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        0x11 | 0x21 => Err(invalid("truncated v2 address block")),
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// `/ip…/tcp/<port>/…` → `/ip…/tcp/<port>`
+fn tcp_prefix(addr: &Multiaddr) -> Multiaddr {
+    addr.iter().take(2).collect()
+}
+
+fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    match addr.iter().next()? {
+        Protocol::Ip4(ip) => Some(ip.into()),
+        Protocol::Ip6(ip) => Some(ip.into()),
+        _ => None,
+    }
+}
+
+// Replace the IP and port of `send_back_addr` with the client's
+fn with_socket_addr(send_back_addr: &Multiaddr, source: SocketAddr) -> Multiaddr {
+    let ip = match source.ip() {
+        IpAddr::V4(ip) => Protocol::Ip4(ip),
+        IpAddr::V6(ip) => Protocol::Ip6(ip),
+    };
+    let rest = send_back_addr.iter().skip(2);
+    [ip, Protocol::Tcp(source.port())]
+        .into_iter()
+        .chain(rest)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: &[u8]) -> io::Result<Option<SocketAddr>> {
+        futures::executor::block_on(read_header(&mut &bytes[..]))
+    }
+
+    #[test]
+    fn test_v1_header() {
+        assert_eq!(
+            header(b"PROXY TCP4 203.0.113.9 10.0.0.1 40000 443\r\n").unwrap(),
+            Some("203.0.113.9:40000".parse().unwrap())
+        );
+        assert_eq!(
+            header(b"PROXY TCP6 2001:db8::1 2001:db8::2 40000 443\r\n").unwrap(),
+            Some("[2001:db8::1]:40000".parse().unwrap())
+        );
+        assert_eq!(header(b"PROXY UNKNOWN\r\n").unwrap(), None);
+        assert!(header(b"GET / HTTP/1.1\r\n").is_err());
+        assert!(header(&[b"PROXY TCP4 ".as_slice(), &[b'1'; 120]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_v2_header_stops_at_its_length() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        bytes.extend_from_slice(&[203, 0, 113, 9, 10, 0, 0, 1]);
+        bytes.extend_from_slice(&40000u16.to_be_bytes());
+        bytes.extend_from_slice(&443u16.to_be_bytes());
+        bytes.extend_from_slice(b"/multistream/1.0.0\n");
+
+        let mut stream = &bytes[..];
+        let source = futures::executor::block_on(read_header(&mut stream)).unwrap();
+        assert_eq!(source, Some("203.0.113.9:40000".parse().unwrap()));
+        assert_eq!(stream, b"/multistream/1.0.0\n");
+
+        // LOCAL command, as sent by health checks
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(header(&local).unwrap(), None);
+    }
+}
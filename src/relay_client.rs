@@ -0,0 +1,128 @@
+//! Reservations on upstream relays while this node is behind a NAT.
+//!
+//! With `relay_client.enabled`, the swarm carries a relay client transport and
+//! behaviour. [`AutoRelay`] decides when to use them: once AutoNAT reports
+//! `Private`, the node listens on `<relay>/p2p-circuit` for each configured
+//! relay (the bootstrap list by default), which makes a reservation there; the
+//! resulting `/p2p-circuit` listen addresses are advertised like any other.
+//! When AutoNAT reports `Public` again the reservations are released.
+
+use std::collections::HashMap;
+
+use libp2p::autonat::NatStatus;
+use libp2p::core::transport::ListenerId;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+
+/// Tracks the reservations to hold on upstream relays.
+#[derive(Debug, Clone)]
+pub struct AutoRelay {
+    // `<relay>/p2p-circuit` listen addresses, one per relay
+    circuit_addrs: Vec<Multiaddr>,
+    listeners: HashMap<ListenerId, Multiaddr>,
+    private: bool,
+}
+
+impl AutoRelay {
+    /// Reserves on `relays` when private. Addresses without a `/p2p/<relay>`
+    /// suffix, or pointing at `local_peer_id`, are skipped and returned.
+    pub fn new(local_peer_id: PeerId, relays: &[Multiaddr]) -> (Self, Vec<Multiaddr>) {
+        let (usable, skipped): (Vec<_>, Vec<_>) = relays.iter().cloned().partition(|relay| {
+            matches!(relay.iter().last(), Some(Protocol::P2p(peer_id)) if peer_id != local_peer_id)
+        });
+        let auto_relay = Self {
+            circuit_addrs: usable
+                .into_iter()
+                .map(|relay| relay.with(Protocol::P2pCircuit))
+                .collect(),
+            listeners: HashMap::new(),
+            private: false,
+        };
+        (auto_relay, skipped)
+    }
+
+    /// Records a new AutoNAT status. Returns the listeners to remove because
+    /// the node turned out to be public; `Unknown` changes nothing.
+    pub fn set_nat_status(&mut self, status: &NatStatus) -> Vec<ListenerId> {
+        match status {
+            NatStatus::Private => self.private = true,
+            NatStatus::Public(_) => {
+                self.private = false;
+                return self.listeners.drain().map(|(id, _)| id).collect();
+            }
+            NatStatus::Unknown => {}
+        }
+        Vec::new()
+    }
+
+    /// `/p2p-circuit` addresses to listen on now: every relay without an
+    /// active reservation while the node is private, none otherwise.
+    pub fn missing_reservations(&self) -> Vec<Multiaddr> {
+        if !self.private {
+            return Vec::new();
+        }
+        self.circuit_addrs
+            .iter()
+            .filter(|addr| !self.listeners.values().any(|active| active == *addr))
+            .cloned()
+            .collect()
+    }
+
+    /// Records the listener reserving through `circuit_addr`.
+    pub fn reserving(&mut self, circuit_addr: Multiaddr, listener_id: ListenerId) {
+        self.listeners.insert(listener_id, circuit_addr);
+    }
+
+    /// Forgets a closed listener, so [`Self::missing_reservations`] retries its relay.
+    /// Returns `true` if it was a reservation listener.
+    pub fn listener_closed(&mut self, listener_id: ListenerId) -> bool {
+        self.listeners.remove(&listener_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    fn peer_id() -> PeerId {
+        PeerId::from(Keypair::generate_ed25519().public())
+    }
+
+    #[test]
+    fn test_reserves_only_while_private() {
+        let local = peer_id();
+        let relay: Multiaddr = format!("/dns4/relay.example.com/tcp/443/wss/p2p/{}", peer_id())
+            .parse()
+            .unwrap();
+        let relays = [
+            relay.clone(),
+            "/ip4/192.0.2.1/tcp/4001".parse().unwrap(),
+            format!("/ip4/192.0.2.2/tcp/4001/p2p/{local}")
+                .parse()
+                .unwrap(),
+        ];
+        let (mut auto_relay, skipped) = AutoRelay::new(local, &relays);
+        assert_eq!(skipped.len(), 2);
+
+        assert!(auto_relay.missing_reservations().is_empty());
+        assert!(auto_relay.set_nat_status(&NatStatus::Private).is_empty());
+        let wanted = auto_relay.missing_reservations();
+        assert_eq!(wanted, vec![relay.with(Protocol::P2pCircuit)]);
+
+        let listener = ListenerId::next();
+        auto_relay.reserving(wanted[0].clone(), listener);
+        assert!(auto_relay.missing_reservations().is_empty());
+        assert!(auto_relay.set_nat_status(&NatStatus::Unknown).is_empty());
+
+        // A lost reservation is retried while still private
+        assert!(auto_relay.listener_closed(listener));
+        assert_eq!(auto_relay.missing_reservations(), wanted);
+
+        let listener = ListenerId::next();
+        auto_relay.reserving(wanted[0].clone(), listener);
+        let public = NatStatus::Public("/ip4/198.51.100.1/tcp/4001".parse().unwrap());
+        assert_eq!(auto_relay.set_nat_status(&public), vec![listener]);
+        assert!(auto_relay.missing_reservations().is_empty());
+    }
+}
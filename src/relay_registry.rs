@@ -0,0 +1,343 @@
+//! Live view of the reservations and circuits held by the relay server.
+//!
+//! [`RelayRegistry::on_event`] consumes every `relay::Event`; the main loop
+//! also reports disconnected peers, since the relay drops their reservations
+//! without an event. [`RelayRegistry::snapshot`] is what the `/relay` HTTP
+//! endpoint of the admin server serves.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2p::{relay, PeerId};
+use serde::Serialize;
+
+/// Closed circuits kept for [`RelayRegistry::recently_closed`].
+pub const RECENTLY_CLOSED_CAPACITY: usize = 100;
+
+/// A live reservation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    /// Peer holding the reservation
+    pub peer_id: PeerId,
+    /// When the reservation was first accepted
+    pub accepted_at: SystemTime,
+    /// When the reservation lapses unless renewed
+    pub expires_at: SystemTime,
+    /// Times the reservation was renewed since `accepted_at`
+    pub renewals: u32,
+}
+
+/// A circuit, open or recently closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Circuit {
+    /// Peer that asked for the circuit
+    pub src_peer_id: PeerId,
+    /// Peer the circuit leads to, holding a reservation
+    pub dst_peer_id: PeerId,
+    /// When the circuit was accepted
+    pub started_at: SystemTime,
+    /// When the circuit closed, `None` while it is open
+    pub closed_at: Option<SystemTime>,
+    /// Bytes relayed in both directions, known once the circuit closed normally or hit a limit
+    pub bytes: Option<u64>,
+    /// Why the circuit closed, `None` when it ended without error
+    pub close_reason: Option<String>,
+}
+
+/// Requests refused or failed, by kind, since startup.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Counters {
+    /// Reservation requests refused by the limits or admission policy
+    pub reservations_denied: u64,
+    /// Reservation requests whose answer could not be sent
+    pub reservations_failed: u64,
+    /// Circuit requests refused by the limits or admission policy
+    pub circuits_denied: u64,
+    /// Circuit requests whose answer could not be sent, or whose destination could not be reached
+    pub circuits_failed: u64,
+}
+
+/// Tracks reservations and circuits from the relay events.
+#[derive(Debug, Clone)]
+pub struct RelayRegistry {
+    reservation_duration: Duration,
+    reservations: HashMap<PeerId, Reservation>,
+    circuits: Vec<Circuit>,
+    recently_closed: VecDeque<Circuit>,
+    counters: Counters,
+}
+
+impl RelayRegistry {
+    /// Creates an empty registry for reservations lasting `reservation_duration`.
+    pub fn new(reservation_duration: Duration) -> Self {
+        Self {
+            reservation_duration,
+            reservations: HashMap::new(),
+            circuits: Vec::new(),
+            recently_closed: VecDeque::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Records a relay event that happened at `now`.
+    pub fn on_event(&mut self, event: &relay::Event, now: SystemTime) {
+        #[allow(deprecated)]
+        match event {
+            relay::Event::ReservationReqAccepted { src_peer_id, .. } => {
+                let expires_at = now + self.reservation_duration;
+                self.reservations
+                    .entry(*src_peer_id)
+                    .and_modify(|reservation| {
+                        reservation.expires_at = expires_at;
+                        reservation.renewals += 1;
+                    })
+                    .or_insert(Reservation {
+                        peer_id: *src_peer_id,
+                        accepted_at: now,
+                        expires_at,
+                        renewals: 0,
+                    });
+            }
+            relay::Event::ReservationTimedOut { src_peer_id } => {
+                self.reservations.remove(src_peer_id);
+            }
+            relay::Event::ReservationReqDenied { .. } => self.counters.reservations_denied += 1,
+            relay::Event::ReservationReqAcceptFailed { .. }
+            | relay::Event::ReservationReqDenyFailed { .. } => {
+                self.counters.reservations_failed += 1
+            }
+            relay::Event::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            } => self.circuits.push(Circuit {
+                src_peer_id: *src_peer_id,
+                dst_peer_id: *dst_peer_id,
+                started_at: now,
+                closed_at: None,
+                bytes: None,
+                close_reason: None,
+            }),
+            relay::Event::CircuitReqDenied { .. } => self.counters.circuits_denied += 1,
+            relay::Event::CircuitReqDenyFailed { .. }
+            | relay::Event::CircuitReqOutboundConnectFailed { .. }
+            | relay::Event::CircuitReqAcceptFailed { .. } => self.counters.circuits_failed += 1,
+            relay::Event::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                bytes,
+                error,
+            } => {
+                // Circuits between the same peers are indistinguishable; close the oldest
+                let Some(index) = self.circuits.iter().position(|circuit| {
+                    circuit.src_peer_id == *src_peer_id && circuit.dst_peer_id == *dst_peer_id
+                }) else {
+                    return;
+                };
+                let mut circuit = self.circuits.remove(index);
+                circuit.closed_at = Some(now);
+                circuit.bytes = *bytes;
+                circuit.close_reason = error.as_ref().map(ToString::to_string);
+                if self.recently_closed.len() == RECENTLY_CLOSED_CAPACITY {
+                    self.recently_closed.pop_front();
+                }
+                self.recently_closed.push_back(circuit);
+            }
+        }
+    }
+
+    /// Forgets the reservations of a peer that is no longer connected.
+    pub fn on_peer_disconnected(&mut self, peer_id: &PeerId) {
+        self.reservations.remove(peer_id);
+    }
+
+    /// Live reservations.
+    pub fn reservations(&self) -> impl Iterator<Item = &Reservation> {
+        self.reservations.values()
+    }
+
+    /// Open circuits, oldest first.
+    pub fn circuits(&self) -> &[Circuit] {
+        &self.circuits
+    }
+
+    /// The last [`RECENTLY_CLOSED_CAPACITY`] closed circuits, oldest first.
+    pub fn recently_closed(&self) -> impl Iterator<Item = &Circuit> {
+        self.recently_closed.iter()
+    }
+
+    /// Refused and failed requests since startup.
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Serializable view of the registry, omitting reservations expired by `now`.
+    pub fn snapshot(&self, now: SystemTime) -> Snapshot {
+        let reservations: BTreeMap<_, _> = self
+            .reservations
+            .values()
+            .filter(|reservation| reservation.expires_at > now)
+            .map(|reservation| {
+                (
+                    reservation.peer_id.to_string(),
+                    ReservationSnapshot {
+                        accepted_at: unix_secs(reservation.accepted_at),
+                        expires_at: unix_secs(reservation.expires_at),
+                        renewals: reservation.renewals,
+                    },
+                )
+            })
+            .collect();
+        Snapshot {
+            reservations,
+            circuits: self.circuits.iter().map(CircuitSnapshot::from).collect(),
+            recently_closed: self
+                .recently_closed
+                .iter()
+                .map(CircuitSnapshot::from)
+                .collect(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+/// JSON form of the registry; times are Unix seconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    /// Reservations by PeerId
+    pub reservations: BTreeMap<String, ReservationSnapshot>,
+    /// Open circuits, oldest first
+    pub circuits: Vec<CircuitSnapshot>,
+    /// The last [`RECENTLY_CLOSED_CAPACITY`] closed circuits, oldest first
+    pub recently_closed: Vec<CircuitSnapshot>,
+    /// Refused and failed requests since startup
+    pub counters: Counters,
+}
+
+/// JSON form of a [`Reservation`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ReservationSnapshot {
+    /// When the reservation was first accepted, in Unix seconds
+    pub accepted_at: u64,
+    /// When the reservation lapses unless renewed, in Unix seconds
+    pub expires_at: u64,
+    /// Times the reservation was renewed
+    pub renewals: u32,
+}
+
+/// JSON form of a [`Circuit`].
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitSnapshot {
+    /// PeerId of the peer that asked for the circuit
+    pub src: String,
+    /// PeerId of the peer the circuit leads to
+    pub dst: String,
+    /// When the circuit was accepted, in Unix seconds
+    pub started_at: u64,
+    /// When the circuit closed, in Unix seconds; absent while open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<u64>,
+    /// Bytes relayed in both directions; absent while open or when unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// Why the circuit closed; absent when it ended without error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub close_reason: Option<String>,
+}
+
+impl From<&Circuit> for CircuitSnapshot {
+    fn from(circuit: &Circuit) -> Self {
+        Self {
+            src: circuit.src_peer_id.to_string(),
+            dst: circuit.dst_peer_id.to_string(),
+            started_at: unix_secs(circuit.started_at),
+            closed_at: circuit.closed_at.map(unix_secs),
+            bytes: circuit.bytes,
+            close_reason: circuit.close_reason.clone(),
+        }
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_id() -> PeerId {
+        PeerId::from(libp2p::identity::Keypair::generate_ed25519().public())
+    }
+
+    #[test]
+    fn test_reservations_track_renewals_and_expiry() {
+        let mut registry = RelayRegistry::new(Duration::from_secs(3600));
+        let (client, other) = (peer_id(), peer_id());
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let accepted = |src_peer_id| relay::Event::ReservationReqAccepted {
+            src_peer_id,
+            renewed: false,
+        };
+
+        registry.on_event(&accepted(client), start);
+        registry.on_event(&accepted(other), start);
+        registry.on_event(&accepted(client), start + Duration::from_secs(1800));
+        registry.on_event(
+            &relay::Event::ReservationReqDenied { src_peer_id: other },
+            start,
+        );
+        registry.on_peer_disconnected(&other);
+
+        let snapshot = registry.snapshot(start + Duration::from_secs(3601));
+        let reservation = &snapshot.reservations[&client.to_string()];
+        assert_eq!(snapshot.reservations.len(), 1);
+        assert_eq!(reservation.renewals, 1);
+        assert_eq!(reservation.expires_at, 1_000_000 + 1800 + 3600);
+        assert_eq!(snapshot.counters.reservations_denied, 1);
+
+        // Expired reservations are left out of the snapshot
+        assert!(registry
+            .snapshot(start + Duration::from_secs(5401))
+            .reservations
+            .is_empty());
+    }
+
+    #[test]
+    fn test_closed_circuits_keep_bytes_and_reason() {
+        let mut registry = RelayRegistry::new(Duration::from_secs(3600));
+        let (src, dst) = (peer_id(), peer_id());
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let accepted = relay::Event::CircuitReqAccepted {
+            src_peer_id: src,
+            dst_peer_id: dst,
+        };
+
+        registry.on_event(&accepted, start);
+        registry.on_event(&accepted, start + Duration::from_secs(5));
+        assert_eq!(registry.circuits().len(), 2);
+
+        registry.on_event(
+            &relay::Event::CircuitClosed {
+                src_peer_id: src,
+                dst_peer_id: dst,
+                bytes: Some(4096),
+                error: Some(std::io::ErrorKind::TimedOut.into()),
+            },
+            start + Duration::from_secs(120),
+        );
+
+        assert_eq!(registry.circuits().len(), 1);
+        assert_eq!(
+            registry.circuits()[0].started_at,
+            start + Duration::from_secs(5)
+        );
+        let closed: Vec<_> = registry.recently_closed().collect();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].bytes, Some(4096));
+        assert_eq!(closed[0].close_reason.as_deref(), Some("timed out"));
+        let snapshot = serde_json::to_value(registry.snapshot(start)).unwrap();
+        assert_eq!(snapshot["recently_closed"][0]["bytes"], 4096);
+    }
+}
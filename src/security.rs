@@ -0,0 +1,185 @@
+//! Security protocol negotiation for the TCP and WebSocket transports.
+//!
+//! Offers Noise and libp2p TLS 1.3 (`/tls/1.0.0`) during multistream
+//! negotiation, in the order configured in `transport.security`. Go peers and
+//! some Rust peers prefer TLS, js-libp2p peers generally speak Noise.
+
+use std::io;
+
+use futures::future::{BoxFuture, Either};
+use futures::{AsyncRead, AsyncWrite, FutureExt, TryFutureExt};
+use libp2p::core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade, UpgradeInfo};
+use libp2p::identity::Keypair;
+use libp2p::{noise, tls, PeerId};
+use serde::{Deserialize, Serialize};
+
+/// A security protocol that can be offered on TCP and WebSocket connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityProtocol {
+    /// Noise XX (`/noise`)
+    Noise,
+    /// libp2p TLS 1.3 (`/tls/1.0.0`)
+    Tls,
+}
+
+/// Errors of the security upgrade.
+#[derive(Debug, thiserror::Error)]
+pub enum SecurityError {
+    #[error("failed to set up Noise: {0}")]
+    NoiseConfig(noise::Error),
+    #[error("failed to set up TLS: {0}")]
+    TlsConfig(#[from] tls::certificate::GenError),
+    #[error("Noise handshake failed: {0}")]
+    Noise(#[from] noise::Error),
+    #[error("TLS handshake failed: {0}")]
+    Tls(#[from] tls::UpgradeError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// The encrypted stream produced by either protocol.
+pub type SecureStream<C> = Either<noise::Output<C>, tls::TlsStream<C>>;
+
+/// Connection upgrade negotiating the configured security protocols in order.
+#[derive(Clone)]
+pub struct SecurityUpgrade {
+    order: Vec<SecurityProtocol>,
+    noise: Option<noise::Config>,
+    tls: Option<tls::Config>,
+}
+
+impl SecurityUpgrade {
+    /// Offers `protocols` in the given order; duplicates are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SecurityError`] if a protocol's configuration cannot be
+    /// derived from `keypair`.
+    pub fn new(keypair: &Keypair, protocols: &[SecurityProtocol]) -> Result<Self, SecurityError> {
+        let mut order = Vec::new();
+        for protocol in protocols {
+            if !order.contains(protocol) {
+                order.push(*protocol);
+            }
+        }
+        let noise = if order.contains(&SecurityProtocol::Noise) {
+            Some(noise::Config::new(keypair).map_err(SecurityError::NoiseConfig)?)
+        } else {
+            None
+        };
+        let tls = if order.contains(&SecurityProtocol::Tls) {
+            Some(tls::Config::new(keypair)?)
+        } else {
+            None
+        };
+        Ok(Self { order, noise, tls })
+    }
+
+    fn upgrade<C>(
+        self,
+        socket: C,
+        info: &'static str,
+        inbound: bool,
+    ) -> BoxFuture<'static, Result<(PeerId, SecureStream<C>), SecurityError>>
+    where
+        C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        if let Some(noise) = self.noise.filter(|c| c.protocol_info().any(|p| p == info)) {
+            let upgrade = if inbound {
+                noise.upgrade_inbound(socket, info).boxed()
+            } else {
+                noise.upgrade_outbound(socket, info).boxed()
+            };
+            return upgrade
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Left(stream)))
+                .err_into()
+                .boxed();
+        }
+        if let Some(tls) = self.tls.filter(|c| c.protocol_info().any(|p| p == info)) {
+            let upgrade = if inbound {
+                tls.upgrade_inbound(socket, info)
+            } else {
+                tls.upgrade_outbound(socket, info)
+            };
+            return upgrade
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Right(stream)))
+                .err_into()
+                .boxed();
+        }
+        futures::future::ready(Err(io::Error::other(format!(
+            "negotiated security protocol {} is not enabled",
+            info
+        ))
+        .into()))
+        .boxed()
+    }
+}
+
+impl UpgradeInfo for SecurityUpgrade {
+    type Info = &'static str;
+    type InfoIter = Vec<&'static str>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.order
+            .iter()
+            .flat_map(|protocol| match protocol {
+                SecurityProtocol::Noise => self
+                    .noise
+                    .iter()
+                    .flat_map(|c| c.protocol_info())
+                    .collect::<Vec<_>>(),
+                SecurityProtocol::Tls => self.tls.iter().flat_map(|c| c.protocol_info()).collect(),
+            })
+            .collect()
+    }
+}
+
+impl<C> InboundConnectionUpgrade<C> for SecurityUpgrade
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = (PeerId, SecureStream<C>);
+    type Error = SecurityError;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, socket: C, info: Self::Info) -> Self::Future {
+        self.upgrade(socket, info, true)
+    }
+}
+
+impl<C> OutboundConnectionUpgrade<C> for SecurityUpgrade
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = (PeerId, SecureStream<C>);
+    type Error = SecurityError;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, socket: C, info: Self::Info) -> Self::Future {
+        self.upgrade(socket, info, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocols_offered_in_configured_order() {
+        let keypair = Keypair::generate_ed25519();
+        let upgrade = SecurityUpgrade::new(
+            &keypair,
+            &[
+                SecurityProtocol::Tls,
+                SecurityProtocol::Noise,
+                SecurityProtocol::Tls,
+            ],
+        )
+        .unwrap();
+        assert_eq!(upgrade.protocol_info(), vec!["/tls/1.0.0", "/noise"]);
+
+        let noise_only = SecurityUpgrade::new(&keypair, &[SecurityProtocol::Noise]).unwrap();
+        assert_eq!(noise_only.protocol_info(), vec!["/noise"]);
+    }
+}
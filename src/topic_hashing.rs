@@ -0,0 +1,159 @@
+//! How topic names map to gossipsub topic hashes.
+//!
+//! js-libp2p gossipsub, used by the TS relay and the browser apps, identifies a
+//! topic by its plain name, hence [`TopicHashing::Identity`] by default.
+//! [`TopicHashing::Sha256`] matches rust-libp2p peers using `Sha256Topic`.
+//!
+//! The relay always subscribes through [`subscription`], built from a hash, so
+//! mirrored topics are joined under the exact hash peers used. A
+//! [`TopicBridge`] joins both forms of a topic and republishes each message
+//! received on one form on the other, letting JS and Rust peers meet.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::gossipsub::{IdentTopic, Sha256Topic, TopicHash};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How long a bridged payload is remembered, so two bridging relays do not
+/// bounce it back and forth.
+const BRIDGE_SEEN_TTL: Duration = Duration::from_secs(120);
+
+/// How topic names are hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopicHashing {
+    /// The hash is the name itself, like js-libp2p
+    #[default]
+    Identity,
+    /// Base64 of the SHA-256 of the topic descriptor, like `Sha256Topic`
+    Sha256,
+}
+
+impl TopicHashing {
+    /// Hash of the topic named `name`.
+    pub fn hash(self, name: &str) -> TopicHash {
+        match self {
+            TopicHashing::Identity => IdentTopic::new(name).hash(),
+            TopicHashing::Sha256 => Sha256Topic::new(name).hash(),
+        }
+    }
+
+    /// The other hashing.
+    pub fn other(self) -> Self {
+        match self {
+            TopicHashing::Identity => TopicHashing::Sha256,
+            TopicHashing::Sha256 => TopicHashing::Identity,
+        }
+    }
+}
+
+/// A topic to subscribe to or unsubscribe from whose hash is exactly `hash`.
+pub fn subscription(hash: &TopicHash) -> IdentTopic {
+    IdentTopic::new(hash.as_str())
+}
+
+/// Topics bridged between their identity and SHA-256 forms.
+#[derive(Debug, Clone, Default)]
+pub struct TopicBridge {
+    // Each form of a bridged topic, with the other form
+    counterparts: HashMap<TopicHash, TopicHash>,
+    // SHA-256 digests of recently bridged payloads, which peers cannot make collide
+    seen: HashMap<[u8; 32], Instant>,
+}
+
+impl TopicBridge {
+    /// Bridges the topics named `names`.
+    pub fn new<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut counterparts = HashMap::new();
+        for name in names {
+            let identity = TopicHashing::Identity.hash(name);
+            let sha256 = TopicHashing::Sha256.hash(name);
+            counterparts.insert(identity.clone(), sha256.clone());
+            counterparts.insert(sha256, identity);
+        }
+        Self {
+            counterparts,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Both forms of every bridged topic.
+    pub fn topics(&self) -> impl Iterator<Item = &TopicHash> {
+        self.counterparts.keys()
+    }
+
+    /// The other form of `topic`, if bridged.
+    pub fn counterpart(&self, topic: &TopicHash) -> Option<&TopicHash> {
+        self.counterparts.get(topic)
+    }
+
+    /// Where to republish `data` received on `topic` at `now`: the other form,
+    /// unless the topic is not bridged or the payload was bridged recently.
+    pub fn forward(&mut self, topic: &TopicHash, data: &[u8], now: Instant) -> Option<TopicHash> {
+        let counterpart = self.counterparts.get(topic)?.clone();
+        self.seen
+            .retain(|_, bridged_at| now.duration_since(*bridged_at) < BRIDGE_SEEN_TTL);
+
+        // The same digest for both forms, so a payload coming back is recognised
+        let form = std::cmp::min(topic, &counterpart).as_str();
+        let digest: [u8; 32] = Sha256::new()
+            .chain_update((form.len() as u64).to_be_bytes())
+            .chain_update(form)
+            .chain_update(data)
+            .finalize()
+            .into();
+        if self.seen.contains_key(&digest) {
+            return None;
+        }
+        self.seen.insert(digest, now);
+        Some(counterpart)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashing_and_exact_subscription() {
+        let name = "réseau-constellation";
+        assert_eq!(TopicHashing::Identity.hash(name).as_str(), name);
+        assert_eq!(
+            TopicHashing::Sha256.hash(name),
+            Sha256Topic::new(name).hash()
+        );
+        assert_eq!(TopicHashing::Identity.other(), TopicHashing::Sha256);
+
+        // Subscribing from a peer's hash never hashes it again
+        let sha256 = TopicHashing::Sha256.hash(name);
+        assert_eq!(subscription(&sha256).hash(), sha256);
+    }
+
+    #[test]
+    fn test_bridge_forwards_once_between_forms() {
+        let name = "orbiter._peer-discovery._p2p._pubsub";
+        let identity = TopicHashing::Identity.hash(name);
+        let sha256 = TopicHashing::Sha256.hash(name);
+        let mut bridge = TopicBridge::new([name]);
+        let now = Instant::now();
+
+        assert_eq!(bridge.topics().count(), 2);
+        assert_eq!(bridge.counterpart(&sha256), Some(&identity));
+        assert_eq!(
+            bridge.forward(&identity, b"hello", now),
+            Some(sha256.clone())
+        );
+        // Coming back on the other form, e.g. through another bridging relay
+        assert_eq!(bridge.forward(&sha256, b"hello", now), None);
+        assert_eq!(
+            bridge.forward(&sha256, b"hello", now + BRIDGE_SEEN_TTL),
+            Some(identity.clone())
+        );
+        assert_eq!(
+            bridge.forward(&TopicHashing::Identity.hash("other"), b"hello", now),
+            None
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Which connected peers are interested in each topic the relay mirrors.
+//!
+//! Like `src/relai.ts`, the relay subscribes to every topic a peer subscribes
+//! to, so it can forward messages between peers that are not connected to each
+//! other. [`TopicInterest`] records those subscriptions per peer and reports a
+//! topic once nobody connected has been interested in it for the grace period,
+//! so the relay can leave it. The grace period keeps the mesh while a browser
+//! reloads. Exempt topics, such as the always-relay list, are never reported.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use libp2p::gossipsub::TopicHash;
+use libp2p::PeerId;
+
+#[derive(Debug, Clone, Default)]
+struct Interest {
+    peers: HashSet<PeerId>,
+    // When the last interested peer left
+    idle_since: Option<Instant>,
+}
+
+/// Tracks topic → interested peers for the mirrored topics.
+#[derive(Debug, Clone)]
+pub struct TopicInterest {
+    grace_period: Duration,
+    exempt: HashSet<TopicHash>,
+    topics: HashMap<TopicHash, Interest>,
+}
+
+impl TopicInterest {
+    /// Creates a tracker releasing topics idle for `grace_period`, except `exempt` ones.
+    pub fn new(grace_period: Duration, exempt: impl IntoIterator<Item = TopicHash>) -> Self {
+        Self {
+            grace_period,
+            exempt: exempt.into_iter().collect(),
+            topics: HashMap::new(),
+        }
+    }
+
+    /// Records that `peer` subscribed to `topic`.
+    pub fn subscribed(&mut self, peer: PeerId, topic: TopicHash) {
+        let interest = self.topics.entry(topic).or_default();
+        interest.peers.insert(peer);
+        interest.idle_since = None;
+    }
+
+    /// Records that `peer` unsubscribed from `topic` at `now`.
+    pub fn unsubscribed(&mut self, peer: &PeerId, topic: &TopicHash, now: Instant) {
+        if let Some(interest) = self.topics.get_mut(topic) {
+            if interest.peers.remove(peer) && interest.peers.is_empty() {
+                interest.idle_since = Some(now);
+            }
+        }
+    }
+
+    /// Records that `peer` closed its last connection at `now`.
+    pub fn peer_disconnected(&mut self, peer: &PeerId, now: Instant) {
+        for interest in self.topics.values_mut() {
+            if interest.peers.remove(peer) && interest.peers.is_empty() {
+                interest.idle_since = Some(now);
+            }
+        }
+    }
+
+    /// Stops tracking a topic the relay left for another reason, e.g. an eviction.
+    pub fn forget(&mut self, topic: &TopicHash) {
+        self.topics.remove(topic);
+    }
+
+    /// Connected peers subscribed to `topic`.
+    pub fn interested_peers(&self, topic: &TopicHash) -> usize {
+        self.topics
+            .get(topic)
+            .map_or(0, |interest| interest.peers.len())
+    }
+
+    /// Number of topics tracked, idle ones included.
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    /// Whether no topic is tracked.
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    /// Forgets and returns the topics idle for at least the grace period at
+    /// `now`; the relay should unsubscribe from them. Exempt topics are kept.
+    pub fn take_abandoned(&mut self, now: Instant) -> Vec<TopicHash> {
+        let abandoned: Vec<TopicHash> = self
+            .topics
+            .iter()
+            .filter(|(topic, interest)| {
+                !self.exempt.contains(topic)
+                    && interest
+                        .idle_since
+                        .is_some_and(|since| now.duration_since(since) >= self.grace_period)
+            })
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        for topic in &abandoned {
+            self.topics.remove(topic);
+        }
+        abandoned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_id() -> PeerId {
+        PeerId::from(libp2p::identity::Keypair::generate_ed25519().public())
+    }
+
+    #[test]
+    fn test_topic_abandoned_after_grace_period() {
+        let grace = Duration::from_secs(300);
+        let mut interest = TopicInterest::new(grace, []);
+        let (alice, bob) = (peer_id(), peer_id());
+        let topic = TopicHash::from_raw("orbit-db/app");
+        let start = Instant::now();
+
+        interest.subscribed(alice, topic.clone());
+        interest.subscribed(bob, topic.clone());
+        interest.unsubscribed(&alice, &topic, start);
+        assert_eq!(interest.interested_peers(&topic), 1);
+        assert!(interest.take_abandoned(start + grace).is_empty());
+
+        interest.peer_disconnected(&bob, start);
+        assert!(interest
+            .take_abandoned(start + Duration::from_secs(299))
+            .is_empty());
+
+        // Coming back within the grace period keeps the topic
+        interest.subscribed(alice, topic.clone());
+        interest.peer_disconnected(&alice, start + Duration::from_secs(10));
+        assert!(interest.take_abandoned(start + grace).is_empty());
+
+        assert_eq!(
+            interest.take_abandoned(start + Duration::from_secs(310)),
+            vec![topic]
+        );
+        assert!(interest.is_empty());
+    }
+
+    #[test]
+    fn test_exempt_topics_are_never_abandoned() {
+        let exempt = TopicHash::from_raw("réseau-constellation");
+        let mut interest = TopicInterest::new(Duration::ZERO, [exempt.clone()]);
+        let peer = peer_id();
+        let now = Instant::now();
+
+        interest.subscribed(peer, exempt.clone());
+        interest.peer_disconnected(&peer, now);
+        assert!(interest.take_abandoned(now).is_empty());
+        assert_eq!(interest.len(), 1);
+    }
+}
@@ -0,0 +1,282 @@
+//! Which topics the relay mirrors for its peers, and how many.
+//!
+//! A topic named by a peer's subscription is mirrored unless it matches a
+//! `deny` pattern; an `allow` match overrides `deny`. In
+//! [`MirrorMode::Allowlist`] only topics matching `allow` are mirrored.
+//! Patterns match the topic as the peer names it: an exact name, a prefix or a
+//! regex.
+//!
+//! At most `max_topics` topics are mirrored at once. Mirroring one more evicts
+//! the least recently used one, use being a peer subscription or a message.
+//! Exempt topics, the relay's own subscriptions, are always allowed and do not
+//! count towards the cap.
+
+use std::collections::{HashMap, HashSet};
+
+use libp2p::gossipsub::TopicHash;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Which topics are mirrored when no pattern matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorMode {
+    /// Every topic not denied
+    #[default]
+    All,
+    /// Only topics matching an `allow` pattern
+    Allowlist,
+}
+
+/// A topic pattern as configured, e.g. `{ prefix = "orbit-db/" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopicPattern {
+    Exact(String),
+    Prefix(String),
+    Regex(String),
+}
+
+/// Errors in the topic policy configuration.
+#[derive(Debug, thiserror::Error)]
+pub enum TopicPolicyError {
+    #[error("invalid topic regex '{pattern}': {source}")]
+    InvalidRegex {
+        pattern: String,
+        source: Box<regex::Error>,
+    },
+}
+
+/// A compiled [`TopicPattern`].
+#[derive(Debug, Clone)]
+pub enum TopicMatcher {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl TopicMatcher {
+    /// Compiles `pattern`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TopicPolicyError::InvalidRegex`] if a regex does not compile.
+    pub fn new(pattern: &TopicPattern) -> Result<Self, TopicPolicyError> {
+        Ok(match pattern {
+            TopicPattern::Exact(name) => TopicMatcher::Exact(name.clone()),
+            TopicPattern::Prefix(prefix) => TopicMatcher::Prefix(prefix.clone()),
+            TopicPattern::Regex(pattern) => {
+                TopicMatcher::Regex(Regex::new(pattern).map_err(|source| {
+                    TopicPolicyError::InvalidRegex {
+                        pattern: pattern.clone(),
+                        source: Box::new(source),
+                    }
+                })?)
+            }
+        })
+    }
+
+    /// Whether the topic, as named by peers, matches.
+    pub fn matches(&self, topic: &str) -> bool {
+        match self {
+            TopicMatcher::Exact(name) => topic == name,
+            TopicMatcher::Prefix(prefix) => topic.starts_with(prefix.as_str()),
+            TopicMatcher::Regex(regex) => regex.is_match(topic),
+        }
+    }
+}
+
+/// Outcome of [`TopicPolicy::admit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// The topic must not be mirrored
+    Denied,
+    /// The topic is mirrored; `evicted` must be unsubscribed to stay within the cap
+    Mirror { evicted: Option<TopicHash> },
+}
+
+/// Decides which topics are mirrored and keeps them within the cap.
+#[derive(Debug, Clone)]
+pub struct TopicPolicy {
+    mode: MirrorMode,
+    allow: Vec<TopicMatcher>,
+    deny: Vec<TopicMatcher>,
+    max_topics: usize,
+    exempt: HashSet<TopicHash>,
+    // Mirrored topics with the tick of their last use
+    mirrored: HashMap<TopicHash, u64>,
+    tick: u64,
+}
+
+impl TopicPolicy {
+    /// Creates a policy mirroring at most `max_topics` topics, 0 for unlimited.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TopicPolicyError::InvalidRegex`] if a regex does not compile.
+    pub fn new(
+        mode: MirrorMode,
+        allow: &[TopicPattern],
+        deny: &[TopicPattern],
+        max_topics: usize,
+    ) -> Result<Self, TopicPolicyError> {
+        Ok(Self {
+            mode,
+            allow: allow
+                .iter()
+                .map(TopicMatcher::new)
+                .collect::<Result<_, _>>()?,
+            deny: deny
+                .iter()
+                .map(TopicMatcher::new)
+                .collect::<Result<_, _>>()?,
+            max_topics,
+            exempt: HashSet::new(),
+            mirrored: HashMap::new(),
+            tick: 0,
+        })
+    }
+
+    /// Always allows `exempt` topics, outside the cap.
+    pub fn with_exempt(mut self, exempt: impl IntoIterator<Item = TopicHash>) -> Self {
+        self.exempt = exempt.into_iter().collect();
+        self
+    }
+
+    /// Whether the patterns and mode allow mirroring `topic`.
+    pub fn allows(&self, topic: &TopicHash) -> bool {
+        if self.exempt.contains(topic) {
+            return true;
+        }
+        let name = topic.as_str();
+        if self.allow.iter().any(|matcher| matcher.matches(name)) {
+            return true;
+        }
+        if self.deny.iter().any(|matcher| matcher.matches(name)) {
+            return false;
+        }
+        self.mode == MirrorMode::All
+    }
+
+    /// Decides whether to mirror `topic` after a peer subscribed to it,
+    /// marking it as just used.
+    pub fn admit(&mut self, topic: TopicHash) -> Decision {
+        if !self.allows(&topic) {
+            return Decision::Denied;
+        }
+        if self.exempt.contains(&topic) {
+            return Decision::Mirror { evicted: None };
+        }
+        let evicted = if !self.mirrored.contains_key(&topic)
+            && self.max_topics > 0
+            && self.mirrored.len() >= self.max_topics
+        {
+            self.evict_least_recently_used()
+        } else {
+            None
+        };
+        self.touch(&topic);
+        self.mirrored.insert(topic, self.tick);
+        Decision::Mirror { evicted }
+    }
+
+    /// Marks a mirrored topic as just used, e.g. when a message arrives on it.
+    pub fn touch(&mut self, topic: &TopicHash) {
+        self.tick += 1;
+        if let Some(last_used) = self.mirrored.get_mut(topic) {
+            *last_used = self.tick;
+        }
+    }
+
+    /// Forgets a topic the relay stopped mirroring.
+    pub fn remove(&mut self, topic: &TopicHash) {
+        self.mirrored.remove(topic);
+    }
+
+    /// Number of mirrored topics counted against the cap.
+    pub fn len(&self) -> usize {
+        self.mirrored.len()
+    }
+
+    /// Whether no topic counts against the cap.
+    pub fn is_empty(&self) -> bool {
+        self.mirrored.is_empty()
+    }
+
+    fn evict_least_recently_used(&mut self) -> Option<TopicHash> {
+        let oldest = self
+            .mirrored
+            .iter()
+            .min_by_key(|(_, last_used)| **last_used)
+            .map(|(topic, _)| topic.clone())?;
+        self.mirrored.remove(&oldest);
+        Some(oldest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic(name: &str) -> TopicHash {
+        TopicHash::from_raw(name)
+    }
+
+    #[test]
+    fn test_allow_overrides_deny_and_allowlist_mode() {
+        let allow = [TopicPattern::Exact("spam/but-ok".to_string())];
+        let deny = [
+            TopicPattern::Prefix("spam/".to_string()),
+            TopicPattern::Regex("^[0-9]+$".to_string()),
+        ];
+        let policy = TopicPolicy::new(MirrorMode::All, &allow, &deny, 0).unwrap();
+        assert!(policy.allows(&topic("orbit-db/app")));
+        assert!(!policy.allows(&topic("spam/flood")));
+        assert!(!policy.allows(&topic("12345")));
+        assert!(policy.allows(&topic("spam/but-ok")));
+
+        let allow = [TopicPattern::Prefix("orbit-db/".to_string())];
+        let policy = TopicPolicy::new(MirrorMode::Allowlist, &allow, &[], 0)
+            .unwrap()
+            .with_exempt([topic("réseau-constellation")]);
+        assert!(policy.allows(&topic("orbit-db/app")));
+        assert!(policy.allows(&topic("réseau-constellation")));
+        assert!(!policy.allows(&topic("other")));
+
+        assert!(matches!(
+            TopicPolicy::new(
+                MirrorMode::All,
+                &[],
+                &[TopicPattern::Regex("(".to_string())],
+                0
+            ),
+            Err(TopicPolicyError::InvalidRegex { .. })
+        ));
+    }
+
+    #[test]
+    fn test_cap_evicts_least_recently_used() {
+        let mut policy = TopicPolicy::new(MirrorMode::All, &[], &[], 2)
+            .unwrap()
+            .with_exempt([topic("own")]);
+        let no_eviction = Decision::Mirror { evicted: None };
+
+        assert_eq!(policy.admit(topic("a")), no_eviction);
+        assert_eq!(policy.admit(topic("b")), no_eviction);
+        assert_eq!(policy.admit(topic("own")), no_eviction);
+        policy.touch(&topic("a"));
+
+        assert_eq!(
+            policy.admit(topic("c")),
+            Decision::Mirror {
+                evicted: Some(topic("b"))
+            }
+        );
+        // Re-admitting a mirrored topic only refreshes it
+        assert_eq!(policy.admit(topic("a")), no_eviction);
+        assert_eq!(policy.len(), 2);
+
+        policy.remove(&topic("c"));
+        assert_eq!(policy.admit(topic("d")), no_eviction);
+    }
+}
@@ -0,0 +1,347 @@
+//! Persistent WebRTC certificate with scheduled rotation.
+//!
+//! The `/certhash/...` component of a webrtc-direct address is the hash of the
+//! DTLS certificate, so a certificate generated on every start invalidates every
+//! bootstrap list handed out to browsers. The certificate is therefore kept in a
+//! small TOML file (or passed in through `RELAY_WEBRTC_CERTIFICATE`) and reused.
+//!
+//! With a rotation interval configured, a successor certificate is generated
+//! `rotation_overlap_secs` before the rotation time. During that overlap both
+//! certhashes are advertised, so lists handed out then keep working after the
+//! switch. The transport's certificate is fixed once the swarm is built, so the
+//! switch itself happens when the relay (re)starts after the rotation time.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2p::multihash::Multihash;
+use libp2p_webrtc::tokio::{certificate, Certificate};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::WebRtcConfig;
+
+/// Errors that can occur while loading or persisting the WebRTC certificate.
+#[derive(Debug, thiserror::Error)]
+pub enum CertificateError {
+    #[error("failed to access certificate file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse certificate file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("failed to serialize certificate file: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("invalid WebRTC certificate: {0}")]
+    Certificate(#[from] certificate::Error),
+}
+
+/// When to rotate and how long both certificates are advertised beforehand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationSchedule {
+    pub interval: Duration,
+    pub overlap: Duration,
+}
+
+// On-disk representation; PEM blocks are kept verbatim
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CertificateFile {
+    /// Unix time (seconds) at which `next` replaces `current`
+    rotate_at: Option<u64>,
+    current: String,
+    next: Option<String>,
+}
+
+/// The relay's WebRTC certificate and, during an overlap window, its successor.
+#[derive(Debug, Clone)]
+pub struct WebRtcCertificates {
+    current: Certificate,
+    next: Option<Certificate>,
+    rotate_at: Option<SystemTime>,
+    schedule: Option<RotationSchedule>,
+    // `None` when the certificate came from the environment and cannot be persisted
+    path: Option<PathBuf>,
+}
+
+impl WebRtcCertificates {
+    /// Loads the certificate from `env_pem` if given, otherwise from the
+    /// configured file, creating the file when it does not exist yet.
+    ///
+    /// A rotation that became due while the relay was down is applied here.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CertificateError`] if the file or PEM cannot be read,
+    /// parsed or written back.
+    pub fn load(
+        config: &WebRtcConfig,
+        env_pem: Option<&str>,
+        now: SystemTime,
+    ) -> Result<Self, CertificateError> {
+        if let Some(pem) = env_pem {
+            if config.rotation_interval_secs.is_some() {
+                warn!("WebRTC certificate comes from the environment; rotation is disabled.");
+            }
+            return Self::from_pem(pem);
+        }
+        Self::open(&config.certificate_file, config.rotation_schedule(), now)
+    }
+
+    /// Uses a single PEM-encoded certificate without persistence or rotation.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CertificateError::Certificate`] if the PEM does not parse.
+    pub fn from_pem(pem: &str) -> Result<Self, CertificateError> {
+        Ok(Self {
+            current: Certificate::from_pem(pem)?,
+            next: None,
+            rotate_at: None,
+            schedule: None,
+            path: None,
+        })
+    }
+
+    /// Opens (or creates) the certificate file at `path` and applies `schedule`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CertificateError`] if the file cannot be read, parsed or written.
+    pub fn open(
+        path: &Path,
+        schedule: Option<RotationSchedule>,
+        now: SystemTime,
+    ) -> Result<Self, CertificateError> {
+        let mut changed = false;
+        let mut certificates = match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                let file: CertificateFile =
+                    toml::from_str(&contents).map_err(|source| CertificateError::Parse {
+                        path: path.to_path_buf(),
+                        source,
+                    })?;
+                Self {
+                    current: Certificate::from_pem(&file.current)?,
+                    next: file
+                        .next
+                        .as_deref()
+                        .map(Certificate::from_pem)
+                        .transpose()?,
+                    rotate_at: file
+                        .rotate_at
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                    schedule,
+                    path: Some(path.to_path_buf()),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("Generating new WebRTC certificate in {}", path.display());
+                changed = true;
+                Self {
+                    current: generate()?,
+                    next: None,
+                    rotate_at: None,
+                    schedule,
+                    path: Some(path.to_path_buf()),
+                }
+            }
+            Err(source) => {
+                return Err(CertificateError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+
+        match schedule {
+            Some(schedule) => {
+                if certificates.rotate_at.is_none() {
+                    certificates.rotate_at = Some(now + schedule.interval);
+                    changed = true;
+                }
+                if certificates.rotation_due(now) {
+                    certificates.rotate(now)?;
+                    changed = true;
+                }
+            }
+            None if certificates.rotate_at.is_some() || certificates.next.is_some() => {
+                // Rotation was switched off: keep serving the current certificate
+                certificates.rotate_at = None;
+                certificates.next = None;
+                changed = true;
+            }
+            None => {}
+        }
+
+        if changed {
+            certificates.save()?;
+        }
+        certificates.prepare_next(now)?;
+        Ok(certificates)
+    }
+
+    /// The certificate the WebRTC transport should serve.
+    pub fn certificate(&self) -> Certificate {
+        self.current.clone()
+    }
+
+    /// Certhashes to advertise: the current one, then the successor during the overlap.
+    pub fn certhashes(&self) -> Vec<Multihash<64>> {
+        std::iter::once(&self.current)
+            .chain(self.next.as_ref())
+            .map(|cert| cert.fingerprint().to_multihash())
+            .collect()
+    }
+
+    /// When the successor takes over, if rotation is enabled.
+    pub fn rotate_at(&self) -> Option<SystemTime> {
+        self.rotate_at
+    }
+
+    /// Whether the rotation time has passed.
+    pub fn rotation_due(&self, now: SystemTime) -> bool {
+        self.rotate_at.is_some_and(|at| now >= at)
+    }
+
+    /// Generates and persists the successor once the overlap window has started.
+    /// Returns `true` if a new successor was created, meaning the advertised
+    /// certhashes changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CertificateError`] if the updated file cannot be written.
+    pub fn prepare_next(&mut self, now: SystemTime) -> Result<bool, CertificateError> {
+        let (Some(schedule), Some(rotate_at)) = (self.schedule, self.rotate_at) else {
+            return Ok(false);
+        };
+        if self.next.is_some() || now + schedule.overlap < rotate_at {
+            return Ok(false);
+        }
+        info!("WebRTC certificate rotation window started, generating successor certificate");
+        self.next = Some(generate()?);
+        self.save()?;
+        Ok(true)
+    }
+
+    // Promote the successor (or a fresh certificate) and schedule the next rotation
+    fn rotate(&mut self, now: SystemTime) -> Result<(), CertificateError> {
+        let Some(schedule) = self.schedule else {
+            return Ok(());
+        };
+        self.current = match self.next.take() {
+            Some(next) => next,
+            None => generate()?,
+        };
+        self.rotate_at = Some(now + schedule.interval);
+        info!("Rotated WebRTC certificate");
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), CertificateError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = CertificateFile {
+            rotate_at: self
+                .rotate_at
+                .map(|at| at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()),
+            current: self.current.serialize_pem(),
+            next: self.next.as_ref().map(Certificate::serialize_pem),
+        };
+        let contents = toml::to_string_pretty(&file)?;
+        let io_error = |source| CertificateError::Io {
+            path: path.clone(),
+            source,
+        };
+
+        // The file holds private keys: write them owner-only to a temporary file,
+        // then rename it over the target so a crash never leaves half a certificate
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let _ = std::fs::remove_file(&temp_path);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut temp = options.open(&temp_path).map_err(io_error)?;
+        temp.write_all(contents.as_bytes())
+            .and_then(|()| temp.sync_all())
+            .map_err(io_error)?;
+        std::fs::rename(&temp_path, path).map_err(io_error)
+    }
+}
+
+fn generate() -> Result<Certificate, CertificateError> {
+    Ok(Certificate::generate(&mut rand::thread_rng())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn schedule() -> Option<RotationSchedule> {
+        Some(RotationSchedule {
+            interval: 30 * DAY,
+            overlap: 7 * DAY,
+        })
+    }
+
+    #[test]
+    fn test_certificate_is_stable_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("webrtc-certificate.toml");
+        let now = SystemTime::now();
+
+        let first = WebRtcCertificates::open(&path, None, now).unwrap();
+        let second = WebRtcCertificates::open(&path, None, now).unwrap();
+        assert_eq!(first.certhashes(), second.certhashes());
+        assert_eq!(first.certhashes().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_saved_file_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("webrtc-certificate.toml");
+        WebRtcCertificates::open(&path, schedule(), SystemTime::now()).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Only the renamed file is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_rotation_overlap_then_switch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("webrtc-certificate.toml");
+        let start = SystemTime::now();
+
+        let mut certificates = WebRtcCertificates::open(&path, schedule(), start).unwrap();
+        let original = certificates.certhashes();
+        assert!(!certificates.prepare_next(start + 10 * DAY).unwrap());
+
+        // Inside the overlap window both certhashes are advertised
+        assert!(certificates.prepare_next(start + 25 * DAY).unwrap());
+        let overlap = certificates.certhashes();
+        assert_eq!(overlap.len(), 2);
+        assert_eq!(overlap[0], original[0]);
+
+        // After the rotation time a restart serves the successor
+        let rotated = WebRtcCertificates::open(&path, schedule(), start + 31 * DAY).unwrap();
+        assert_eq!(rotated.certhashes(), vec![overlap[1]]);
+        assert_eq!(rotated.rotate_at(), Some(start + 61 * DAY));
+    }
+}
@@ -0,0 +1,423 @@
+//! Native WSS termination, so nginx in front of the relay becomes optional.
+//!
+//! `/tls/ws` (and `/wss`) listen addresses are served by a WebSocket transport
+//! running over a rustls acceptor. The certificate chain and private key come
+//! from PEM files that are re-read whenever their modification time changes,
+//! so a certbot renewal is picked up without restarting the relay.
+//!
+//! Dialing `/wss` addresses is left to the regular WebSocket transport.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use futures::future::BoxFuture;
+use futures::{AsyncRead, AsyncWrite, FutureExt};
+use futures_rustls::TlsAcceptor;
+use libp2p::core::transport::{DialOpts, ListenerId, Transport, TransportError, TransportEvent};
+use libp2p::multiaddr::Protocol;
+use libp2p::websocket::WsConfig;
+use libp2p::Multiaddr;
+use log::{info, warn};
+use parking_lot::RwLock;
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+
+/// Errors that can occur while loading the WSS certificate.
+#[derive(Debug, thiserror::Error)]
+pub enum WssError {
+    #[error("failed to read {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to parse PEM file {path}: {source}")]
+    Pem { path: PathBuf, source: pem::Error },
+    #[error("no certificate found in {path}")]
+    NoCertificate { path: PathBuf },
+    #[error("unusable certificate or key: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Errors of [`TlsAcceptTransport`].
+#[derive(Debug, thiserror::Error)]
+pub enum TlsAcceptError<E> {
+    #[error(transparent)]
+    Transport(E),
+    #[error("TLS handshake failed: {0}")]
+    Tls(io::Error),
+}
+
+// Modification times of the certificate and key files when they were last read
+type Modified = (Option<SystemTime>, Option<SystemTime>);
+
+/// Serves the certificate from PEM files and reloads it when the files change.
+pub struct CertificateResolver {
+    certificate_file: PathBuf,
+    private_key_file: PathBuf,
+    current: RwLock<(Arc<CertifiedKey>, Modified)>,
+}
+
+impl fmt::Debug for CertificateResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertificateResolver")
+            .field("certificate_file", &self.certificate_file)
+            .field("private_key_file", &self.private_key_file)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CertificateResolver {
+    /// Reads the certificate chain and private key.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`WssError`] if either file cannot be read or does not hold
+    /// a usable certificate chain or key.
+    pub fn load(certificate_file: &Path, private_key_file: &Path) -> Result<Self, WssError> {
+        let modified = modified(certificate_file, private_key_file);
+        let key = load_certified_key(certificate_file, private_key_file)?;
+        Ok(Self {
+            certificate_file: certificate_file.to_path_buf(),
+            private_key_file: private_key_file.to_path_buf(),
+            current: RwLock::new((key, modified)),
+        })
+    }
+
+    /// Re-reads the files if their modification time changed. Returns `true`
+    /// when a new certificate was loaded; on error the previous one stays in use.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`WssError`] if the changed files cannot be loaded.
+    pub fn reload_if_changed(&self) -> Result<bool, WssError> {
+        let modified = modified(&self.certificate_file, &self.private_key_file);
+        if self.current.read().1 == modified {
+            return Ok(false);
+        }
+        let key = load_certified_key(&self.certificate_file, &self.private_key_file)?;
+        *self.current.write() = (key, modified);
+        Ok(true)
+    }
+
+    /// Builds a rustls server configuration that always serves the latest certificate.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`WssError::Rustls`] if the crypto provider rejects the default protocol versions.
+    pub fn server_config(self: &Arc<Self>) -> Result<rustls::ServerConfig, WssError> {
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(config)
+    }
+
+    /// Checks the files every `interval` on the tokio runtime and reloads them when changed.
+    pub fn spawn_reloader(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match self.reload_if_changed() {
+                    Ok(true) => info!(
+                        "Reloaded WSS certificate from {}",
+                        self.certificate_file.display()
+                    ),
+                    Ok(false) => {}
+                    Err(e) => warn!(
+                        "Failed to reload WSS certificate, keeping the previous one: {}",
+                        e
+                    ),
+                }
+            }
+        });
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().0.clone())
+    }
+}
+
+fn modified(certificate_file: &Path, private_key_file: &Path) -> Modified {
+    let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    (mtime(certificate_file), mtime(private_key_file))
+}
+
+fn load_certified_key(
+    certificate_file: &Path,
+    private_key_file: &Path,
+) -> Result<Arc<CertifiedKey>, WssError> {
+    let pem_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| match source {
+            pem::Error::Io(source) => WssError::Io { path, source },
+            source => WssError::Pem { path, source },
+        }
+    };
+    let chain = CertificateDer::pem_file_iter(certificate_file)
+        .map_err(pem_error(certificate_file))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(pem_error(certificate_file))?;
+    if chain.is_empty() {
+        return Err(WssError::NoCertificate {
+            path: certificate_file.to_path_buf(),
+        });
+    }
+    let key =
+        PrivateKeyDer::from_pem_file(private_key_file).map_err(pem_error(private_key_file))?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)?;
+    Ok(Arc::new(CertifiedKey::new(chain, signing_key)))
+}
+
+/// Performs the TLS server handshake on every inbound connection of `T`.
+/// Listen-only: dialing is not supported.
+pub struct TlsAcceptTransport<T> {
+    inner: T,
+    acceptor: TlsAcceptor,
+}
+
+impl<T> TlsAcceptTransport<T> {
+    pub fn new(inner: T, config: rustls::ServerConfig) -> Self {
+        Self {
+            inner,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        }
+    }
+}
+
+impl<T> Transport for TlsAcceptTransport<T>
+where
+    T: Transport + Unpin,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Error: Send + 'static,
+{
+    type Output = futures_rustls::server::TlsStream<T::Output>;
+    type Error = TlsAcceptError<T::Error>;
+    type ListenerUpgrade = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        self.inner
+            .listen_on(id, addr)
+            .map_err(|e| e.map(TlsAcceptError::Transport))
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(
+        &mut self,
+        addr: Multiaddr,
+        _opts: DialOpts,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let this = self.get_mut();
+        Pin::new(&mut this.inner).poll(cx).map(|event| {
+            event
+                .map_upgrade(|upgrade| {
+                    let acceptor = this.acceptor.clone();
+                    async move {
+                        let stream = upgrade.await.map_err(TlsAcceptError::Transport)?;
+                        acceptor.accept(stream).await.map_err(TlsAcceptError::Tls)
+                    }
+                    .boxed()
+                })
+                .map_err(TlsAcceptError::Transport)
+        })
+    }
+}
+
+/// Accepts `/tls/ws` and `/wss` listen addresses, hands them to the inner
+/// WebSocket transport as plain `/ws`, and reports them back as `/tls/ws`.
+/// Listen-only: dialing is not supported.
+pub struct WssListenTransport<T> {
+    inner: T,
+}
+
+impl<T> WssListenTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+}
+
+impl<T> Transport for WssListenTransport<T>
+where
+    T: Transport + Unpin,
+{
+    type Output = T::Output;
+    type Error = T::Error;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = T::Dial;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        let Some(ws_addr) = to_plain_ws(&addr) else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        self.inner.listen_on(id, ws_addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(
+        &mut self,
+        addr: Multiaddr,
+        _opts: DialOpts,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll(cx)
+            .map(|event| match event {
+                TransportEvent::NewAddress {
+                    listener_id,
+                    listen_addr,
+                } => TransportEvent::NewAddress {
+                    listener_id,
+                    listen_addr: to_tls_ws(listen_addr),
+                },
+                TransportEvent::AddressExpired {
+                    listener_id,
+                    listen_addr,
+                } => TransportEvent::AddressExpired {
+                    listener_id,
+                    listen_addr: to_tls_ws(listen_addr),
+                },
+                TransportEvent::Incoming {
+                    listener_id,
+                    upgrade,
+                    local_addr,
+                    send_back_addr,
+                } => TransportEvent::Incoming {
+                    listener_id,
+                    upgrade,
+                    local_addr: to_tls_ws(local_addr),
+                    send_back_addr,
+                },
+                other => other,
+            })
+    }
+}
+
+/// Builds the TLS-terminating WebSocket listener transport over the TCP transport `tcp`.
+pub fn listen_transport<T>(
+    tcp: T,
+    config: rustls::ServerConfig,
+) -> WssListenTransport<WsConfig<TlsAcceptTransport<T>>>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Error: Send + 'static,
+{
+    WssListenTransport::new(WsConfig::new(TlsAcceptTransport::new(tcp, config)))
+}
+
+/// Whether `addr` is a WebSocket address with TLS (`/tls/ws` or `/wss`).
+pub fn is_wss(addr: &Multiaddr) -> bool {
+    to_plain_ws(addr).is_some()
+}
+
+// `/…/tls/ws` or `/…/wss` → `/…/ws`
+fn to_plain_ws(addr: &Multiaddr) -> Option<Multiaddr> {
+    let mut inner = addr.clone();
+    let path = match inner.pop()? {
+        Protocol::Wss(path) => path,
+        Protocol::Ws(path) if inner.pop()? == Protocol::Tls => path,
+        _ => return None,
+    };
+    Some(inner.with(Protocol::Ws(path)))
+}
+
+// `/…/ws` → `/…/tls/ws`
+fn to_tls_ws(mut addr: Multiaddr) -> Multiaddr {
+    match addr.pop() {
+        Some(Protocol::Ws(path)) => addr.with(Protocol::Tls).with(Protocol::Ws(path)),
+        Some(other) => addr.with(other),
+        None => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_self_signed(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let certificate_file = dir.join("fullchain.pem");
+        let private_key_file = dir.join("privkey.pem");
+        std::fs::write(&certificate_file, generated.cert.pem()).unwrap();
+        std::fs::write(&private_key_file, generated.key_pair.serialize_pem()).unwrap();
+        (certificate_file, private_key_file)
+    }
+
+    #[test]
+    fn test_address_rewriting() {
+        let wss: Multiaddr = "/ip4/0.0.0.0/tcp/443/wss".parse().unwrap();
+        let tls_ws: Multiaddr = "/ip4/0.0.0.0/tcp/443/tls/ws".parse().unwrap();
+        let ws: Multiaddr = "/ip4/0.0.0.0/tcp/443/ws".parse().unwrap();
+
+        assert_eq!(to_plain_ws(&wss), Some(ws.clone()));
+        assert_eq!(to_plain_ws(&tls_ws), Some(ws.clone()));
+        assert_eq!(to_plain_ws(&ws), None);
+        assert_eq!(to_tls_ws(ws), tls_ws);
+    }
+
+    #[test]
+    fn test_certificate_reloaded_when_files_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let (certificate_file, private_key_file) =
+            write_self_signed(dir.path(), "first.example.com");
+        let resolver = CertificateResolver::load(&certificate_file, &private_key_file).unwrap();
+        let first = resolver.current.read().0.cert.clone();
+        assert!(!resolver.reload_if_changed().unwrap());
+
+        // Make sure the new files get a different modification time
+        std::thread::sleep(Duration::from_millis(20));
+        write_self_signed(dir.path(), "second.example.com");
+        let later = SystemTime::now() + Duration::from_secs(1);
+        for path in [&certificate_file, &private_key_file] {
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(later)
+                .unwrap();
+        }
+
+        assert!(resolver.reload_if_changed().unwrap());
+        assert_ne!(resolver.current.read().0.cert, first);
+    }
+}
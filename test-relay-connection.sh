@@ -14,13 +14,12 @@ echo "LIBP2P_WEBSOCKET_PRESERVE_DNS=true" >> .env.test
 # Set env vars for the test
 export RUST_LOG=info
 export DOTENV_FILENAME=.env.test
-export TEST_RELAY=$HOSTNAME
 
 echo "📝 Using domain $HOSTNAME for connection test"
 echo "👉 CRITICAL: Ensuring DNS hostnames are preserved for TLS validation"
 
 # Run the test with RUST_BACKTRACE to see better errors
-RUST_BACKTRACE=1 cargo run
+RUST_BACKTRACE=1 cargo run -- probe "$HOSTNAME"
 
 # Check exit status
 if [ $? -eq 0 ]; then